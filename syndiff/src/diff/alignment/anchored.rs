@@ -0,0 +1,75 @@
+use super::{
+    align_around_matches, compute_children_alignment, heaviest_common_subseq, AlignmentContext,
+    IdenticalNode, NodeAlignment, SeqNodeAlignment, SubtreeAlignment, Weight, WeightedNode,
+};
+use crate::generic_tree::{Subtree, Tree};
+use std::collections::{HashMap, VecDeque};
+
+/// Zip two nodes with the same anchor key, even when replacing them would be cheaper
+fn zip_anchor(
+    del: &WeightedNode,
+    ins: &WeightedNode,
+    context: &AlignmentContext,
+) -> (Weight, NodeAlignment) {
+    if del == ins {
+        return (context.spine_leaf_weight(), NodeAlignment::Copy);
+    }
+    match (&del.node, &ins.node) {
+        (Tree::Node(kind, del_sub), Tree::Node(_, ins_sub)) => {
+            let (cost, sub_align) = compute_children_alignment(*kind, del_sub, ins_sub, context);
+            (cost, NodeAlignment::Zip(sub_align))
+        }
+        // Keys and names are taken from the children of nodes, so anchors are never leaves
+        _ => (del.weight + ins.weight, NodeAlignment::Replace),
+    }
+}
+
+/// Align sequences containing keyed or named nodes: siblings with the same key or name are
+/// paired first and always zipped, then the subsequences between them are aligned by
+/// `algorithm`, that never zips siblings with different keys.
+pub(super) fn compute_anchored_alignment(
+    del_seq: &[Subtree<WeightedNode>],
+    ins_seq: &[Subtree<WeightedNode>],
+    alignment: &mut Vec<SeqNodeAlignment>,
+    context: &AlignmentContext,
+    algorithm: &dyn SubtreeAlignment,
+) -> Weight {
+    let mut del_positions: HashMap<_, VecDeque<usize>> = HashMap::new();
+    for (del_pos, del) in del_seq.iter().enumerate() {
+        if let Some(key) = context.anchor_key(&del.node) {
+            del_positions
+                .entry((del.field, key))
+                .or_default()
+                .push_back(del_pos);
+        }
+    }
+
+    // Siblings with the same key or name are paired in order of appearance, and only the
+    // heaviest pairs increasing in both sequences are anchored, the others are moves
+    let pairs = ins_seq.iter().enumerate().filter_map(|(ins_pos, ins)| {
+        let del_pos = context
+            .anchor_key(&ins.node)
+            .and_then(|key| del_positions.get_mut(&(ins.field, key)))
+            .and_then(VecDeque::pop_front)?;
+        let weight = del_seq[del_pos].node.weight + ins.node.weight;
+        Some((IdenticalNode { del_pos, ins_pos }, weight))
+    });
+    let anchors = heaviest_common_subseq(pairs)
+        .into_iter()
+        .rev()
+        .map(|anchor| (anchor.del_pos, anchor.ins_pos))
+        .collect();
+
+    align_around_matches(
+        del_seq,
+        ins_seq,
+        anchors,
+        alignment,
+        |del, ins, alignment| {
+            let (anchor_cost, anchor_align) = zip_anchor(&del.node, &ins.node, context);
+            alignment.push(SeqNodeAlignment::Zip(anchor_align));
+            anchor_cost
+        },
+        |del_gap, ins_gap, alignment| algorithm.align(del_gap, ins_gap, alignment, context),
+    )
+}
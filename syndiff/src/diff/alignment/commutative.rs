@@ -0,0 +1,114 @@
+use super::{AlignmentContext, NodeAlignment, SeqNodeAlignment, WeightedNode};
+use crate::generic_tree::{Subtree, Tree};
+use recursive::recursive;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// Reorder the children of `ins` that have the same key as a child of `del`, so that they
+/// follow the order of `del`. Children without key or without an original with the same key
+/// stay in place. Returns the reordered children if the order changed.
+fn follow_original_order<'t, K: Hash + Eq>(
+    del_children: &[Subtree<WeightedNode<'t>>],
+    ins_children: &[Subtree<WeightedNode<'t>>],
+    child_key: impl Fn(&Subtree<WeightedNode>) -> Option<K>,
+) -> Option<Vec<Subtree<WeightedNode<'t>>>> {
+    let mut del_positions: HashMap<K, VecDeque<usize>> = HashMap::new();
+    for (del_pos, del) in del_children.iter().enumerate() {
+        if let Some(key) = child_key(del) {
+            del_positions.entry(key).or_default().push_back(del_pos);
+        }
+    }
+
+    let mut matched = Vec::new();
+    for (ins_pos, ins) in ins_children.iter().enumerate() {
+        if let Some(del_pos) = child_key(ins)
+            .and_then(|key| del_positions.get_mut(&key))
+            .and_then(VecDeque::pop_front)
+        {
+            matched.push((ins_pos, del_pos));
+        }
+    }
+    if matched.windows(2).all(|pair| pair[0].1 < pair[1].1) {
+        return None;
+    }
+
+    // Place the matched children in the slots they occupied, in the original order
+    let slots: Vec<_> = matched.iter().map(|&(ins_pos, _)| ins_pos).collect();
+    matched.sort_by_key(|&(_, del_pos)| del_pos);
+    let mut children: Vec<_> = ins_children.iter().cloned().map(Some).collect();
+    let moved: Vec<_> = matched
+        .iter()
+        .map(|&(ins_pos, _)| children[ins_pos].take().unwrap())
+        .collect();
+    for (slot, child) in slots.into_iter().zip(moved) {
+        children[slot] = Some(child);
+    }
+    Some(children.into_iter().map(Option::unwrap).collect())
+}
+
+/// Undo the moves of children inside the zipped nodes of `ins` whose kind is commutative, and
+/// the moves of keyed children, so that they do not appear as changes. Subtrees of `ins` may be
+/// shared, so reordered nodes are rebuilt with updated hashes instead of being modified in
+/// place. Returns the rebuilt `ins` if any children were reordered.
+#[recursive]
+pub(super) fn undo_commutative_moves<'t>(
+    del: &WeightedNode<'t>,
+    ins: &WeightedNode<'t>,
+    alignment: &NodeAlignment,
+    context: &AlignmentContext<'_, 't>,
+) -> Option<WeightedNode<'t>> {
+    let sub_align = match alignment {
+        NodeAlignment::Zip(sub_align) => sub_align,
+        NodeAlignment::Copy | NodeAlignment::Replace => return None,
+    };
+    let (del_children, kind, ins_children) = match (&del.node, &ins.node) {
+        (Tree::Node(_, del_children), Tree::Node(kind, ins_children)) => {
+            (del_children, *kind, ins_children)
+        }
+        _ => return None,
+    };
+
+    let mut reordered_children: Option<Vec<_>> = None;
+    let (mut del_pos, mut ins_pos) = (0, 0);
+    for align in sub_align {
+        match align {
+            SeqNodeAlignment::Zip(node_align) => {
+                // Alignments forged inside a custom algorithm may not fit, they are replaced
+                // when the trees are aligned
+                let (Some(del_child), Some(ins_child)) =
+                    (del_children.get(del_pos), ins_children.get(ins_pos))
+                else {
+                    return None;
+                };
+                if let Some(reordered_child) =
+                    undo_commutative_moves(&del_child.node, &ins_child.node, node_align, context)
+                {
+                    reordered_children.get_or_insert_with(|| ins_children.to_vec())[ins_pos].node =
+                        reordered_child;
+                }
+                del_pos += 1;
+                ins_pos += 1;
+            }
+            SeqNodeAlignment::Delete => del_pos += 1,
+            SeqNodeAlignment::Insert => ins_pos += 1,
+        }
+    }
+    if context.commutative_kinds.contains(&kind) {
+        let children = reordered_children.as_deref().unwrap_or(ins_children);
+        if let Some(reordered) = follow_original_order(del_children, children, |ch| {
+            matches!(ch.node.node, Tree::Node(..)).then(|| (ch.field, ch.node.hash))
+        }) {
+            reordered_children = Some(reordered);
+        }
+    }
+    if context.has_keyed_children(del_children) {
+        let children = reordered_children.as_deref().unwrap_or(ins_children);
+        if let Some(reordered) = follow_original_order(del_children, children, |ch| {
+            context.node_key(&ch.node).map(|key| (ch.field, key))
+        }) {
+            reordered_children = Some(reordered);
+        }
+    }
+
+    reordered_children.map(|children| context.interner.intern(Tree::Node(kind, children.into())))
+}
@@ -0,0 +1,142 @@
+use super::myers::compute_myers_alignment;
+use super::{
+    AlignmentContext, NodeAlignment, SeqNodeAlignment, SubtreeAlignment, Weight, WeightedNode,
+};
+use crate::generic_tree::Subtree;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Subtrees occurring more often than this in the original sequence never start a split run
+const MAX_OCCURRENCES: usize = 64;
+
+/// Run of identical subtrees around which a region is split
+struct SplitRun {
+    del_start: usize,
+    ins_start: usize,
+    len: usize,
+}
+
+/// Find the run of identical subtrees whose rarest subtree has the fewest occurrences in
+/// `del_seq`, preferring the heaviest run in case of tie
+fn find_split_run(
+    del_seq: &[Subtree<WeightedNode>],
+    ins_seq: &[Subtree<WeightedNode>],
+) -> Option<SplitRun> {
+    let mut occurrences: HashMap<&Subtree<WeightedNode>, Vec<usize>> = HashMap::new();
+    for (del_pos, del) in del_seq.iter().enumerate() {
+        occurrences.entry(del).or_default().push(del_pos);
+    }
+    let nb_occurrences = |sub| occurrences.get(sub).map(Vec::len).unwrap_or(0);
+
+    let mut best = None;
+    for (ins_pos, ins) in ins_seq.iter().enumerate() {
+        let del_positions = match occurrences.get(ins) {
+            Some(del_positions) if del_positions.len() <= MAX_OCCURRENCES => del_positions,
+            _ => continue,
+        };
+        for &del_pos in del_positions {
+            // Only consider maximal runs, unless they start with a too frequent subtree
+            if del_pos > 0
+                && ins_pos > 0
+                && del_seq[del_pos - 1] == ins_seq[ins_pos - 1]
+                && nb_occurrences(&del_seq[del_pos - 1]) <= MAX_OCCURRENCES
+            {
+                continue;
+            }
+            let run = del_seq[del_pos..]
+                .iter()
+                .zip(&ins_seq[ins_pos..])
+                .take_while(|(del, ins)| del == ins);
+            let (mut len, mut weight, mut rarest) = (0, 0, usize::MAX);
+            for (del, _) in run {
+                len += 1;
+                weight += del.node.weight;
+                rarest = rarest.min(nb_occurrences(del));
+            }
+            let score = (Reverse(rarest), weight);
+            if best
+                .as_ref()
+                .is_none_or(|(best_score, _)| score > *best_score)
+            {
+                best = Some((
+                    score,
+                    SplitRun {
+                        del_start: del_pos,
+                        ins_start: ins_pos,
+                        len,
+                    },
+                ));
+            }
+        }
+    }
+    best.map(|(_, run)| run)
+}
+
+enum HistogramTask {
+    Align(Range<usize>, Range<usize>),
+    Copy(usize),
+}
+
+/// Recursively split the sequences around the run of identical subtrees containing the fewest
+/// occurrences of its elements, like the histogram diff of git. Regions without such runs are
+/// aligned with `compute_myers_alignment`.
+fn compute_histogram_alignment(
+    del_seq: &[Subtree<WeightedNode>],
+    ins_seq: &[Subtree<WeightedNode>],
+    alignment: &mut Vec<SeqNodeAlignment>,
+    context: &AlignmentContext,
+) -> Weight {
+    // Tasks are kept on an explicit stack, that can grow with the number of splits
+    let mut cost = 0;
+    let mut tasks = vec![HistogramTask::Align(0..del_seq.len(), 0..ins_seq.len())];
+    while let Some(task) = tasks.pop() {
+        match task {
+            HistogramTask::Align(del_range, ins_range) => {
+                let del_region = &del_seq[del_range.clone()];
+                let ins_region = &ins_seq[ins_range.clone()];
+                match find_split_run(del_region, ins_region) {
+                    Some(run) => {
+                        let del_split = del_range.start + run.del_start;
+                        let ins_split = ins_range.start + run.ins_start;
+                        tasks.push(HistogramTask::Align(
+                            del_split + run.len..del_range.end,
+                            ins_split + run.len..ins_range.end,
+                        ));
+                        tasks.push(HistogramTask::Copy(run.len));
+                        tasks.push(HistogramTask::Align(
+                            del_range.start..del_split,
+                            ins_range.start..ins_split,
+                        ));
+                    }
+                    None => {
+                        cost += compute_myers_alignment(del_region, ins_region, alignment, context)
+                    }
+                }
+            }
+            HistogramTask::Copy(len) => {
+                for _ in 0..len {
+                    alignment.push(SeqNodeAlignment::Zip(NodeAlignment::Copy));
+                }
+                cost += len * context.spine_leaf_weight();
+            }
+        }
+    }
+    cost
+}
+
+/// Histogram alignment: sequences are split around their rarest common subtrees, which keeps
+/// unrelated repeated subtrees from being matched together
+pub struct HistogramAlignment;
+
+impl SubtreeAlignment for HistogramAlignment {
+    fn align(
+        &self,
+        del_seq: &[Subtree<WeightedNode>],
+        ins_seq: &[Subtree<WeightedNode>],
+        alignment: &mut Vec<SeqNodeAlignment>,
+        context: &AlignmentContext,
+    ) -> Weight {
+        compute_histogram_alignment(del_seq, ins_seq, alignment, context)
+    }
+}
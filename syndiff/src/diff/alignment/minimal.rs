@@ -1,10 +1,13 @@
+use super::myers::compute_gap_alignment;
+use super::patience::compute_patience_alignment_with_fallback;
 use super::{
-    compute_node_alignment, SeqNodeAlignment, SubtreeAlignmentAlgorithm, Weight, WeightedNode,
-    SPINE_LEAF_WEIGHT,
+    compute_node_alignment, AlignmentBudget, AlignmentContext, AlignmentDiagnostic, AlignmentIssue,
+    NodeAlignment, SeqNodeAlignment, SubtreeAlignment, Weight, WeightedNode,
 };
 use crate::generic_tree::Subtree;
+use crate::parallel::{map_slice, nb_threads};
 use std::cmp::{max, Reverse};
-use std::collections::BinaryHeap;
+use std::collections::{BinaryHeap, HashMap};
 
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 struct AlignmentAStarNode {
@@ -21,9 +24,10 @@ impl AlignmentAStarNode {
         del_pos: usize,
         ins_pos: usize,
         source_edge: Option<SeqNodeAlignment>,
+        spine_leaf_weight: Weight,
     ) -> Self {
         AlignmentAStarNode {
-            estimated_cost: Reverse(cost + SPINE_LEAF_WEIGHT * max(del_pos, ins_pos)),
+            estimated_cost: Reverse(cost + spine_leaf_weight * max(del_pos, ins_pos)),
             source_edge,
             cost,
             del_pos,
@@ -32,29 +36,111 @@ impl AlignmentAStarNode {
     }
 }
 
+/// Minimal alignment within `budget`, approximated beyond it like with `MinimalAlignment`
 pub(super) fn compute_minimal_alignment(
     del_seq: &[Subtree<WeightedNode>],
     ins_seq: &[Subtree<WeightedNode>],
     alignment: &mut Vec<SeqNodeAlignment>,
-    sub_algorithm: SubtreeAlignmentAlgorithm,
+    context: &AlignmentContext,
+    budget: MinimalAlignment,
 ) -> Weight {
+    budget.align(del_seq, ins_seq, alignment, context)
+}
+
+/// Alignments of zipped children, by their positions in both sequences
+type ZipCache = HashMap<(usize, usize), (Weight, NodeAlignment)>;
+
+/// Compute in parallel the alignments of the zipped children that the next positions to visit
+/// need, one per thread. The search then visits them in the same order as without threads, so
+/// the alignment does not depend on their number.
+fn prefetch_zips(
+    del_seq: &[Subtree<WeightedNode>],
+    ins_seq: &[Subtree<WeightedNode>],
+    to_visit_heap: &mut BinaryHeap<AlignmentAStarNode>,
+    visited_nodes: &[Option<Option<SeqNodeAlignment>>],
+    zip_cache: &mut ZipCache,
+    context: &AlignmentContext,
+) {
+    let node_index = |del_pos, ins_pos| del_pos + ins_pos * (del_seq.len() + 1);
+    let needs_zip = |node: &AlignmentAStarNode| {
+        node.del_pos > 0
+            && node.ins_pos > 0
+            && visited_nodes[node_index(node.del_pos, node.ins_pos)].is_none()
+            && !zip_cache.contains_key(&(node.del_pos - 1, node.ins_pos - 1))
+            && context.can_zip(&del_seq[node.del_pos - 1], &ins_seq[node.ins_pos - 1])
+    };
+    match to_visit_heap.peek() {
+        Some(next_node) if needs_zip(next_node) => (),
+        _ => return,
+    }
+
+    let mut next_nodes = Vec::new();
+    let mut zips = Vec::new();
+    while zips.len() < nb_threads() {
+        let node = match to_visit_heap.pop() {
+            Some(node) => node,
+            None => break,
+        };
+        if needs_zip(&node) && !zips.contains(&(node.del_pos - 1, node.ins_pos - 1)) {
+            zips.push((node.del_pos - 1, node.ins_pos - 1));
+        }
+        next_nodes.push(node);
+    }
+    to_visit_heap.extend(next_nodes);
+
+    let zip_alignments = map_slice(&zips, |&(del_pos, ins_pos)| {
+        compute_node_alignment(&del_seq[del_pos].node, &ins_seq[ins_pos].node, context)
+    });
+    zip_cache.extend(zips.into_iter().zip(zip_alignments));
+}
+
+/// Compute the minimal alignment, unless it needs more memory or time than allowed by `budget`
+pub(super) fn try_compute_minimal_alignment(
+    del_seq: &[Subtree<WeightedNode>],
+    ins_seq: &[Subtree<WeightedNode>],
+    alignment: &mut Vec<SeqNodeAlignment>,
+    context: &AlignmentContext,
+    budget: MinimalAlignment,
+) -> Result<Weight, AlignmentBudget> {
     // Using an A* pathfinding approach:
     // Nodes are pair of position in both sequences, edges are edit operations, distance is cost.
     // Goal: arrive at position (0, 0) from (n, m).
+    let nb_cells = (del_seq.len() + 1).saturating_mul(ins_seq.len() + 1);
+    if nb_cells > budget.max_cells {
+        return Err(AlignmentBudget::Memory);
+    }
     let mut visited_nodes = Vec::new();
-    visited_nodes.resize_with((del_seq.len() + 1) * (ins_seq.len() + 1), Default::default);
+    visited_nodes.resize_with(nb_cells, Default::default);
     let node_index = |del_pos, ins_pos| del_pos + ins_pos * (del_seq.len() + 1);
 
+    let spine_leaf_weight = context.spine_leaf_weight();
     let mut to_visit_heap = BinaryHeap::new();
     to_visit_heap.push(AlignmentAStarNode::new(
         0,
         del_seq.len(),
         ins_seq.len(),
         None,
+        spine_leaf_weight,
     ));
 
+    let mut zip_cache = HashMap::new();
     let cost;
+    let mut nb_steps = 0;
     loop {
+        if nb_steps == budget.max_steps {
+            return Err(AlignmentBudget::Time);
+        }
+        nb_steps += 1;
+        if nb_threads() > 1 {
+            prefetch_zips(
+                del_seq,
+                ins_seq,
+                &mut to_visit_heap,
+                &visited_nodes,
+                &mut zip_cache,
+                context,
+            );
+        }
         let node = to_visit_heap.pop().unwrap();
         match &mut visited_nodes[node_index(node.del_pos, node.ins_pos)] {
             Some(_) => continue,
@@ -71,6 +157,7 @@ pub(super) fn compute_minimal_alignment(
                 node.del_pos - 1,
                 node.ins_pos,
                 Some(SeqNodeAlignment::Delete),
+                spine_leaf_weight,
             ));
         }
         if node.ins_pos > 0 {
@@ -79,18 +166,22 @@ pub(super) fn compute_minimal_alignment(
                 node.del_pos,
                 node.ins_pos - 1,
                 Some(SeqNodeAlignment::Insert),
+                spine_leaf_weight,
             ));
         }
         if node.del_pos > 0 && node.ins_pos > 0 {
             let del = &del_seq[node.del_pos - 1];
             let ins = &ins_seq[node.ins_pos - 1];
-            if del.field == ins.field {
-                let (cost, align) = compute_node_alignment(&del.node, &ins.node, sub_algorithm);
+            if context.can_zip(del, ins) {
+                let (cost, align) = zip_cache
+                    .remove(&(node.del_pos - 1, node.ins_pos - 1))
+                    .unwrap_or_else(|| compute_node_alignment(&del.node, &ins.node, context));
                 to_visit_heap.push(AlignmentAStarNode::new(
                     node.cost + cost,
                     node.del_pos - 1,
                     node.ins_pos - 1,
                     Some(SeqNodeAlignment::Zip(align)),
+                    spine_leaf_weight,
                 ));
             }
         }
@@ -109,10 +200,64 @@ pub(super) fn compute_minimal_alignment(
         };
         alignment.push(align_op)
     }
-    cost
+    Ok(cost)
 }
 
-pub const MINIMAL_ALIGNMENT: SubtreeAlignmentAlgorithm =
-    SubtreeAlignmentAlgorithm(|del_seq, ins_seq, alignment| {
-        compute_minimal_alignment(del_seq, ins_seq, alignment, MINIMAL_ALIGNMENT)
-    });
+/// Alignment of minimal cost, found with an A* search over all the edit sequences.
+///
+/// The search for one sequence of children is abandoned if it needs more than `max_cells`
+/// positions in memory or explores more than `max_steps` of them. That sequence alone is then
+/// aligned with the patience algorithm, and the approximation is reported in the diagnostics.
+/// The gaps between the identical subtrees zipped by the patience algorithm get their minimal
+/// alignment within the same budget, or are aligned like with the Myers algorithm beyond it.
+#[derive(Clone, Copy)]
+pub struct MinimalAlignment {
+    pub max_cells: usize,
+    pub max_steps: usize,
+}
+
+impl MinimalAlignment {
+    /// Search without limits, that may exhaust memory on huge sequences
+    pub const UNBOUNDED: MinimalAlignment = MinimalAlignment {
+        max_cells: usize::MAX,
+        max_steps: usize::MAX,
+    };
+}
+
+impl Default for MinimalAlignment {
+    fn default() -> Self {
+        MinimalAlignment {
+            max_cells: 1 << 22,
+            max_steps: 1 << 24,
+        }
+    }
+}
+
+impl SubtreeAlignment for MinimalAlignment {
+    fn align(
+        &self,
+        del_seq: &[Subtree<WeightedNode>],
+        ins_seq: &[Subtree<WeightedNode>],
+        alignment: &mut Vec<SeqNodeAlignment>,
+        context: &AlignmentContext,
+    ) -> Weight {
+        try_compute_minimal_alignment(del_seq, ins_seq, alignment, context, *self).unwrap_or_else(
+            |exceeded| {
+                context.report(AlignmentDiagnostic {
+                    issue: AlignmentIssue::OverBudget(exceeded),
+                    del_len: del_seq.len(),
+                    ins_len: ins_seq.len(),
+                    fallback: "patience",
+                });
+                compute_patience_alignment_with_fallback(
+                    del_seq,
+                    ins_seq,
+                    alignment,
+                    context,
+                    compute_gap_alignment,
+                    *self,
+                )
+            },
+        )
+    }
+}
@@ -1,17 +1,189 @@
-use super::weight::{HashSum, Weight, WeightedNode, SPINE_LEAF_WEIGHT};
-use crate::generic_tree::{Subtree, Tree};
+use super::weight::{CostModel, HashSum, TreeInterner, Weight, WeightedNode};
+use crate::generic_tree::{FieldId, NodeKind, Subtree, Tree};
+use crate::parallel::{map_slice, MaybeSend, MaybeSync};
+use anchored::compute_anchored_alignment;
+use commutative::undo_commutative_moves;
+use recursive::recursive;
 use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::sync::Mutex;
 
+mod anchored;
+mod commutative;
+mod histogram;
 mod minimal;
+mod myers;
 mod patience;
 
-#[derive(Clone, Copy)]
-pub struct SubtreeAlignmentAlgorithm(
-    fn(&[Subtree<WeightedNode>], &[Subtree<WeightedNode>], &mut Vec<SeqNodeAlignment>) -> Weight,
-);
+pub use histogram::HistogramAlignment;
+pub use minimal::MinimalAlignment;
+pub use myers::MyersAlignment;
+pub use patience::PatienceAlignment;
 
-pub use minimal::MINIMAL_ALIGNMENT;
-pub use patience::PATIENCE_ALIGNMENT;
+pub(super) use patience::{heaviest_common_subseq, IdenticalNode};
+
+/// Algorithm aligning the children of two zipped nodes. With the `parallel` feature, it is shared
+/// by the threads aligning independent sequences and must be `Sync`.
+pub trait SubtreeAlignment: MaybeSync {
+    /// Push in `alignment` the operations transforming `del_seq` into `ins_seq`, in order, and
+    /// return their total cost.
+    ///
+    /// Zipping two subtrees costs the weight returned by `context.align_nodes`, deleting or
+    /// inserting one costs its weight. Subtrees may only be zipped if `context.can_zip` allows it,
+    /// with the alignment returned by `context.align_nodes`. Invalid alignments are reported and
+    /// computed again with the minimal alignment.
+    fn align(
+        &self,
+        del_seq: &[Subtree<WeightedNode>],
+        ins_seq: &[Subtree<WeightedNode>],
+        alignment: &mut Vec<SeqNodeAlignment>,
+        context: &AlignmentContext,
+    ) -> Weight;
+}
+
+/// Resource limiting the search of an alignment
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlignmentBudget {
+    Memory,
+    Time,
+}
+
+impl fmt::Display for AlignmentBudget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AlignmentBudget::Memory => write!(f, "memory"),
+            AlignmentBudget::Time => write!(f, "time"),
+        }
+    }
+}
+
+/// Reason why the alignment of a sequence of children was computed by another algorithm
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlignmentIssue {
+    /// The algorithm exceeded its budget
+    OverBudget(AlignmentBudget),
+    /// The algorithm returned operations that do not transform the original sequence into the
+    /// modified one
+    Invalid,
+}
+
+/// Report that the alignment of a sequence of children was approximated or replaced by another
+/// algorithm. Diagnostics are ordered by issue and then by sequence lengths.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AlignmentDiagnostic {
+    pub issue: AlignmentIssue,
+    /// Number of children in the original and modified sequences
+    pub del_len: usize,
+    pub ins_len: usize,
+    /// Name of the algorithm used instead
+    pub fallback: &'static str,
+}
+
+impl fmt::Display for AlignmentDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.issue {
+            AlignmentIssue::OverBudget(budget) => write!(
+                f,
+                "aligning {} with {} children exceeds the {} budget, approximated with {}",
+                self.del_len, self.ins_len, budget, self.fallback
+            ),
+            AlignmentIssue::Invalid => write!(
+                f,
+                "the alignment of {} with {} children is invalid, realigned with {}",
+                self.del_len, self.ins_len, self.fallback
+            ),
+        }
+    }
+}
+
+/// Parameters shared by the alignments of all the children sequences
+pub struct AlignmentContext<'a, 't> {
+    pub(crate) algorithm: &'a dyn SubtreeAlignment,
+    pub(crate) kind_algorithms: &'a HashMap<NodeKind, Box<dyn SubtreeAlignment>>,
+    pub(crate) commutative_kinds: &'a HashSet<NodeKind>,
+    pub(crate) key_fields: &'a HashMap<NodeKind, FieldId>,
+    pub(crate) name_fields: &'a HashMap<NodeKind, FieldId>,
+    pub(crate) cost_model: &'a CostModel,
+    /// Builds the nodes whose children are reordered, like the nodes of the aligned trees
+    pub(crate) interner: &'a TreeInterner<'a, 't>,
+    pub(crate) diagnostics: Mutex<Vec<AlignmentDiagnostic>>,
+}
+
+/// Kind and hash of the child of `node` in the field registered for its kind in `fields`
+fn field_child_key(
+    node: &WeightedNode,
+    fields: &HashMap<NodeKind, FieldId>,
+) -> Option<(NodeKind, HashSum)> {
+    match &node.node {
+        Tree::Node(kind, children) => {
+            let field = fields.get(kind)?;
+            let child = children.iter().find(|ch| ch.field == Some(*field))?;
+            Some((*kind, child.node.hash))
+        }
+        Tree::Leaf(_) => None,
+    }
+}
+
+impl<'a, 't> AlignmentContext<'a, 't> {
+    /// Kind and hash of the key child of a node, if its kind has a key field
+    fn node_key(&self, node: &WeightedNode) -> Option<(NodeKind, HashSum)> {
+        field_child_key(node, self.key_fields)
+    }
+
+    /// Key used to pair siblings before aligning the others: the key of the node if it has one,
+    /// otherwise its name
+    fn anchor_key(&self, node: &WeightedNode) -> Option<(NodeKind, HashSum)> {
+        self.node_key(node)
+            .or_else(|| field_child_key(node, self.name_fields))
+    }
+
+    /// Siblings can be zipped if they have the same field and the same key
+    pub fn can_zip(&self, del: &Subtree<WeightedNode>, ins: &Subtree<WeightedNode>) -> bool {
+        del.field == ins.field
+            && (self.key_fields.is_empty() || self.node_key(&del.node) == self.node_key(&ins.node))
+    }
+
+    /// Cost and alignment of zipping two nodes, that may be replaced if zipping their children
+    /// is more expensive
+    pub fn align_nodes(&self, del: &WeightedNode, ins: &WeightedNode) -> (Weight, NodeAlignment) {
+        compute_node_alignment(del, ins, self)
+    }
+
+    /// Algorithm aligning the children of the nodes of the given kind
+    pub fn algorithm_for(&self, kind: NodeKind) -> &dyn SubtreeAlignment {
+        match self.kind_algorithms.get(&kind) {
+            Some(algorithm) => algorithm.as_ref(),
+            None => self.algorithm,
+        }
+    }
+
+    /// Cost of zipping two identical subtrees
+    pub fn spine_leaf_weight(&self) -> Weight {
+        self.cost_model.spine_leaf_weight
+    }
+
+    /// Record that an alignment was approximated. Candidate alignments of the same sequences
+    /// may be computed several times, so identical diagnostics are only recorded once.
+    pub fn report(&self, diagnostic: AlignmentDiagnostic) {
+        let mut diagnostics = self.diagnostics.lock().unwrap();
+        if !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic)
+        }
+    }
+
+    fn has_keyed_children(&self, children: &[Subtree<WeightedNode>]) -> bool {
+        !self.key_fields.is_empty() && children.iter().any(|ch| self.node_key(&ch.node).is_some())
+    }
+
+    fn has_anchored_children(&self, children: &[Subtree<WeightedNode>]) -> bool {
+        !(self.key_fields.is_empty() && self.name_fields.is_empty())
+            && children
+                .iter()
+                .any(|ch| self.anchor_key(&ch.node).is_some())
+    }
+}
 
 pub enum AlignedNode<'t> {
     Spine(Tree<'t, AlignedSeqNode<'t>>, HashSum, HashSum),
@@ -25,13 +197,15 @@ pub enum AlignedSeqNode<'t> {
     Inserted(Vec<Subtree<WeightedNode<'t>>>),
 }
 
-enum NodeAlignment {
+/// How a pair of zipped nodes is aligned
+pub enum NodeAlignment {
     Zip(Vec<SeqNodeAlignment>),
     Copy,
     Replace,
 }
 
-enum SeqNodeAlignment {
+/// Edit operation on a child in the alignment of two children sequences
+pub enum SeqNodeAlignment {
     Zip(NodeAlignment),
     Delete,
     Insert,
@@ -63,18 +237,83 @@ impl Ord for SeqNodeAlignment {
     }
 }
 
+/// Push the alignment of two sequences where the `matches` pairs are zipped with
+/// `align_match`, and the gaps between them are aligned with `align_gap`. Each gap and its
+/// following match are aligned independently, in parallel with the `parallel` feature.
+pub(super) fn align_around_matches<'a, 't>(
+    del_seq: &'a [Subtree<WeightedNode<'t>>],
+    ins_seq: &'a [Subtree<WeightedNode<'t>>],
+    matches: Vec<(usize, usize)>,
+    alignment: &mut Vec<SeqNodeAlignment>,
+    align_match: impl Fn(
+            &'a Subtree<WeightedNode<'t>>,
+            &'a Subtree<WeightedNode<'t>>,
+            &mut Vec<SeqNodeAlignment>,
+        ) -> Weight
+        + MaybeSync
+        + MaybeSend,
+    align_gap: impl Fn(
+            &'a [Subtree<WeightedNode<'t>>],
+            &'a [Subtree<WeightedNode<'t>>],
+            &mut Vec<SeqNodeAlignment>,
+        ) -> Weight
+        + MaybeSync
+        + MaybeSend,
+) -> Weight {
+    let mut pieces = Vec::with_capacity(matches.len() + 1);
+    let (mut del_pos, mut ins_pos) = (0, 0);
+    for (match_del_pos, match_ins_pos) in matches {
+        pieces.push((
+            del_pos..match_del_pos,
+            ins_pos..match_ins_pos,
+            Some((match_del_pos, match_ins_pos)),
+        ));
+        del_pos = match_del_pos + 1;
+        ins_pos = match_ins_pos + 1;
+    }
+    pieces.push((del_pos..del_seq.len(), ins_pos..ins_seq.len(), None));
+
+    let align_piece = |(del_gap, ins_gap, matched): &(Range<usize>, Range<usize>, _),
+                       alignment: &mut Vec<SeqNodeAlignment>| {
+        let mut cost = align_gap(
+            &del_seq[del_gap.clone()],
+            &ins_seq[ins_gap.clone()],
+            alignment,
+        );
+        if let Some((match_del_pos, match_ins_pos)) = *matched {
+            cost += align_match(&del_seq[match_del_pos], &ins_seq[match_ins_pos], alignment);
+        }
+        cost
+    };
+    if pieces.len() == 1 {
+        return align_piece(&pieces[0], alignment);
+    }
+    map_slice(&pieces, |piece| {
+        let mut piece_alignment = Vec::new();
+        let cost = align_piece(piece, &mut piece_alignment);
+        (cost, piece_alignment)
+    })
+    .into_iter()
+    .map(|(cost, piece_alignment)| {
+        alignment.extend(piece_alignment);
+        cost
+    })
+    .sum()
+}
+
+#[recursive]
 fn compute_node_alignment(
     del: &WeightedNode,
     ins: &WeightedNode,
-    align_subtree_algorithm: SubtreeAlignmentAlgorithm,
+    context: &AlignmentContext,
 ) -> (Weight, NodeAlignment) {
     if del == ins {
-        return (SPINE_LEAF_WEIGHT, NodeAlignment::Copy);
+        return (context.spine_leaf_weight(), NodeAlignment::Copy);
     }
     match (&del.node, &ins.node) {
         (Tree::Node(del_kind, del_sub), Tree::Node(ins_kind, ins_sub)) if del_kind == ins_kind => {
-            let mut sub_align = Vec::new();
-            let cost = align_subtree_algorithm.0(del_sub, ins_sub, &mut sub_align);
+            let (cost, sub_align) =
+                compute_children_alignment(*del_kind, del_sub, ins_sub, context);
             if cost < del.weight + ins.weight {
                 (cost, NodeAlignment::Zip(sub_align))
             } else {
@@ -85,6 +324,78 @@ fn compute_node_alignment(
     }
 }
 
+fn compute_children_alignment(
+    kind: NodeKind,
+    del_sub: &[Subtree<WeightedNode>],
+    ins_sub: &[Subtree<WeightedNode>],
+    context: &AlignmentContext,
+) -> (Weight, Vec<SeqNodeAlignment>) {
+    let align_with = |algorithm: &dyn SubtreeAlignment| {
+        let mut sub_align = Vec::new();
+        let cost =
+            if context.has_anchored_children(del_sub) || context.has_anchored_children(ins_sub) {
+                compute_anchored_alignment(del_sub, ins_sub, &mut sub_align, context, algorithm)
+            } else {
+                algorithm.align(del_sub, ins_sub, &mut sub_align, context)
+            };
+        (cost, sub_align)
+    };
+    let (cost, sub_align) = align_with(context.algorithm_for(kind));
+    if is_valid_alignment(del_sub, ins_sub, &sub_align, context) {
+        return (cost, sub_align);
+    }
+    context.report(AlignmentDiagnostic {
+        issue: AlignmentIssue::Invalid,
+        del_len: del_sub.len(),
+        ins_len: ins_sub.len(),
+        fallback: "minimal",
+    });
+    align_with(&MinimalAlignment::default())
+}
+
+/// Check that `alignment` consumes exactly the children of both sequences, and zips only
+/// siblings that can be zipped with an alignment that fits them. Alignments may be computed by
+/// algorithms outside of this crate, so they are checked before being trusted.
+fn is_valid_alignment(
+    del_seq: &[Subtree<WeightedNode>],
+    ins_seq: &[Subtree<WeightedNode>],
+    alignment: &[SeqNodeAlignment],
+    context: &AlignmentContext,
+) -> bool {
+    let (mut del_iter, mut ins_iter) = (del_seq.iter(), ins_seq.iter());
+    for align in alignment {
+        let valid = match align {
+            SeqNodeAlignment::Zip(node_align) => match (del_iter.next(), ins_iter.next()) {
+                (Some(del), Some(ins)) => {
+                    context.can_zip(del, ins)
+                        && fits_node_alignment(&del.node, &ins.node, node_align)
+                }
+                _ => false,
+            },
+            SeqNodeAlignment::Delete => del_iter.next().is_some(),
+            SeqNodeAlignment::Insert => ins_iter.next().is_some(),
+        };
+        if !valid {
+            return false;
+        }
+    }
+    del_iter.next().is_none() && ins_iter.next().is_none()
+}
+
+/// Check that a node alignment can apply to two nodes. The alignments of their children are
+/// checked when they are computed.
+fn fits_node_alignment(del: &WeightedNode, ins: &WeightedNode, alignment: &NodeAlignment) -> bool {
+    match (alignment, &del.node, &ins.node) {
+        (NodeAlignment::Copy, _, _) => del == ins,
+        (NodeAlignment::Replace, _, _) => true,
+        (NodeAlignment::Zip(_), Tree::Node(del_kind, _), Tree::Node(ins_kind, _)) => {
+            del_kind == ins_kind
+        }
+        (NodeAlignment::Zip(_), _, _) => false,
+    }
+}
+
+#[recursive]
 fn align_nodes<'t>(
     del: WeightedNode<'t>,
     ins: WeightedNode<'t>,
@@ -92,42 +403,50 @@ fn align_nodes<'t>(
 ) -> AlignedNode<'t> {
     match alignment {
         NodeAlignment::Zip(sub_align) => {
-            if let (Tree::Node(_, sub_del), Tree::Node(kind, sub_ins)) = (del.node, ins.node) {
-                AlignedNode::Spine(
-                    Tree::Node(kind, align_subtrees(sub_del, sub_ins, sub_align)),
-                    del.hash,
-                    ins.hash,
-                )
-            } else {
-                panic!("Wrong node alignment in align_nodes")
+            if let (Tree::Node(_, sub_del), Tree::Node(kind, sub_ins)) = (&del.node, &ins.node) {
+                if let Some(aligned_sub) =
+                    align_subtrees(sub_del.to_vec(), sub_ins.to_vec(), sub_align)
+                {
+                    return AlignedNode::Spine(
+                        Tree::Node(*kind, aligned_sub.into()),
+                        del.hash,
+                        ins.hash,
+                    );
+                }
             }
+            // Only alignments forged inside a custom algorithm may not fit, replace the nodes
+            AlignedNode::Changed(del, ins)
         }
         NodeAlignment::Copy => AlignedNode::Unchanged(ins),
         NodeAlignment::Replace => AlignedNode::Changed(del, ins),
     }
 }
 
+/// Apply an alignment to two children sequences, or return None if it does not consume exactly
+/// their children
 fn align_subtrees<'t>(
     del: Vec<Subtree<WeightedNode<'t>>>,
     ins: Vec<Subtree<WeightedNode<'t>>>,
     alignment: Vec<SeqNodeAlignment>,
-) -> Vec<AlignedSeqNode<'t>> {
+) -> Option<Vec<AlignedSeqNode<'t>>> {
     let mut del_iter = del.into_iter();
     let mut ins_iter = ins.into_iter();
     let mut aligned_vec = Vec::new();
     for align in alignment {
         match align {
             SeqNodeAlignment::Zip(sub_align) => {
-                let del = del_iter.next().unwrap();
-                let ins = ins_iter.next().unwrap();
-                debug_assert!(del.field == ins.field);
+                let del = del_iter.next()?;
+                let ins = ins_iter.next()?;
+                if del.field != ins.field {
+                    return None;
+                }
                 aligned_vec.push(AlignedSeqNode::Zipped(Subtree {
                     field: ins.field,
                     node: align_nodes(del.node, ins.node, sub_align),
                 }))
             }
             SeqNodeAlignment::Delete => {
-                let del = del_iter.next().unwrap();
+                let del = del_iter.next()?;
                 if let Some(AlignedSeqNode::Deleted(del_list)) = aligned_vec.last_mut() {
                     del_list.push(del);
                 } else {
@@ -135,7 +454,7 @@ fn align_subtrees<'t>(
                 }
             }
             SeqNodeAlignment::Insert => {
-                let ins = ins_iter.next().unwrap();
+                let ins = ins_iter.next()?;
                 if let Some(AlignedSeqNode::Inserted(ins_list)) = aligned_vec.last_mut() {
                     ins_list.push(ins);
                 } else {
@@ -146,17 +465,22 @@ fn align_subtrees<'t>(
     }
 
     // Checking that the alignment did not forget elements
-    assert!(del_iter.next().is_none());
-    assert!(ins_iter.next().is_none());
-
-    aligned_vec
+    if del_iter.next().is_some() || ins_iter.next().is_some() {
+        return None;
+    }
+    Some(aligned_vec)
 }
 
 pub fn align_trees<'t>(
     del: WeightedNode<'t>,
-    ins: WeightedNode<'t>,
-    subtree_algorithm: SubtreeAlignmentAlgorithm,
+    mut ins: WeightedNode<'t>,
+    context: &AlignmentContext<'_, 't>,
 ) -> AlignedNode<'t> {
-    let (_, align) = compute_node_alignment(&del, &ins, subtree_algorithm);
+    let (_, mut align) = compute_node_alignment(&del, &ins, context);
+    if let Some(reordered_ins) = undo_commutative_moves(&del, &ins, &align, context) {
+        // Moved children are now back at their original place, align them again
+        ins = reordered_ins;
+        align = compute_node_alignment(&del, &ins, context).1;
+    }
     align_nodes(del, ins, align)
 }
@@ -0,0 +1,124 @@
+use super::minimal::try_compute_minimal_alignment;
+use super::{
+    align_around_matches, AlignmentContext, AlignmentDiagnostic, AlignmentIssue, MinimalAlignment,
+    NodeAlignment, SeqNodeAlignment, SubtreeAlignment, Weight, WeightedNode,
+};
+use crate::generic_tree::{Subtree, Tree};
+use crate::lcs::common_subsequence;
+
+/// Gaps between identical subtrees with at most this many pairs of children are aligned with
+/// the minimal alignment, larger ones only zip children of the same kind
+const MAX_MINIMAL_GAP_SIZE: usize = 1 << 16;
+
+fn delete_and_insert(
+    del_seq: &[Subtree<WeightedNode>],
+    ins_seq: &[Subtree<WeightedNode>],
+    alignment: &mut Vec<SeqNodeAlignment>,
+) -> Weight {
+    alignment.extend(del_seq.iter().map(|_| SeqNodeAlignment::Delete));
+    alignment.extend(ins_seq.iter().map(|_| SeqNodeAlignment::Insert));
+    del_seq
+        .iter()
+        .chain(ins_seq)
+        .map(|sub| sub.node.weight)
+        .sum()
+}
+
+/// Align a sequence of children without identical subtrees in common.
+/// Small sequences get their minimal alignment if it fits in `budget`, larger ones zip the
+/// children with the same kind and field found by the Myers algorithm, and delete or insert
+/// the others. Small sequences exceeding the budget are reported in the diagnostics.
+pub(super) fn compute_gap_alignment(
+    del_seq: &[Subtree<WeightedNode>],
+    ins_seq: &[Subtree<WeightedNode>],
+    alignment: &mut Vec<SeqNodeAlignment>,
+    context: &AlignmentContext,
+    budget: MinimalAlignment,
+) -> Weight {
+    if del_seq.is_empty() || ins_seq.is_empty() {
+        return delete_and_insert(del_seq, ins_seq, alignment);
+    }
+    if del_seq.len().saturating_mul(ins_seq.len()) <= MAX_MINIMAL_GAP_SIZE {
+        match try_compute_minimal_alignment(del_seq, ins_seq, alignment, context, budget) {
+            Ok(cost) => return cost,
+            Err(exceeded) => context.report(AlignmentDiagnostic {
+                issue: AlignmentIssue::OverBudget(exceeded),
+                del_len: del_seq.len(),
+                ins_len: ins_seq.len(),
+                fallback: "myers",
+            }),
+        }
+    }
+
+    let kind_of = |sub: &Subtree<WeightedNode>| match &sub.node.node {
+        Tree::Node(kind, _) => (sub.field, Some(*kind)),
+        Tree::Leaf(_) => (sub.field, None),
+    };
+    let del_kinds: Vec<_> = del_seq.iter().map(kind_of).collect();
+    let ins_kinds: Vec<_> = ins_seq.iter().map(kind_of).collect();
+    align_around_matches(
+        del_seq,
+        ins_seq,
+        common_subsequence(&del_kinds, &ins_kinds),
+        alignment,
+        |del, ins, alignment| {
+            if context.can_zip(del, ins) {
+                let (cost, align) = context.align_nodes(&del.node, &ins.node);
+                alignment.push(SeqNodeAlignment::Zip(align));
+                cost
+            } else {
+                delete_and_insert(
+                    std::slice::from_ref(del),
+                    std::slice::from_ref(ins),
+                    alignment,
+                )
+            }
+        },
+        delete_and_insert,
+    )
+}
+
+/// Zip the identical subtrees forming a longest common subsequence found by the Myers
+/// algorithm, and align the gaps between them with `compute_gap_alignment`
+pub(super) fn compute_myers_alignment(
+    del_seq: &[Subtree<WeightedNode>],
+    ins_seq: &[Subtree<WeightedNode>],
+    alignment: &mut Vec<SeqNodeAlignment>,
+    context: &AlignmentContext,
+) -> Weight {
+    align_around_matches(
+        del_seq,
+        ins_seq,
+        common_subsequence(del_seq, ins_seq),
+        alignment,
+        |_, _, alignment| {
+            alignment.push(SeqNodeAlignment::Zip(NodeAlignment::Copy));
+            context.spine_leaf_weight()
+        },
+        |del_gap, ins_gap, alignment| {
+            compute_gap_alignment(
+                del_gap,
+                ins_gap,
+                alignment,
+                context,
+                MinimalAlignment::UNBOUNDED,
+            )
+        },
+    )
+}
+
+/// Myers O(ND) alignment: identical subtrees are matched in linear space, and only the
+/// remaining gaps are aligned by weight. Suited to huge sequences of children.
+pub struct MyersAlignment;
+
+impl SubtreeAlignment for MyersAlignment {
+    fn align(
+        &self,
+        del_seq: &[Subtree<WeightedNode>],
+        ins_seq: &[Subtree<WeightedNode>],
+        alignment: &mut Vec<SeqNodeAlignment>,
+        context: &AlignmentContext,
+    ) -> Weight {
+        compute_myers_alignment(del_seq, ins_seq, alignment, context)
+    }
+}
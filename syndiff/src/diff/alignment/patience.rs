@@ -1,20 +1,23 @@
 use super::minimal::compute_minimal_alignment;
 use super::{
-    NodeAlignment, SeqNodeAlignment, SubtreeAlignmentAlgorithm, Weight, WeightedNode,
-    SPINE_LEAF_WEIGHT,
+    align_around_matches, AlignmentContext, MinimalAlignment, NodeAlignment, SeqNodeAlignment,
+    SubtreeAlignment, Weight, WeightedNode,
 };
 use crate::generic_tree::Subtree;
 use std::cmp::min;
 use std::collections::hash_map::Entry;
 use std::collections::{BTreeMap, HashMap};
 
+/// Positions of identical subtrees in the original and modified sequences
 #[derive(Clone, Copy)]
-struct IdenticalNode {
-    del_pos: usize,
-    ins_pos: usize,
+pub(crate) struct IdenticalNode {
+    pub del_pos: usize,
+    pub ins_pos: usize,
 }
 
-fn heaviest_common_subseq<S>(seq: S) -> Vec<IdenticalNode>
+/// Heaviest subsequence of identical subtrees increasing in both sequences, in reverse order.
+/// The subtrees must be given by increasing `ins_pos`.
+pub(crate) fn heaviest_common_subseq<S>(seq: S) -> Vec<IdenticalNode>
 where
     S: IntoIterator<Item = (IdenticalNode, Weight)>,
 {
@@ -54,10 +57,23 @@ where
     rev_subseq
 }
 
+/// Alignment of the sequences without unique identical subtrees, within the budget of a
+/// minimal alignment
+type FallbackAlignmentFn = fn(
+    &[Subtree<WeightedNode>],
+    &[Subtree<WeightedNode>],
+    &mut Vec<SeqNodeAlignment>,
+    &AlignmentContext,
+    MinimalAlignment,
+) -> Weight;
+
 fn compute_unique_subtrees_alignment(
     del_seq: &[Subtree<WeightedNode>],
     ins_seq: &[Subtree<WeightedNode>],
     alignment: &mut Vec<SeqNodeAlignment>,
+    context: &AlignmentContext,
+    fallback: FallbackAlignmentFn,
+    budget: MinimalAlignment,
 ) -> Weight {
     // Find unique nodes in del and remember their position
     let mut unique_del_pos = HashMap::new();
@@ -97,31 +113,40 @@ fn compute_unique_subtrees_alignment(
     drop(unique_ins);
 
     if reversed_his.is_empty() {
-        compute_minimal_alignment(del_seq, ins_seq, alignment, PATIENCE_ALIGNMENT)
+        fallback(del_seq, ins_seq, alignment, context, budget)
     } else {
-        let mut cost = 0;
-        let mut del_pos = 0;
-        let mut ins_pos = 0;
-        for id_node in reversed_his.into_iter().rev() {
-            cost += compute_patience_alignment(
-                &del_seq[del_pos..id_node.del_pos],
-                &ins_seq[ins_pos..id_node.ins_pos],
-                alignment,
-            );
-            alignment.push(SeqNodeAlignment::Zip(NodeAlignment::Copy));
-            cost += SPINE_LEAF_WEIGHT;
-            del_pos = id_node.del_pos + 1;
-            ins_pos = id_node.ins_pos + 1;
-        }
-        cost += compute_patience_alignment(&del_seq[del_pos..], &ins_seq[ins_pos..], alignment);
-        cost
+        let matches = reversed_his
+            .into_iter()
+            .rev()
+            .map(|id_node| (id_node.del_pos, id_node.ins_pos))
+            .collect();
+        align_around_matches(
+            del_seq,
+            ins_seq,
+            matches,
+            alignment,
+            |_, _, alignment| {
+                alignment.push(SeqNodeAlignment::Zip(NodeAlignment::Copy));
+                context.spine_leaf_weight()
+            },
+            |del_gap, ins_gap, alignment| {
+                compute_patience_alignment_with_fallback(
+                    del_gap, ins_gap, alignment, context, fallback, budget,
+                )
+            },
+        )
     }
 }
 
-fn compute_patience_alignment(
+/// Patience alignment where the subsequences without unique identical subtrees are aligned
+/// by `fallback` within `budget`
+pub(super) fn compute_patience_alignment_with_fallback(
     del_seq: &[Subtree<WeightedNode>],
     ins_seq: &[Subtree<WeightedNode>],
     alignment: &mut Vec<SeqNodeAlignment>,
+    context: &AlignmentContext,
+    fallback: FallbackAlignmentFn,
+    budget: MinimalAlignment,
 ) -> Weight {
     // First strip identical head and tail
     let nb_id_head = del_seq
@@ -144,13 +169,34 @@ fn compute_patience_alignment(
     for _ in 0..nb_id_head {
         alignment.push(SeqNodeAlignment::Zip(NodeAlignment::Copy));
     }
-    let inner_cost = compute_unique_subtrees_alignment(del_seq, ins_seq, alignment);
+    let inner_cost =
+        compute_unique_subtrees_alignment(del_seq, ins_seq, alignment, context, fallback, budget);
     for _ in 0..nb_id_tail {
         alignment.push(SeqNodeAlignment::Zip(NodeAlignment::Copy));
     }
 
-    inner_cost + (nb_id_head + nb_id_tail) * SPINE_LEAF_WEIGHT
+    inner_cost + (nb_id_head + nb_id_tail) * context.spine_leaf_weight()
 }
 
-pub const PATIENCE_ALIGNMENT: SubtreeAlignmentAlgorithm =
-    SubtreeAlignmentAlgorithm(compute_patience_alignment);
+/// Patience alignment: identical subtrees that are unique in both sequences are zipped first,
+/// and the minimal alignment is only computed between them, within its default budget
+pub struct PatienceAlignment;
+
+impl SubtreeAlignment for PatienceAlignment {
+    fn align(
+        &self,
+        del_seq: &[Subtree<WeightedNode>],
+        ins_seq: &[Subtree<WeightedNode>],
+        alignment: &mut Vec<SeqNodeAlignment>,
+        context: &AlignmentContext,
+    ) -> Weight {
+        compute_patience_alignment_with_fallback(
+            del_seq,
+            ins_seq,
+            alignment,
+            context,
+            compute_minimal_alignment,
+            MinimalAlignment::default(),
+        )
+    }
+}
@@ -0,0 +1,326 @@
+use super::elision::unelided_tree;
+use super::weight::TreeInterner;
+use super::{
+    compute_diff, diff_weighted_trees, heaviest_common_subseq, AlignmentDiagnostic, ChangeNode,
+    DiffOptions, DiffSpineNode, DiffSpineSeqNode, IdenticalNode, Metavariable, Weight,
+    WeightedNode,
+};
+use crate::generic_tree::{NodeKind, Subtree, Tree};
+use crate::parallel::map_slice;
+use crate::syn_tree::SynNode;
+use crate::Error;
+use recursive::recursive;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::iter::once;
+use std::ops::Range;
+
+/// Part of the top-level children of the versions of a file
+pub(crate) enum TopLevelChunk {
+    /// Children identical in every version, given by their range in the original version
+    Unchanged(Range<usize>),
+    /// Range of children of each version, the original one first, that differ in some version
+    Changed(Vec<Range<usize>>),
+}
+
+/// Top-level children of several versions of a file, split into chunks by anchors: children
+/// identical in every version and unique in each of them, in the same order in all of them.
+pub(crate) struct TopLevelChunks<'t> {
+    kind: NodeKind,
+    /// Weighted top-level children of each version, the original one first
+    children: Vec<Vec<Subtree<WeightedNode<'t>>>>,
+    pub chunks: Vec<TopLevelChunk>,
+}
+
+/// Position of the children that appear only once in a sequence
+fn unique_positions<'a, 't>(
+    children: &'a [Subtree<WeightedNode<'t>>],
+) -> HashMap<&'a Subtree<WeightedNode<'t>>, usize> {
+    let mut positions = HashMap::new();
+    for (pos, child) in children.iter().enumerate() {
+        match positions.entry(child) {
+            Entry::Occupied(entry) => *entry.into_mut() = usize::MAX,
+            Entry::Vacant(entry) => {
+                entry.insert(pos);
+            }
+        }
+    }
+    positions.retain(|_, pos| *pos != usize::MAX);
+    positions
+}
+
+/// Add unchanged children to the chunks, extending the previous chunk if it is unchanged too
+fn push_unchanged(chunks: &mut Vec<TopLevelChunk>, range: Range<usize>) {
+    if let Some(TopLevelChunk::Unchanged(prev_range)) = chunks.last_mut() {
+        prev_range.end = range.end;
+    } else if !range.is_empty() {
+        chunks.push(TopLevelChunk::Unchanged(range));
+    }
+}
+
+impl<'t> TopLevelChunks<'t> {
+    /// Split the top-level children of `trees`, the original version first. Returns `None` if
+    /// the roots do not all have the same kind.
+    pub(crate) fn split(trees: &[&SynNode<'t>], options: &DiffOptions) -> Option<Self> {
+        let kind = match &trees[0].0 {
+            Tree::Node(kind, _) => *kind,
+            Tree::Leaf(_) => return None,
+        };
+        let mut top_levels = Vec::new();
+        for tree in trees {
+            match &tree.0 {
+                Tree::Node(tree_kind, children) if *tree_kind == kind => top_levels.push(children),
+                _ => return None,
+            }
+        }
+
+        let interner = TreeInterner::new(
+            &options.cost_model,
+            options.verify_hashes,
+            options.share_subtrees,
+        );
+        let children: Vec<Vec<_>> = top_levels
+            .into_iter()
+            .map(|children| {
+                map_slice(children, |child| {
+                    child.as_ref().map(|sub| interner.weight_tree(sub))
+                })
+            })
+            .collect();
+
+        // Position of each anchor candidate in every version, with its weight
+        let unique_children: Vec<_> = children.iter().map(|ch| unique_positions(ch)).collect();
+        let mut anchors: Vec<(Vec<usize>, Weight)> = unique_children[0]
+            .keys()
+            .filter_map(|child| {
+                let positions = unique_children
+                    .iter()
+                    .map(|unique| unique.get(child).copied())
+                    .collect::<Option<_>>()?;
+                Some((positions, child.node.weight))
+            })
+            .collect();
+        drop(unique_children);
+
+        // Keep the heaviest anchors in the same order in the original and each version in turn.
+        // The anchors kept for previous versions are already in order, so any subset of them
+        // remains in order.
+        for version in 1..children.len() {
+            anchors.sort_unstable_by_key(|(positions, _)| positions[version]);
+            let kept: HashSet<usize> =
+                heaviest_common_subseq(anchors.iter().map(|(positions, weight)| {
+                    (
+                        IdenticalNode {
+                            del_pos: positions[0],
+                            ins_pos: positions[version],
+                        },
+                        *weight,
+                    )
+                }))
+                .into_iter()
+                .map(|anchor| anchor.del_pos)
+                .collect();
+            anchors.retain(|(positions, _)| kept.contains(&positions[0]));
+        }
+        anchors.sort_unstable_by_key(|(positions, _)| positions[0]);
+
+        let mut chunks = Vec::new();
+        let ends: Vec<usize> = children.iter().map(|ch| ch.len()).collect();
+        let mut starts = vec![0; children.len()];
+        for anchor in anchors
+            .iter()
+            .map(|(positions, _)| positions)
+            .chain(once(&ends))
+        {
+            let ranges: Vec<Range<usize>> = starts
+                .iter()
+                .zip(anchor)
+                .map(|(&start, &end)| start..end)
+                .collect();
+            let origin_chunk = &children[0][ranges[0].clone()];
+            if children
+                .iter()
+                .zip(&ranges)
+                .all(|(version, range)| &version[range.clone()] == origin_chunk)
+            {
+                push_unchanged(&mut chunks, ranges[0].clone());
+            } else {
+                chunks.push(TopLevelChunk::Changed(ranges));
+            }
+            if anchor != &ends {
+                push_unchanged(&mut chunks, anchor[0]..anchor[0] + 1);
+                starts = anchor.iter().map(|pos| pos + 1).collect();
+            }
+        }
+
+        Some(TopLevelChunks {
+            kind,
+            children,
+            chunks,
+        })
+    }
+
+    /// Root of the given children of a version
+    fn chunk_root(
+        &self,
+        interner: &TreeInterner<'_, 't>,
+        version: usize,
+        range: &Range<usize>,
+    ) -> WeightedNode<'t> {
+        interner.intern(Tree::Node(
+            self.kind,
+            self.children[version][range.clone()].to_vec().into(),
+        ))
+    }
+
+    /// Compute the difference between the original children of a chunk and the children of one
+    /// of its versions, as the children of a zipped root. Its metavariables are numbered from 0.
+    pub(crate) fn diff_chunk(
+        &self,
+        ranges: &[Range<usize>],
+        version: usize,
+        options: &DiffOptions,
+    ) -> (Vec<DiffSpineSeqNode<'t>>, Vec<AlignmentDiagnostic>) {
+        let interner = TreeInterner::new(&options.cost_model, options.verify_hashes, false);
+        let origin_root = self.chunk_root(&interner, 0, &ranges[0]);
+        let modified_root = self.chunk_root(&interner, version, &ranges[version]);
+        let (diff, diagnostics) =
+            diff_weighted_trees(origin_root, modified_root, &interner, options);
+        let seq = match diff {
+            DiffSpineNode::Spine(Tree::Node(_, seq)) => seq.into_vec(),
+            DiffSpineNode::Changed(
+                ChangeNode::InPlace(Tree::Node(_, del)),
+                ChangeNode::InPlace(Tree::Node(_, ins)),
+            ) => replacement_seq(del.into_vec(), ins.into_vec()),
+            DiffSpineNode::Changed(_, _) => {
+                // The root of a chunk was elided, replace the chunk without elisions instead
+                let unelided = |version: usize| {
+                    self.children[version][ranges[version].clone()]
+                        .iter()
+                        .map(|child| child.as_ref().map(unelided_tree))
+                        .collect()
+                };
+                replacement_seq(unelided(0), unelided(version))
+            }
+            DiffSpineNode::Spine(Tree::Leaf(_)) | DiffSpineNode::Unchanged => {
+                self.unchanged_seq(ranges[0].clone())
+            }
+        };
+        (seq, diagnostics)
+    }
+
+    /// Zipped unchanged nodes for the given original children, leaves are kept like in diffs
+    pub(crate) fn unchanged_seq(&self, range: Range<usize>) -> Vec<DiffSpineSeqNode<'t>> {
+        self.children[0][range]
+            .iter()
+            .map(|child| {
+                DiffSpineSeqNode::Zipped(Subtree {
+                    field: child.field,
+                    node: match child.node.node {
+                        Tree::Leaf(tok) => DiffSpineNode::Spine(Tree::Leaf(tok)),
+                        _ => DiffSpineNode::Unchanged,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    pub(crate) fn kind(&self) -> NodeKind {
+        self.kind
+    }
+}
+
+/// Children sequence replacing all the `del` children by the `ins` children
+fn replacement_seq<'t>(
+    del: Vec<Subtree<ChangeNode<'t>>>,
+    ins: Vec<Subtree<ChangeNode<'t>>>,
+) -> Vec<DiffSpineSeqNode<'t>> {
+    let mut seq = Vec::new();
+    if !del.is_empty() {
+        seq.push(DiffSpineSeqNode::Deleted(del));
+    }
+    if !ins.is_empty() {
+        seq.push(DiffSpineSeqNode::Inserted(ins));
+    }
+    seq
+}
+
+#[recursive]
+fn shift_metavars_in_change(change: &mut ChangeNode, first_metavar: usize, end: &mut usize) {
+    match change {
+        ChangeNode::InPlace(node) => {
+            node.visit_mut(|sub| shift_metavars_in_change(&mut sub.node, first_metavar, end))
+        }
+        ChangeNode::Elided(mv) => {
+            *mv = Metavariable(mv.0 + first_metavar);
+            *end = (*end).max(mv.0 + 1);
+        }
+    }
+}
+
+#[recursive]
+fn shift_metavars_in_spine(spine: &mut DiffSpineNode, first_metavar: usize, end: &mut usize) {
+    match spine {
+        DiffSpineNode::Spine(spine) => {
+            spine.visit_mut(|seq_node| shift_metavars_in_seq(seq_node, first_metavar, end))
+        }
+        DiffSpineNode::Unchanged => (),
+        DiffSpineNode::Changed(del, ins) => {
+            shift_metavars_in_change(del, first_metavar, end);
+            shift_metavars_in_change(ins, first_metavar, end);
+        }
+    }
+}
+
+fn shift_metavars_in_seq(seq_node: &mut DiffSpineSeqNode, first_metavar: usize, end: &mut usize) {
+    match seq_node {
+        DiffSpineSeqNode::Zipped(sub) => shift_metavars_in_spine(&mut sub.node, first_metavar, end),
+        DiffSpineSeqNode::Deleted(change_list) | DiffSpineSeqNode::Inserted(change_list) => {
+            for change in change_list {
+                shift_metavars_in_change(&mut change.node, first_metavar, end)
+            }
+        }
+    }
+}
+
+/// Large-file mode of `compute_diff`: the top-level children identical in both trees and unique
+/// in each of them are kept, and only the chunks of children between them are diffed, in
+/// parallel with the `parallel` feature. Moves from one chunk to another are not detected.
+/// Fails if the cost model of `options` is inadmissible.
+pub fn compute_chunked_diff<'t>(
+    origin_tree: &SynNode<'t>,
+    modified_tree: &SynNode<'t>,
+    options: &DiffOptions,
+) -> Result<(DiffSpineNode<'t>, Vec<AlignmentDiagnostic>), Error> {
+    options.cost_model.validate()?;
+    let chunks = match TopLevelChunks::split(&[origin_tree, modified_tree], options) {
+        Some(chunks) => chunks,
+        None => return compute_diff(origin_tree, modified_tree, options),
+    };
+    let chunk_diffs = map_slice(&chunks.chunks, |chunk| match chunk {
+        TopLevelChunk::Unchanged(range) => (chunks.unchanged_seq(range.clone()), Vec::new()),
+        TopLevelChunk::Changed(ranges) => chunks.diff_chunk(ranges, 1, options),
+    });
+
+    // Number the metavariables of each chunk after those of the previous chunks
+    let mut seq = Vec::new();
+    let mut all_diagnostics = Vec::new();
+    let mut next_metavar = 0;
+    for (mut chunk_seq, diagnostics) in chunk_diffs {
+        let mut end = next_metavar;
+        for seq_node in &mut chunk_seq {
+            shift_metavars_in_seq(seq_node, next_metavar, &mut end);
+        }
+        next_metavar = end;
+        seq.extend(chunk_seq);
+        for diagnostic in diagnostics {
+            if !all_diagnostics.contains(&diagnostic) {
+                all_diagnostics.push(diagnostic)
+            }
+        }
+    }
+    Ok((
+        DiffSpineNode::Spine(Tree::Node(chunks.kind, seq.into())),
+        all_diagnostics,
+    ))
+}
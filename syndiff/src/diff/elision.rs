@@ -2,8 +2,10 @@ use super::alignment::{AlignedNode, AlignedSeqNode};
 use super::weight::{HashSum, WeightedNode};
 use super::{ChangeNode, DiffSpineNode, DiffSpineSeqNode, Metavariable};
 use crate::generic_tree::{NodeKind, Tree};
+use recursive::recursive;
 use std::collections::{HashMap, HashSet};
 
+#[recursive]
 fn collect_node_hashes(
     tree: &WeightedNode,
     hash_set: &mut HashSet<HashSum>,
@@ -20,6 +22,7 @@ fn collect_node_hashes(
         .visit(|sub| collect_node_hashes(&sub.node, hash_set, kind_whitelist));
 }
 
+#[recursive]
 fn collect_change_node_hashes(
     tree: &AlignedNode,
     del_hash_set: &mut HashSet<HashSum>,
@@ -65,6 +68,7 @@ fn collect_changed_subtree_hashes(
     }
 }
 
+#[recursive]
 fn collect_wanted_elisions(
     tree: &WeightedNode,
     possible_elisions: &HashSet<HashSum>,
@@ -79,6 +83,7 @@ fn collect_wanted_elisions(
     }
 }
 
+#[recursive]
 fn collect_change_node_elisions(
     tree: &AlignedNode,
     possible_elisions: &HashSet<HashSum>,
@@ -201,6 +206,7 @@ impl MetavarNameGenerator {
     }
 }
 
+#[recursive]
 fn elide_tree<'t>(
     tree: &WeightedNode<'t>,
     elisions: &HashSet<HashSum>,
@@ -216,6 +222,12 @@ fn elide_tree<'t>(
     }
 }
 
+/// Convert a weighted tree into a change tree without any elision
+pub(super) fn unelided_tree<'t>(tree: &WeightedNode<'t>) -> ChangeNode<'t> {
+    elide_tree(tree, &HashSet::new(), &mut MetavarNameGenerator::default())
+}
+
+#[recursive]
 fn elide_and_keep_del<'t>(
     tree: &AlignedNode<'t>,
     elisions: &HashSet<HashSum>,
@@ -251,6 +263,7 @@ fn elide_and_keep_del<'t>(
     }
 }
 
+#[recursive]
 fn elide_and_keep_ins<'t>(
     tree: &AlignedNode<'t>,
     elisions: &HashSet<HashSum>,
@@ -286,6 +299,7 @@ fn elide_and_keep_ins<'t>(
     }
 }
 
+#[recursive]
 fn elide_change_nodes<'t>(
     tree: &AlignedNode<'t>,
     elisions: &HashSet<HashSum>,
@@ -1,39 +1,153 @@
 mod alignment;
+mod chunked;
 mod elision;
 mod tree;
 mod weight;
 
-pub use alignment::{MINIMAL_ALIGNMENT, PATIENCE_ALIGNMENT};
+pub use alignment::{
+    AlignmentBudget, AlignmentContext, AlignmentDiagnostic, AlignmentIssue, HistogramAlignment,
+    MinimalAlignment, MyersAlignment, NodeAlignment, PatienceAlignment, SeqNodeAlignment,
+    SubtreeAlignment,
+};
+pub use chunked::compute_chunked_diff;
+pub(crate) use chunked::{TopLevelChunk, TopLevelChunks};
 pub use tree::Metavariable;
 pub use tree::{ChangeNode, DiffSpineNode, DiffSpineSeqNode};
+pub use weight::{CostModel, HashSum, Weight, WeightedNode, WeightedNodeData};
 
-use crate::generic_tree::NodeKind;
+use crate::generic_tree::{FieldId, NodeKind};
+use crate::parallel::join;
 use crate::syn_tree::SynNode;
-use alignment::{align_trees, SubtreeAlignmentAlgorithm};
+use crate::Error;
+use alignment::{align_trees, heaviest_common_subseq, IdenticalNode};
 use elision::find_metavariable_elisions;
-use std::collections::HashSet;
-use weight::weight_tree;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use weight::{TreeInterner, MAX_WEIGHT};
 
+pub struct DiffOptions {
+    /// Only try to elide moved subtrees with these kinds, or any kind if `None`
+    pub elision_whitelist: Option<HashSet<NodeKind>>,
+    /// Algorithm aligning the children of zipped nodes
+    pub alignment: Box<dyn SubtreeAlignment>,
+    /// Algorithm aligning the children of the nodes of each kind, instead of `alignment`
+    pub kind_alignments: HashMap<NodeKind, Box<dyn SubtreeAlignment>>,
+    /// Kinds of the nodes whose children are unordered. Moving their children is not a change.
+    pub commutative_kinds: HashSet<NodeKind>,
+    /// Field holding the key of the nodes of each kind, like the `key` of a JSON `pair`.
+    /// Siblings with equal keys are always zipped together, siblings with different keys never
+    /// are, and they are unordered like the children of commutative nodes: reordering them is
+    /// not a change, so a merge keeps them in their original order.
+    pub key_fields: HashMap<NodeKind, FieldId>,
+    /// Field holding the name of the nodes of each kind, like the `name` of a `function_item`.
+    /// Siblings with the same name are paired and zipped before aligning the other siblings.
+    pub name_fields: HashMap<NodeKind, FieldId>,
+    /// Weights deciding when nodes are zipped or replaced
+    pub cost_model: CostModel,
+    /// Confirm that subtrees with equal hashes have equal bytes, instead of trusting the
+    /// 128-bit hashes alone
+    pub verify_hashes: bool,
+    /// Store identical subtrees once and share them between all their occurrences, in both
+    /// trees. The tokens of a shared subtree are then slices of its first occurrence, conflict
+    /// reports still locate them in each file since they search for their text.
+    pub share_subtrees: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            elision_whitelist: None,
+            alignment: Box::new(MinimalAlignment::default()),
+            kind_alignments: HashMap::new(),
+            commutative_kinds: HashSet::new(),
+            key_fields: HashMap::new(),
+            name_fields: HashMap::new(),
+            cost_model: CostModel::default(),
+            verify_hashes: false,
+            share_subtrees: true,
+        }
+    }
+}
+
+/// Compute the difference from `origin_tree` to `modified_tree`, and the diagnostics of the
+/// approximated alignments. Fails if the cost model of `options` is inadmissible.
 pub fn compute_diff<'t>(
     origin_tree: &SynNode<'t>,
     modified_tree: &SynNode<'t>,
-    kind_whitelist: &Option<HashSet<NodeKind>>,
-    align_subtree_algorithm: SubtreeAlignmentAlgorithm,
-) -> DiffSpineNode<'t> {
-    // Hash the syntax trees and compute their weights
-    let mut origin_weighted_tree = weight_tree(origin_tree);
-    origin_weighted_tree.weight += 1; // Small incentive to keep the root node
-    let modified_weighted_tree = weight_tree(modified_tree);
+    options: &DiffOptions,
+) -> Result<(DiffSpineNode<'t>, Vec<AlignmentDiagnostic>), Error> {
+    options.cost_model.validate()?;
 
-    // Merge the common parts from both trees to create a spine of unchanged
-    // structure.
-    let aligned_tree = align_trees(
+    // Hash the syntax trees and compute their weights. Both trees share the same interner, so
+    // that identical subtrees can be stored once and hashes identify the same subtrees across
+    // them.
+    let interner = TreeInterner::new(
+        &options.cost_model,
+        options.verify_hashes,
+        options.share_subtrees,
+    );
+    let (origin_weighted_tree, modified_weighted_tree) = join(
+        || interner.weight_tree(origin_tree),
+        || interner.weight_tree(modified_tree),
+    );
+    Ok(diff_weighted_trees(
         origin_weighted_tree,
         modified_weighted_tree,
-        align_subtree_algorithm,
+        &interner,
+        options,
+    ))
+}
+
+/// Compute the difference between two weighted trees built by `interner`, which also builds
+/// the nodes rebuilt during the alignment
+fn diff_weighted_trees<'t>(
+    origin_weighted_tree: WeightedNode<'t>,
+    modified_weighted_tree: WeightedNode<'t>,
+    interner: &TreeInterner<'_, 't>,
+    options: &DiffOptions,
+) -> (DiffSpineNode<'t>, Vec<AlignmentDiagnostic>) {
+    let origin_weighted_tree = origin_weighted_tree.with_weight(
+        origin_weighted_tree
+            .weight
+            .saturating_add(options.cost_model.root_incentive)
+            .min(MAX_WEIGHT),
     );
 
+    // Merge the common parts from both trees to create a spine of unchanged
+    // structure. Children moved inside commutative nodes are kept in their original order.
+    // Approximated alignments are reported in the diagnostics, sorted since they are reported
+    // in the order in which threads finish their alignments.
+    let context = AlignmentContext {
+        algorithm: options.alignment.as_ref(),
+        kind_algorithms: &options.kind_alignments,
+        commutative_kinds: &options.commutative_kinds,
+        key_fields: &options.key_fields,
+        name_fields: &options.name_fields,
+        cost_model: &options.cost_model,
+        interner,
+        diagnostics: Mutex::new(Vec::new()),
+    };
+    let aligned_tree = align_trees(origin_weighted_tree, modified_weighted_tree, &context);
+
     // Compute the difference as a deletion and an insertion tree by eliding
     // parts reused from original to modified
-    find_metavariable_elisions(&aligned_tree, kind_whitelist)
+    let diff = find_metavariable_elisions(&aligned_tree, &options.elision_whitelist);
+    let mut diagnostics = context.diagnostics.into_inner().unwrap();
+    diagnostics.sort();
+    (diff, diagnostics)
+}
+
+/// Compute the differences from the same original tree to two modified trees, like the two
+/// sides of a three-way merge. Both diffs are computed in parallel with the `parallel` feature.
+pub fn compute_diff_pair<'t>(
+    origin_tree: &SynNode<'t>,
+    first_modified_tree: &SynNode<'t>,
+    second_modified_tree: &SynNode<'t>,
+    options: &DiffOptions,
+) -> Result<[(DiffSpineNode<'t>, Vec<AlignmentDiagnostic>); 2], Error> {
+    let (first_diff, second_diff) = join(
+        || compute_diff(origin_tree, first_modified_tree, options),
+        || compute_diff(origin_tree, second_modified_tree, options),
+    );
+    Ok([first_diff?, second_diff?])
 }
@@ -1,25 +1,119 @@
-use crate::generic_tree::{Subtree, Tree};
+use crate::generic_tree::{FieldId, NodeKind, Subtree, Token, Tree};
+use crate::parallel::map_slice;
 use crate::syn_tree::SynNode;
-use std::collections::hash_map::DefaultHasher;
+use crate::Error;
+use recursive::recursive;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, MutexGuard};
+use xxhash_rust::xxh3::{xxh3_128, Xxh3};
 
 pub type Weight = usize;
-pub const NODE_WEIGHT: Weight = 0;
-pub const LEAF_WEIGHT: Weight = 2;
-pub const SPINE_LEAF_WEIGHT: Weight = 1;
 
+/// Weights of subtrees are capped, so that the costs summing the weights of many subtrees cannot
+/// overflow, even with huge kind multipliers
+pub(super) const MAX_WEIGHT: Weight = Weight::MAX >> (Weight::BITS / 2);
+
+/// Weights deciding whether two nodes are zipped together or replaced.
+/// Zipping two nodes costs the weight of their changed subtrees, replacing them costs their
+/// whole weight.
+#[derive(Clone)]
+pub struct CostModel {
+    /// Weight of an internal node, in addition to the weight of its children
+    pub node_weight: Weight,
+    /// Weight of a leaf token
+    pub leaf_weight: Weight,
+    /// Cost of keeping an unchanged subtree. It should not exceed the weight of any leaf,
+    /// otherwise alignments are not guaranteed to be minimal.
+    pub spine_leaf_weight: Weight,
+    /// Extra weight of the original root, as a small incentive to keep it
+    pub root_incentive: Weight,
+    /// Multiplier of the weight of the nodes of each kind, e.g. to make identifiers more
+    /// expensive to change than comments. Multipliers must be positive, otherwise alignments
+    /// are not guaranteed to be minimal.
+    pub kind_multipliers: HashMap<NodeKind, Weight>,
+}
+
+impl CostModel {
+    /// Check that the weights guarantee minimal alignments: multipliers are positive and
+    /// keeping a subtree never costs more than any leaf
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.spine_leaf_weight > self.leaf_weight {
+            return Err(Error::InadmissibleCostModel(String::from(
+                "the spine weight must not exceed the leaf weight",
+            )));
+        }
+        let zero_kind = self
+            .kind_multipliers
+            .iter()
+            .filter(|(_, multiplier)| **multiplier == 0)
+            .map(|(kind, _)| kind)
+            .min();
+        match zero_kind {
+            Some(kind) => Err(Error::ZeroKindMultiplier(*kind)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel {
+            node_weight: 0,
+            leaf_weight: 2,
+            spine_leaf_weight: 1,
+            root_incentive: 1,
+            kind_multipliers: HashMap::new(),
+        }
+    }
+}
+
+/// Hash identifying a weighted subtree, see `hash_tree` for its layout
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
-pub struct HashSum(u64);
+pub struct HashSum(u128);
 
-pub struct WeightedNode<'t> {
+impl From<HashSum> for u128 {
+    fn from(hash: HashSum) -> u128 {
+        hash.0
+    }
+}
+
+pub struct WeightedNodeData<'t> {
     pub node: Tree<'t, Subtree<WeightedNode<'t>>>,
     pub hash: HashSum,
     pub weight: Weight,
 }
 
+/// Shared handle on a weighted subtree. Identical subtrees built by the same `TreeInterner`
+/// point to the same data, so cloning a handle never copies the subtree.
+#[derive(Clone)]
+pub struct WeightedNode<'t>(Arc<WeightedNodeData<'t>>);
+
+impl<'t> WeightedNode<'t> {
+    pub fn new(node: Tree<'t, Subtree<WeightedNode<'t>>>, hash: HashSum, weight: Weight) -> Self {
+        WeightedNode(Arc::new(WeightedNodeData { node, hash, weight }))
+    }
+
+    /// Copy of the root of this subtree with another weight, that does not affect the other
+    /// occurrences of the subtree
+    pub fn with_weight(&self, weight: Weight) -> Self {
+        WeightedNode::new(self.node.clone(), self.hash, weight)
+    }
+}
+
+impl<'t> Deref for WeightedNode<'t> {
+    type Target = WeightedNodeData<'t>;
+
+    fn deref(&self) -> &WeightedNodeData<'t> {
+        &self.0
+    }
+}
+
 impl<'t> Hash for WeightedNode<'t> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        state.write_u64(self.hash.0)
+        state.write_u128(self.hash.0)
     }
 }
 
@@ -30,21 +124,216 @@ impl<'t> PartialEq for WeightedNode<'t> {
 }
 impl<'t> Eq for WeightedNode<'t> {}
 
-pub fn weight_tree<'t>(input: &SynNode<'t>) -> WeightedNode<'t> {
-    let mut weight = match input.0 {
-        Tree::Node(_, _) => NODE_WEIGHT,
-        Tree::Leaf(_) => LEAF_WEIGHT,
+/// What a subtree hash covers: the bytes of a leaf, or the kind of a node with the fields and
+/// hashes of its children
+enum HashedContent<'t> {
+    Leaf(Token<'t>),
+    Node(NodeKind, Vec<(Option<FieldId>, HashSum)>),
+}
+
+impl<'t> PartialEq for HashedContent<'t> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (HashedContent::Leaf(token), HashedContent::Leaf(other_token)) => {
+                token.same_bytes(other_token)
+            }
+            (
+                HashedContent::Node(kind, children),
+                HashedContent::Node(other_kind, other_children),
+            ) => kind == other_kind && children == other_children,
+            _ => false,
+        }
+    }
+}
+
+/// Check that the subtrees with equal hashes have equal contents, instead of trusting the
+/// hashes. A subtree colliding with another one gets a new hash, so that equal hashes
+/// always identify equal subtrees.
+fn verify_hash<'t>(
+    contents: &Sharded<HashMap<HashSum, HashedContent<'t>>>,
+    node: &Tree<'t, Subtree<WeightedNode<'t>>>,
+    hash: HashSum,
+) -> HashSum {
+    let content = match node {
+        Tree::Leaf(token) => HashedContent::Leaf(*token),
+        Tree::Node(kind, children) => HashedContent::Node(
+            *kind,
+            children.iter().map(|ch| (ch.field, ch.node.hash)).collect(),
+        ),
     };
-    let node = input.0.map_subtrees(|sub| {
-        let hashed_sub = weight_tree(sub);
-        weight += hashed_sub.weight;
-        hashed_sub
-    });
-    let mut hasher = DefaultHasher::new();
-    node.hash(&mut hasher);
-    WeightedNode {
-        node,
-        hash: HashSum(hasher.finish()),
-        weight,
+    // Colliding subtrees follow the same chain of rehashes, until they find their own
+    // content or a free hash
+    let mut hash = hash;
+    loop {
+        match contents.shard(hash).entry(hash) {
+            Entry::Vacant(entry) => {
+                entry.insert(content);
+                return hash;
+            }
+            Entry::Occupied(entry) if *entry.get() == content => return hash,
+            Entry::Occupied(_) => hash = HashSum(xxh3_128(&hash.0.to_le_bytes())),
+        }
+    }
+}
+
+/// Whether two trees are leaves with the same bytes, or nodes of the same kind sharing the
+/// same children. Unlike hashes, this distinguishes tokens differing only by whitespace.
+fn is_same_tree(left: &Tree<Subtree<WeightedNode>>, right: &Tree<Subtree<WeightedNode>>) -> bool {
+    match (left, right) {
+        (Tree::Leaf(left_token), Tree::Leaf(right_token)) => {
+            left_token.bytes() == right_token.bytes()
+        }
+        (Tree::Node(left_kind, left_children), Tree::Node(right_kind, right_children)) => {
+            left_kind == right_kind
+                && left_children.len() == right_children.len()
+                && left_children
+                    .iter()
+                    .zip(right_children)
+                    .all(|(left, right)| {
+                        left.field == right.field && Arc::ptr_eq(&left.node.0, &right.node.0)
+                    })
+        }
+        _ => false,
+    }
+}
+
+/// Number of independently locked parts of the tables of an interner, so that threads weighing
+/// different subtrees seldom wait for each other
+const NB_SHARDS: usize = 64;
+
+/// Depth of the nodes whose children are weighed in parallel by `weight_tree`. Deeper subtrees
+/// are too small to be worth spreading over several threads.
+const PARALLEL_DEPTH: usize = 2;
+
+/// Table split in shards by hash, each behind its own lock
+struct Sharded<T>(Vec<Mutex<T>>);
+
+impl<T: Default> Sharded<T> {
+    fn new() -> Self {
+        Sharded((0..NB_SHARDS).map(|_| Mutex::default()).collect())
+    }
+
+    fn shard(&self, hash: HashSum) -> MutexGuard<'_, T> {
+        self.0[(hash.0 % NB_SHARDS as u128) as usize]
+            .lock()
+            .unwrap()
+    }
+}
+
+/// Builds weighted trees where identical subtrees are hash-consed: they are weighed and stored
+/// once, and shared by all their occurrences in the trees built by the same interner.
+/// Several trees, and the children of a node, can be weighed in parallel by the same interner.
+pub struct TreeInterner<'c, 't> {
+    cost_model: &'c CostModel,
+    verifier: Option<Sharded<HashMap<HashSum, HashedContent<'t>>>>,
+    shared_nodes: Option<Sharded<HashMap<HashSum, Vec<WeightedNode<'t>>>>>,
+}
+
+impl<'c, 't> TreeInterner<'c, 't> {
+    /// Create an interner weighing subtrees with `cost_model`. If `verify_hashes` is set, the
+    /// contents of subtrees with equal hashes are compared instead of trusting the hashes.
+    /// If `share_subtrees` is not set, every occurrence of a subtree gets its own nodes.
+    pub fn new(cost_model: &'c CostModel, verify_hashes: bool, share_subtrees: bool) -> Self {
+        TreeInterner {
+            cost_model,
+            verifier: verify_hashes.then(Sharded::new),
+            shared_nodes: share_subtrees.then(Sharded::new),
+        }
+    }
+
+    /// Hash a syntax tree and compute its weight, reusing the nodes already built for its
+    /// identical subtrees
+    pub fn weight_tree(&self, input: &SynNode<'t>) -> WeightedNode<'t> {
+        self.weight_subtree(input, 0)
+    }
+
+    #[recursive]
+    fn weight_subtree(&self, input: &SynNode<'t>, depth: usize) -> WeightedNode<'t> {
+        let weight_child = |child: &Subtree<SynNode<'t>>| {
+            child
+                .as_ref()
+                .map(|sub| self.weight_subtree(sub, depth + 1))
+        };
+        let node = input.0.convert(|children| {
+            if depth < PARALLEL_DEPTH {
+                map_slice(children, weight_child)
+            } else {
+                children.iter().map(weight_child).collect()
+            }
+        });
+        self.intern(node)
+    }
+
+    /// Hash a node whose children are already weighed and compute its weight, reusing the node
+    /// already built if an identical one exists
+    pub fn intern(&self, node: Tree<'t, Subtree<WeightedNode<'t>>>) -> WeightedNode<'t> {
+        let mut hash = hash_tree(&node);
+        if let Some(verifier) = &self.verifier {
+            hash = verify_hash(verifier, &node, hash);
+        }
+        let shared_nodes = match &self.shared_nodes {
+            Some(shared_nodes) => shared_nodes,
+            None => {
+                let weight = self.weigh(&node);
+                return WeightedNode::new(node, hash, weight);
+            }
+        };
+
+        // Keep the lock between the search and the insertion, so that identical subtrees
+        // weighed in parallel still end up sharing the same node
+        let mut shared_nodes = shared_nodes.shard(hash);
+        let candidates = shared_nodes.entry(hash).or_default();
+        if let Some(shared) = candidates
+            .iter()
+            .find(|cand| is_same_tree(&cand.node, &node))
+        {
+            return shared.clone();
+        }
+        let weight = self.weigh(&node);
+        let weighted = WeightedNode::new(node, hash, weight);
+        candidates.push(weighted.clone());
+        weighted
+    }
+
+    fn weigh(&self, node: &Tree<Subtree<WeightedNode>>) -> Weight {
+        match node {
+            Tree::Node(kind, children) => {
+                let weight = children
+                    .iter()
+                    .fold(self.cost_model.node_weight, |weight, ch| {
+                        weight.saturating_add(ch.node.weight)
+                    });
+                match self.cost_model.kind_multipliers.get(kind) {
+                    Some(multiplier) => weight.saturating_mul(*multiplier),
+                    None => weight,
+                }
+                .min(MAX_WEIGHT)
+            }
+            Tree::Leaf(_) => self.cost_model.leaf_weight.min(MAX_WEIGHT),
+        }
+    }
+}
+
+/// Compute the XXH3 128-bit hash of a weighted tree. It is stable across platforms and
+/// releases, and covers the following bytes:
+/// - for a leaf, a 0 byte followed by the little endian hash of its token,
+/// - for a node, a 1 byte followed by its little endian kind, then for each child its little
+///   endian field (0 if it has none) and hash.
+pub fn hash_tree(node: &Tree<Subtree<WeightedNode>>) -> HashSum {
+    let mut hasher = Xxh3::new();
+    match node {
+        Tree::Leaf(token) => {
+            hasher.update(&[0]);
+            hasher.update(&token.hash_value().to_le_bytes());
+        }
+        Tree::Node(kind, children) => {
+            hasher.update(&[1]);
+            hasher.update(&kind.to_le_bytes());
+            for ch in children {
+                hasher.update(&ch.field.unwrap_or(0).to_le_bytes());
+                hasher.update(&ch.node.hash.0.to_le_bytes());
+            }
+        }
     }
+    HashSum(hasher.digest128())
 }
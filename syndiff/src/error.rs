@@ -0,0 +1,59 @@
+use crate::generic_tree::NodeKind;
+use crate::syn_tree::SyntaxError;
+use std::fmt;
+
+/// Reasons for which syndiff can fail to parse, merge or patch files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Two trees that should be aligned have different node kinds, tokens or metavariables.
+    SpineKindMismatch,
+    /// Two aligned node children sequences do not have the same fields or the same length.
+    FieldMismatch,
+    /// A patch still contains a conflict and cannot be applied.
+    UnresolvedConflict,
+    /// The parser was unable to produce a syntax tree for the source.
+    ParseFailure,
+    /// The source contains syntax errors and was parsed in strict mode.
+    SyntaxErrors(Vec<SyntaxError>),
+    /// Parsing a printed tree does not give back the same tree, lines and columns are numbered
+    /// from 1.
+    ReparseMismatch { line: usize, column: usize },
+    /// The node kind name is unknown to the parser language.
+    UnknownNodeKind(String),
+    /// The field name is unknown to the parser language.
+    UnknownFieldName(String),
+    /// The weights of the cost model do not guarantee minimal alignments.
+    InadmissibleCostModel(String),
+    /// The cost model multiplies the weight of the nodes of this kind by zero, so that their
+    /// changes would be free.
+    ZeroKindMultiplier(NodeKind),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::SpineKindMismatch => write!(f, "node kinds mismatch while aligning trees"),
+            Error::FieldMismatch => write!(f, "node fields mismatch while aligning trees"),
+            Error::UnresolvedConflict => write!(f, "unresolved conflict in patch"),
+            Error::ParseFailure => write!(f, "parse failure"),
+            Error::SyntaxErrors(errors) => write!(f, "{} syntax error(s)", errors.len()),
+            Error::ReparseMismatch { line, column } => write!(
+                f,
+                "printed tree parses differently from {}:{}",
+                line, column
+            ),
+            Error::UnknownNodeKind(kind) => write!(f, "unknown node kind `{}`", kind),
+            Error::UnknownFieldName(field) => write!(f, "unknown field name `{}`", field),
+            Error::InadmissibleCostModel(reason) => {
+                write!(f, "inadmissible cost model: {}", reason)
+            }
+            Error::ZeroKindMultiplier(kind) => write!(
+                f,
+                "inadmissible cost model: the multiplier of node kind #{} must be positive",
+                kind
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
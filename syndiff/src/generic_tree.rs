@@ -1,42 +1,81 @@
 use crate::tree_formatter::{TreeFormattable, TreeFormatter};
-use std::collections::hash_map::DefaultHasher;
+use crate::Error;
+use recursive::recursive;
 use std::hash::{Hash, Hasher};
+use std::ops::{Deref, DerefMut};
+use xxhash_rust::xxh3::{xxh3_128, Xxh3};
 
 pub type NodeKind = u16;
 pub type FieldId = u16;
 
+/// Leaf of a syntax tree, compared by the XXH3 128-bit hash of its bytes.
+/// Unlike the standard library hashers, XXH3 is specified, so the hash of a token is stable
+/// across platforms and releases.
 #[derive(Copy, Clone)]
 pub struct Token<'t> {
-    hash: u64,
+    hash: u128,
     bytes: &'t [u8],
+    ignore_whitespace: bool,
+}
+
+fn is_ignored_whitespace(byte: u8) -> bool {
+    byte == b' ' || byte == b'\t'
 }
 
 impl<'t> Token<'t> {
     pub fn new(bytes: &'t [u8], ignore_whitespace: bool) -> Self {
-        let mut hasher = DefaultHasher::new();
-        if ignore_whitespace {
-            for byte in bytes {
-                if *byte != b' ' && *byte != b'\t' {
-                    byte.hash(&mut hasher);
-                }
+        let hash = if ignore_whitespace {
+            let mut hasher = Xxh3::new();
+            for chunk in bytes.split(|byte| is_ignored_whitespace(*byte)) {
+                hasher.update(chunk);
             }
+            hasher.digest128()
         } else {
-            bytes.hash(&mut hasher);
-        }
+            xxh3_128(bytes)
+        };
         Token {
-            hash: hasher.finish(),
+            hash,
             bytes,
+            ignore_whitespace,
+        }
+    }
+
+    pub fn bytes(&self) -> &'t [u8] {
+        self.bytes
+    }
+
+    pub fn hash_value(&self) -> u128 {
+        self.hash
+    }
+
+    /// Compare the bytes of two tokens, without relying on their hash.
+    /// Spaces and tabs are skipped if one of the tokens was created ignoring whitespace.
+    pub fn same_bytes(&self, other: &Token) -> bool {
+        if self.ignore_whitespace || other.ignore_whitespace {
+            self.bytes
+                .iter()
+                .filter(|byte| !is_ignored_whitespace(**byte))
+                .eq(other
+                    .bytes
+                    .iter()
+                    .filter(|byte| !is_ignored_whitespace(**byte)))
+        } else {
+            self.bytes == other.bytes
         }
     }
 
     pub fn is_extra_block_separator(&self) -> bool {
         self.bytes.iter().filter(|c| **c == b'\n').count() >= 2
     }
+
+    pub fn has_newline(&self) -> bool {
+        self.bytes.contains(&b'\n')
+    }
 }
 
 impl<'t> Hash for Token<'t> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        state.write_u64(self.hash)
+        state.write_u128(self.hash)
     }
 }
 
@@ -49,10 +88,82 @@ impl<'t> Eq for Token<'t> {}
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub enum Tree<'t, T> {
-    Node(NodeKind, Vec<T>),
+    Node(NodeKind, Children<T>),
     Leaf(Token<'t>),
 }
 
+/// Children of a tree node, used like a `Vec`.
+/// Every kind of tree stores its nodes below a `Children` list, so dropping them from a growable
+/// stack segment keeps the drop of arbitrarily deep trees from overflowing the stack.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Children<T>(Vec<T>);
+
+impl<T> Children<T> {
+    pub fn into_vec(mut self) -> Vec<T> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+impl<T> Drop for Children<T> {
+    #[recursive]
+    fn drop(&mut self) {
+        drop(std::mem::take(&mut self.0))
+    }
+}
+
+impl<T> Deref for Children<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Children<T> {
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        &mut self.0
+    }
+}
+
+impl<T> From<Vec<T>> for Children<T> {
+    fn from(children: Vec<T>) -> Self {
+        Children(children)
+    }
+}
+
+impl<T> FromIterator<T> for Children<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Children(iter.into_iter().collect())
+    }
+}
+
+impl<T> IntoIterator for Children<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_vec().into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Children<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut Children<T> {
+    type Item = &'a mut T;
+    type IntoIter = std::slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter_mut()
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Subtree<T> {
     pub field: Option<FieldId>,
@@ -60,7 +171,7 @@ pub struct Subtree<T> {
 }
 
 impl<'t, T> Tree<'t, T> {
-    pub fn visit(&self, mut visit_fn: impl FnMut(&T)) {
+    pub fn visit<'a>(&'a self, mut visit_fn: impl FnMut(&'a T)) {
         match self {
             Tree::Node(_, children) => {
                 for ch in children {
@@ -84,7 +195,7 @@ impl<'t, T> Tree<'t, T> {
 
     pub fn convert<U>(&self, conv_fn: impl FnOnce(&[T]) -> Vec<U>) -> Tree<'t, U> {
         match self {
-            Tree::Node(kind, children) => Tree::Node(*kind, conv_fn(children)),
+            Tree::Node(kind, children) => Tree::Node(*kind, conv_fn(children).into()),
             Tree::Leaf(tok) => Tree::Leaf(*tok),
         }
     }
@@ -95,17 +206,17 @@ impl<'t, T> Tree<'t, T> {
 
     pub fn convert_into<U>(self, conv_fn: impl FnOnce(Vec<T>) -> Vec<U>) -> Tree<'t, U> {
         match self {
-            Tree::Node(kind, children) => Tree::Node(kind, conv_fn(children)),
+            Tree::Node(kind, children) => Tree::Node(kind, conv_fn(children.into_vec()).into()),
             Tree::Leaf(tok) => Tree::Leaf(tok),
         }
     }
 
     pub fn try_convert_into<U>(
         self,
-        conv_fn: impl FnOnce(Vec<T>) -> Option<Vec<U>>,
-    ) -> Option<Tree<'t, U>> {
-        Some(match self {
-            Tree::Node(kind, children) => Tree::Node(kind, conv_fn(children)?),
+        conv_fn: impl FnOnce(Vec<T>) -> Result<Vec<U>, Error>,
+    ) -> Result<Tree<'t, U>, Error> {
+        Ok(match self {
+            Tree::Node(kind, children) => Tree::Node(kind, conv_fn(children.into_vec())?.into()),
             Tree::Leaf(tok) => Tree::Leaf(tok),
         })
     }
@@ -131,28 +242,29 @@ impl<'t, T> Tree<'t, T> {
     pub fn merge_into<L, R>(
         left: Tree<'t, L>,
         right: Tree<'t, R>,
-        merge_child_fn: impl FnOnce(Vec<L>, Vec<R>) -> Option<Vec<T>>,
-    ) -> Option<Self> {
+        merge_child_fn: impl FnOnce(Vec<L>, Vec<R>) -> Result<Vec<T>, Error>,
+    ) -> Result<Self, Error> {
         match (left, right) {
-            (Tree::Node(lkind, lch), Tree::Node(rkind, rch)) if lkind == rkind => {
-                Some(Tree::Node(lkind, merge_child_fn(lch, rch)?))
-            }
-            (Tree::Leaf(ltok), Tree::Leaf(rtok)) if ltok == rtok => Some(Tree::Leaf(ltok)),
-            _ => None,
+            (Tree::Node(lkind, lch), Tree::Node(rkind, rch)) if lkind == rkind => Ok(Tree::Node(
+                lkind,
+                merge_child_fn(lch.into_vec(), rch.into_vec())?.into(),
+            )),
+            (Tree::Leaf(ltok), Tree::Leaf(rtok)) if ltok == rtok => Ok(Tree::Leaf(ltok)),
+            _ => Err(Error::SpineKindMismatch),
         }
     }
 
     pub fn merge_to<L, R>(
         left: &Tree<'t, L>,
         right: &Tree<'t, R>,
-        merge_child_fn: impl FnOnce(&[L], &[R]) -> Option<Vec<T>>,
-    ) -> Option<Self> {
+        merge_child_fn: impl FnOnce(&[L], &[R]) -> Result<Vec<T>, Error>,
+    ) -> Result<Self, Error> {
         match (left, right) {
             (Tree::Node(lkind, lch), Tree::Node(rkind, rch)) if lkind == rkind => {
-                Some(Tree::Node(*lkind, merge_child_fn(lch, rch)?))
+                Ok(Tree::Node(*lkind, merge_child_fn(lch, rch)?.into()))
             }
-            (Tree::Leaf(ltok), Tree::Leaf(rtok)) if ltok == rtok => Some(Tree::Leaf(*ltok)),
-            _ => None,
+            (Tree::Leaf(ltok), Tree::Leaf(rtok)) if ltok == rtok => Ok(Tree::Leaf(*ltok)),
+            _ => Err(Error::SpineKindMismatch),
         }
     }
 
@@ -162,8 +274,11 @@ impl<'t, T> Tree<'t, T> {
     ) -> (Tree<'t, L>, Tree<'t, R>) {
         match self {
             Tree::Node(kind, children) => {
-                let (sub_left, sub_right) = split_children_fn(children);
-                (Tree::Node(kind, sub_left), Tree::Node(kind, sub_right))
+                let (sub_left, sub_right) = split_children_fn(children.into_vec());
+                (
+                    Tree::Node(kind, sub_left.into()),
+                    Tree::Node(kind, sub_right.into()),
+                )
             }
             Tree::Leaf(tok) => (Tree::Leaf(tok), Tree::Leaf(tok)),
         }
@@ -171,6 +286,7 @@ impl<'t, T> Tree<'t, T> {
 }
 
 impl<'t, T: TreeFormattable> TreeFormattable for Tree<'t, T> {
+    #[recursive]
     fn write_with<F: TreeFormatter>(&self, fmt: &mut F) -> std::io::Result<()> {
         match self {
             Tree::Node(_, children) => children.write_with(fmt),
@@ -194,8 +310,11 @@ impl<T> Subtree<T> {
         }
     }
 
-    pub fn try_map<U>(self, conv_fn: impl FnOnce(T) -> Option<U>) -> Option<Subtree<U>> {
-        Some(Subtree {
+    pub fn try_map<U>(
+        self,
+        conv_fn: impl FnOnce(T) -> Result<U, Error>,
+    ) -> Result<Subtree<U>, Error> {
+        Ok(Subtree {
             field: self.field,
             node: conv_fn(self.node)?,
         })
@@ -215,12 +334,12 @@ impl<T> Subtree<T> {
     pub fn merge<L, R>(
         left: Subtree<L>,
         right: Subtree<R>,
-        merge_fn: impl FnOnce(L, R) -> Option<T>,
-    ) -> Option<Self> {
+        merge_fn: impl FnOnce(L, R) -> Result<T, Error>,
+    ) -> Result<Self, Error> {
         if left.field != right.field {
-            return None;
+            return Err(Error::FieldMismatch);
         }
-        Some(Subtree {
+        Ok(Subtree {
             field: left.field,
             node: merge_fn(left.node, right.node)?,
         })
@@ -262,11 +381,11 @@ impl<'t, T> Tree<'t, Subtree<T>> {
     pub fn merge_subtrees_to<L, R>(
         left: &Tree<'t, Subtree<L>>,
         right: &Tree<'t, Subtree<R>>,
-        mut merge_child_fn: impl FnMut(&L, &R) -> Option<T>,
-    ) -> Option<Self> {
+        mut merge_child_fn: impl FnMut(&L, &R) -> Result<T, Error>,
+    ) -> Result<Self, Error> {
         Tree::merge_to(left, right, |left_seq, right_seq| {
             if left_seq.len() != right_seq.len() {
-                return None;
+                return Err(Error::FieldMismatch);
             }
             left_seq
                 .iter()
@@ -279,11 +398,11 @@ impl<'t, T> Tree<'t, Subtree<T>> {
     pub fn merge_subtrees_into<L, R>(
         left: Tree<'t, Subtree<L>>,
         right: Tree<'t, Subtree<R>>,
-        mut merge_child_fn: impl FnMut(L, R) -> Option<T>,
-    ) -> Option<Self> {
+        mut merge_child_fn: impl FnMut(L, R) -> Result<T, Error>,
+    ) -> Result<Self, Error> {
         Tree::merge_into(left, right, |left_seq, right_seq| {
             if left_seq.len() != right_seq.len() {
-                return None;
+                return Err(Error::FieldMismatch);
             }
             left_seq
                 .into_iter()
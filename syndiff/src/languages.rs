@@ -0,0 +1,83 @@
+use std::path::Path;
+use tree_sitter::Language;
+
+/// Grammar statically linked in the binary through a cargo feature
+struct BuiltinLanguage {
+    scope: &'static str,
+    file_types: &'static [&'static str],
+    language: fn() -> Language,
+}
+
+const BUILTIN_LANGUAGES: &[BuiltinLanguage] = &[
+    #[cfg(feature = "rust")]
+    BuiltinLanguage {
+        scope: "source.rust",
+        file_types: &["rs"],
+        language: tree_sitter_rust::language,
+    },
+    #[cfg(feature = "c")]
+    BuiltinLanguage {
+        scope: "source.c",
+        file_types: &["c", "h"],
+        language: tree_sitter_c::language,
+    },
+    #[cfg(feature = "cpp")]
+    BuiltinLanguage {
+        scope: "source.cpp",
+        file_types: &["cc", "cpp", "cxx", "hh", "hpp", "hxx"],
+        language: tree_sitter_cpp::language,
+    },
+    #[cfg(feature = "python")]
+    BuiltinLanguage {
+        scope: "source.python",
+        file_types: &["py", "pyi"],
+        language: tree_sitter_python::language,
+    },
+    #[cfg(feature = "javascript")]
+    BuiltinLanguage {
+        scope: "source.js",
+        file_types: &["js", "mjs", "cjs", "jsx"],
+        language: tree_sitter_javascript::language,
+    },
+    #[cfg(feature = "go")]
+    BuiltinLanguage {
+        scope: "source.go",
+        file_types: &["go"],
+        language: tree_sitter_go::language,
+    },
+    #[cfg(feature = "java")]
+    BuiltinLanguage {
+        scope: "source.java",
+        file_types: &["java"],
+        language: tree_sitter_java::language,
+    },
+    #[cfg(feature = "json")]
+    BuiltinLanguage {
+        scope: "source.json",
+        file_types: &["json"],
+        language: tree_sitter_json::language,
+    },
+];
+
+/// Select one of the grammars embedded with cargo features.
+///
+/// When a scope is given (e.g. `source.rust`) it alone decides the language, otherwise the
+/// language is selected from the extension of `path`.
+/// Returns None if no embedded grammar matches.
+pub fn builtin_language(path: &Path, scope: Option<&str>) -> Option<Language> {
+    let builtin = match scope {
+        Some(scope) => BUILTIN_LANGUAGES.iter().find(|lang| lang.scope == scope),
+        None => {
+            let extension = path.extension()?.to_str()?;
+            BUILTIN_LANGUAGES
+                .iter()
+                .find(|lang| lang.file_types.contains(&extension))
+        }
+    }?;
+    Some((builtin.language)())
+}
+
+/// Scopes of the grammars embedded with cargo features
+pub fn builtin_language_scopes() -> impl Iterator<Item = &'static str> {
+    BUILTIN_LANGUAGES.iter().map(|lang| lang.scope)
+}
@@ -0,0 +1,118 @@
+/// Compute a longest common subsequence between two sequences with the Myers algorithm.
+/// Returns the positions of the matched elements in both sequences.
+///
+/// The linear space variant of the algorithm is used: the sequences are recursively split
+/// around the middle snake of their shortest edit script, so memory stays linear in the length
+/// of the sequences even when they are very different.
+pub fn common_subsequence<T: Eq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let mut matches = Vec::new();
+    push_matches(a, b, (0, 0), &mut matches);
+    matches
+}
+
+fn push_matches<T: Eq>(
+    a: &[T],
+    b: &[T],
+    offset: (usize, usize),
+    matches: &mut Vec<(usize, usize)>,
+) {
+    // Common prefix and suffix are matched directly, this keeps the Myers search (quadratic in
+    // the number of differences) focused on the modified region.
+    let prefix_len = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix_len = a[prefix_len..]
+        .iter()
+        .rev()
+        .zip(b[prefix_len..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let a_mid = &a[prefix_len..a.len() - suffix_len];
+    let b_mid = &b[prefix_len..b.len() - suffix_len];
+
+    matches.extend((0..prefix_len).map(|i| (offset.0 + i, offset.1 + i)));
+    if !a_mid.is_empty() && !b_mid.is_empty() {
+        let mid_offset = (offset.0 + prefix_len, offset.1 + prefix_len);
+        let ((x_start, y_start), (x_end, y_end)) = middle_snake(a_mid, b_mid);
+        push_matches(&a_mid[..x_start], &b_mid[..y_start], mid_offset, matches);
+        matches.extend(
+            (0..x_end - x_start).map(|i| (mid_offset.0 + x_start + i, mid_offset.1 + y_start + i)),
+        );
+        push_matches(
+            &a_mid[x_end..],
+            &b_mid[y_end..],
+            (mid_offset.0 + x_end, mid_offset.1 + y_end),
+            matches,
+        );
+    }
+    matches.extend((0..suffix_len).map(|i| {
+        (
+            offset.0 + a.len() - suffix_len + i,
+            offset.1 + b.len() - suffix_len + i,
+        )
+    }));
+}
+
+/// Find the snake in the middle of a shortest edit script between `a` and `b`, by running the
+/// Myers search simultaneously from both ends until the paths overlap.
+/// Returns the start and end positions of the snake.
+fn middle_snake<T: Eq>(a: &[T], b: &[T]) -> ((usize, usize), (usize, usize)) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let max = (n + m + 1) / 2 + 1;
+    let index = |k: isize| (k + max) as usize;
+
+    // Furthest reaching x position on each forward diagonal k = x - y, and furthest reaching
+    // distance from the end on each backward diagonal, after each step d
+    let mut forward = vec![0; 2 * max as usize + 1];
+    let mut backward = vec![0; 2 * max as usize + 1];
+    for d in 0..max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)]) {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let start = (x, x - k);
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index(k)] = x;
+            let backward_k = delta - k;
+            if delta % 2 != 0
+                && backward_k.abs() < d
+                && forward[index(k)] + backward[index(backward_k)] >= n
+            {
+                return (
+                    (start.0 as usize, start.1 as usize),
+                    (x as usize, y as usize),
+                );
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[index(k - 1)] < backward[index(k + 1)]) {
+                backward[index(k + 1)]
+            } else {
+                backward[index(k - 1)] + 1
+            };
+            let end = (n - x, m - (x - k));
+            let mut y = x - k;
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[index(k)] = x;
+            let forward_k = delta - k;
+            if delta % 2 == 0
+                && forward_k.abs() <= d
+                && backward[index(k)] + forward[index(forward_k)] >= n
+            {
+                return (
+                    ((n - x) as usize, (m - y) as usize),
+                    (end.0 as usize, end.1 as usize),
+                );
+            }
+        }
+    }
+    unreachable!("Myers search did not find a middle snake")
+}
@@ -1,17 +1,35 @@
 mod diff;
+mod error;
 mod generic_tree;
+mod languages;
+mod lcs;
 mod merge;
+mod parallel;
 mod syn_tree;
 mod tree_formatter;
 
 pub use crate::diff::{
-    compute_diff, DiffSpineNode, Metavariable, MINIMAL_ALIGNMENT, PATIENCE_ALIGNMENT,
+    compute_chunked_diff, compute_diff, compute_diff_pair, AlignmentBudget, AlignmentContext,
+    AlignmentDiagnostic, AlignmentIssue, CostModel, DiffOptions, DiffSpineNode, HashSum,
+    HistogramAlignment, Metavariable, MinimalAlignment, MyersAlignment, NodeAlignment,
+    PatienceAlignment, SeqNodeAlignment, SubtreeAlignment, Weight, WeightedNode, WeightedNodeData,
 };
+pub use crate::error::Error;
+pub use crate::generic_tree::{FieldId, NodeKind, Subtree, Token, Tree};
+pub use crate::languages::{builtin_language, builtin_language_scopes};
 pub use crate::merge::{
-    apply_patch, canonicalize_metavars, count_conflicts, merge_diffs, remove_metavars,
-    MergeOptions, MergedSpineNode,
+    apply_patch, canonicalize_metavars, count_conflicts, merge_chunked, merge_diffs, merge_lines,
+    refine_conflicts, remove_metavars, report_conflicts, write_with_chosen_side,
+    write_with_conflict_markers, ConflictInfo, ConflictKind, ConflictMarkerOptions,
+    ConflictRefinement, ConflictReport, ConflictSide, ConflictStyle, DeleteConflictResolution,
+    InsConflictResolution, InsertOrderResolution, LineMerge, MergeOptions, MergedSpineNode,
+    MetavarConflictResolution, NamedAncestor, SourceRange,
+};
+pub use crate::parallel::MaybeSync;
+pub use crate::syn_tree::{
+    add_extra_blocks, field_id, node_kind_id, parse_source, validate_reparse, SynNode, SyntaxError,
+    SyntaxErrorHandling,
 };
-pub use crate::syn_tree::{add_extra_blocks, parse_source, SynNode};
 pub use crate::tree_formatter::{
     AnsiColoredTreeFormatter, PlainTreeFormatter, TextColoredTreeFormatter, TreeFormattable,
     TreeFormatter,
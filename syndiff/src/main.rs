@@ -1,17 +1,26 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
+use libloading::{Library, Symbol};
 use std::cmp::min;
-use std::collections::HashSet;
-use std::ffi::OsStr;
-use std::fs::read;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::{read, rename, File};
+use std::io::Write;
 use std::path::Path;
-use std::process::exit;
+use std::process::{exit, Command};
 use syndiff::{
-    add_extra_blocks, apply_patch, canonicalize_metavars, compute_diff, count_conflicts,
-    merge_diffs, parse_source, remove_metavars, AnsiColoredTreeFormatter, MergeOptions,
-    PlainTreeFormatter, SynNode, TextColoredTreeFormatter, TreeFormattable, MINIMAL_ALIGNMENT,
-    PATIENCE_ALIGNMENT,
+    add_extra_blocks, apply_patch, builtin_language, builtin_language_scopes,
+    canonicalize_metavars, compute_chunked_diff, compute_diff, compute_diff_pair, count_conflicts,
+    field_id, merge_chunked, merge_diffs, merge_lines, node_kind_id, parse_source,
+    refine_conflicts, remove_metavars, report_conflicts, validate_reparse, write_with_chosen_side,
+    write_with_conflict_markers, AlignmentDiagnostic, AnsiColoredTreeFormatter,
+    ConflictMarkerOptions, ConflictRefinement, ConflictSide, ConflictStyle, CostModel,
+    DeleteConflictResolution, DiffOptions, Error, FieldId, HistogramAlignment,
+    InsConflictResolution, InsertOrderResolution, LineMerge, MergeOptions, MergedSpineNode,
+    MetavarConflictResolution, MinimalAlignment, MyersAlignment, NodeKind, PatienceAlignment,
+    PlainTreeFormatter, SubtreeAlignment, SynNode, SyntaxErrorHandling, TextColoredTreeFormatter,
+    TreeFormattable,
 };
-use tree_sitter::Parser;
+use tree_sitter::{Language, Parser};
 use tree_sitter_config::Config;
 use tree_sitter_loader::Loader;
 
@@ -31,15 +40,24 @@ fn main() {
             If three files are given, compute differences between the two modified files \
             and the original and then merge these differences.\n\
             Exit with the number of conflicts found during the merge (capped to 127).\n\n\
-            Syntax trees are parsed from the provided source files by a tree-sitter grammar.")
+            With --git-merge-driver, behave as a git merge driver instead: write the merged \
+            file over the first modified file and exit with 0 if the merge is clean or with 1 \
+            if conflicts remain. Errors exit with a status above 128 so that git reports a \
+            failed merge.\n\n\
+            When the structural merge fails, merged files are produced by a line-based three-way \
+            merge instead, like git merge-file. A merged file without conflicts that does not \
+            parse back to the merged syntax tree counts as one conflict.\n\n\
+            Syntax trees are parsed from the provided source files by a tree-sitter grammar. \
+            Grammars embedded with cargo features are selected first, other languages are \
+            loaded from the parser directories of the tree-sitter configuration.")
         .arg(
             Arg::with_name("original-file")
-                .required(true)
+                .required_unless("install-git-merge-driver")
                 .help("Path to the original file to diff"),
         )
         .arg(
             Arg::with_name("first-modified-file")
-                .required(true)
+                .required_unless("install-git-merge-driver")
                 .help("Path to the first modified file")
         )
         .arg(Arg::with_name("second-modified-file").required(false).help("Path of the second modified file. If provided, perform a three-way merge."))
@@ -50,39 +68,78 @@ fn main() {
         .arg(Arg::with_name("text-colored").short("C").long("text-colored").help("Display difference node colors as plain text without ANSI color codes"))
         .arg(Arg::with_name("merge-files").short("m").long("merge-files").requires("second-modified-file").help("If there are no conflicts, print the resulting merged file instead of the merged difference"))
         .arg(Arg::with_name("allow-nested-deletions").short("d").long("allow-nested-deletions").requires("second-modified-file").help("Accept to merge a deletion nested into another deletion without conflict"))
-        .arg(Arg::with_name("ordered-insertions").short("o").long("ordered-insertions").requires("second-modified-file").help("Do not create insert order conflicts by always placing insertions in the first modified file before those of the second modified file"))
+        .arg(Arg::with_name("ordered-insertions").short("o").long("ordered-insertions").requires("second-modified-file").conflicts_with("insert-order-conflicts").help("Do not create insert order conflicts by always placing insertions in the first modified file before those of the second modified file"))
+        .arg(Arg::with_name("delete-conflicts").long("delete-conflicts").takes_value(true).possible_values(&["deletion", "modification"]).requires("second-modified-file").help("Resolve subtrees deleted in a file and modified in the other by keeping the deletion or the modification"))
+        .arg(Arg::with_name("ins-conflicts").long("ins-conflicts").takes_value(true).possible_values(&["left", "right", "both"]).requires("second-modified-file").help("Resolve subtrees replaced differently in both modified files by keeping the version of the first (left) or second (right) modified file, or both of them if they are part of a sequence"))
+        .arg(Arg::with_name("metavar-conflicts").long("metavar-conflicts").takes_value(true).possible_values(&["left", "right"]).requires("second-modified-file").help("Resolve moved subtrees with incompatible modifications by keeping the version of the first (left) or second (right) modified file"))
+        .arg(Arg::with_name("insert-order-conflicts").long("insert-order-conflicts").takes_value(true).possible_values(&["left-first", "right-first", "drop"]).requires("second-modified-file").help("Resolve concurrent insertions at the same place by placing those of the first (left) or second (right) modified file first, or by dropping all of them"))
+        .arg(Arg::with_name("commutative-kinds").long("commutative-kinds").takes_value(true).use_delimiter(true).help("Comma separated list of tree-sitter node kinds whose children are unordered: moving their children is not a change and concurrent insertions inside them are all kept"))
+        .arg(Arg::with_name("sort-commutative").long("sort-commutative").help("Sort by their text the concurrent insertions kept inside commutative nodes or with different keys"))
+        .arg(Arg::with_name("key-fields").long("key-fields").takes_value(true).use_delimiter(true).help("Comma separated list of kind.field pairs (e.g. pair.key) giving the field holding the key of nodes: siblings with the same key are always matched, and siblings with different keys are unordered and never conflict. Reordering siblings is not a change, merges keep their original order"))
+        .arg(Arg::with_name("anchor-names").long("anchor-names").takes_value(true).use_delimiter(true).help("Comma separated list of kind.field pairs (e.g. function_item.name) giving the field holding the name of items: siblings with the same name are paired before aligning the others, even if their contents are rewritten"))
+        .arg(Arg::with_name("refine-conflicts").long("refine-conflicts").takes_value(true).possible_values(&["tokens", "lines"]).requires("second-modified-file").help("Resolve conflicting replacements of a subtree when a three-way merge of their tokens or lines against the original subtree is clean. Implies --standalone"))
+        .arg(Arg::with_name("conflict-report").long("conflict-report").requires("second-modified-file").conflicts_with_all(&["merge-files", "quiet"]).help("Print a JSON report describing each conflict and its location in the three files instead of the merged difference. Locations are found by searching the text of each side of the conflict, and may be approximate when whitespace is ignored or moves of both sides are combined"))
         .arg(Arg::with_name("quiet").short("q").long("quiet").requires("second-modified-file").help("Do not print anything, just compute the number of conflicts"))
-        .arg(Arg::with_name("scope").long("scope").takes_value(true).help("Select the tree-sitter language by scope instead of file extension"))
+        .arg(Arg::with_name("scope").long("scope").takes_value(true).help("Select the tree-sitter language by scope (e.g. source.rust) instead of file extension"))
+        .arg(Arg::with_name("grammar").long("grammar").takes_value(true).requires("language-name").conflicts_with("grammar-dir").help("Load the tree-sitter language from the given compiled parser shared library instead of selecting it from the configuration"))
+        .arg(Arg::with_name("language-name").long("language-name").takes_value(true).requires("grammar").help("Name of the language exported by the --grammar shared library, as in its tree_sitter_<name> function"))
+        .arg(Arg::with_name("grammar-dir").long("grammar-dir").takes_value(true).help("Load the tree-sitter language from the given grammar directory (e.g. tree-sitter-c) instead of the parser directories of the configuration"))
+        .arg(Arg::with_name("strict").long("strict").help("Refuse to diff or merge files containing syntax errors, instead of only reporting them"))
+        .arg(Arg::with_name("opaque-syntax-errors").long("opaque-syntax-errors").conflicts_with("strict").help("Treat each region with syntax errors as a single token, so that it is either kept or replaced as a whole"))
         .arg(Arg::with_name("extra-blocks").short("b").long("extra-blocks").help("Add extra structure with additional blocks separated by empty lines"))
         .arg(Arg::with_name("ignore-whitespace").short("w").long("ignore-whitespace").help("Ignore differences in whitespace, take the spacing of the first modified file when a choice has to be made"))
-        .arg(Arg::with_name("patience").long("patience").help("Use the patience diff algorithm for subtree sequences"))
+        .arg(Arg::with_name("verify-hashes").long("verify-hashes").help("Compare the bytes of subtrees with equal hashes instead of trusting their 128-bit hashes, to rule out hash collisions"))
+        .arg(Arg::with_name("large-file").long("large-file").help("Keep the unchanged top-level items that are unique in every file, and only diff and merge the chunks of items between them. Faster on huge generated or vendored files, but moves between chunks are not detected"))
+        .arg(Arg::with_name("patience").long("patience").conflicts_with_all(&["myers", "histogram"]).help("Use the patience diff algorithm for subtree sequences"))
+        .arg(Arg::with_name("node-weight").long("node-weight").takes_value(true).validator(is_number).help("Weight of each internal syntax node in the cost model deciding whether nodes are zipped or replaced [default: 0]"))
+        .arg(Arg::with_name("leaf-weight").long("leaf-weight").takes_value(true).validator(is_number).help("Weight of each token in the cost model [default: 2]"))
+        .arg(Arg::with_name("spine-weight").long("spine-weight").takes_value(true).validator(is_number).help("Cost of keeping an unchanged subtree, that should not exceed the token weight [default: 1]"))
+        .arg(Arg::with_name("root-incentive").long("root-incentive").takes_value(true).validator(is_number).help("Extra weight of the original root node, as an incentive to keep it [default: 1]"))
+        .arg(Arg::with_name("kind-multipliers").long("kind-multipliers").takes_value(true).use_delimiter(true).help("Comma separated list of kind=multiplier pairs (e.g. identifier=4) scaling the weight of the nodes of these kinds, so that changing them is more or less expensive"))
+        .arg(Arg::with_name("kind-algorithms").long("kind-algorithms").takes_value(true).use_delimiter(true).help("Comma separated list of kind=algorithm pairs (e.g. source_file=patience) choosing the algorithm among minimal, patience, myers and histogram aligning the children of the nodes of these kinds"))
+        .arg(Arg::with_name("max-alignment-cells").long("max-alignment-cells").takes_value(true).validator(is_number).conflicts_with_all(&["patience", "myers", "histogram"]).help("Memory budget of the default minimal alignment, in search positions for one sequence of children. Sequences over budget are aligned with the patience algorithm instead"))
+        .arg(Arg::with_name("max-alignment-steps").long("max-alignment-steps").takes_value(true).validator(is_number).conflicts_with_all(&["patience", "myers", "histogram"]).help("Time budget of the default minimal alignment, in explored search positions for one sequence of children. Sequences over budget are aligned with the patience algorithm instead"))
+        .arg(Arg::with_name("myers").long("myers").conflicts_with("histogram").help("Use the Myers diff algorithm for subtree sequences, in linear memory even on huge sequences of children"))
+        .arg(Arg::with_name("histogram").long("histogram").help("Use the histogram diff algorithm for subtree sequences, splitting them around their rarest common subtrees"))
+        .arg(Arg::with_name("git-merge-driver").long("git-merge-driver").requires("second-modified-file").conflicts_with_all(&["merge-files", "quiet"]).help("Run as a git merge driver called with %O %A %B: atomically replace the first modified file by the merge result and exit with 1 if there are conflicts"))
+        .arg(Arg::with_name("conflict-style").long("conflict-style").takes_value(true).possible_values(&["merge", "diff3"]).requires("second-modified-file").help("With --merge-files, print files with conflicts as source text where each conflict is delimited by textual conflict markers, in git merge or diff3 style"))
+        .arg(Arg::with_name("unresolved-side").long("unresolved-side").takes_value(true).possible_values(&["left", "base", "right"]).requires("second-modified-file").conflicts_with("conflict-style").help("With --merge-files, print files with conflicts by applying every conflict-free change and taking the given side for the content of each conflict"))
+        .arg(Arg::with_name("marker-size").long("marker-size").takes_value(true).validator(is_number).requires("second-modified-file").help("Length of the textual conflict markers (%L for git merge drivers)"))
+        .arg(Arg::with_name("path").long("path").takes_value(true).help("Path of the file inside the repository (%P), used instead of the original file name to select the tree-sitter language"))
+        .arg(Arg::with_name("install-git-merge-driver").long("install-git-merge-driver").conflicts_with("original-file").help("Register this executable as the `syndiff` merge driver in the git configuration of the current repository, then exit"))
+        .arg(Arg::with_name("global").long("global").requires("install-git-merge-driver").help("Register the git merge driver in the global git configuration instead"))
         .get_matches_safe()
         .unwrap_or_else(|err| {
             eprintln!("{}", err);
             exit(-1)
         });
 
-    let config = Config::load().unwrap();
-    let mut lang_loader = Loader::new().unwrap();
-    lang_loader
-        .find_all_languages(&config.get().unwrap())
-        .unwrap_or_else(|err| {
-            eprintln!("Error loading parser list: {}", err);
-            exit(-2)
-        });
+    if cmd_args.is_present("install-git-merge-driver") {
+        install_git_merge_driver(cmd_args.is_present("global"));
+        exit(0)
+    }
 
     let origin_filename = cmd_args.value_of_os("original-file").unwrap();
 
-    let language = lang_loader
-        .select_language(
-            Path::new(&origin_filename),
-            Path::new(""),
-            cmd_args.value_of("scope"),
-        )
-        .unwrap_or_else(|err| {
+    // Git merge drivers receive temporary files without extension, use the path of the
+    // file inside the repository to select the language when it is given.
+    let language_path = Path::new(cmd_args.value_of_os("path").unwrap_or(origin_filename));
+    let scope = cmd_args.value_of("scope");
+    // The same language is used for all the input files
+    let language = if let Some(grammar) = cmd_args.value_of_os("grammar") {
+        let language_name = cmd_args.value_of("language-name").unwrap();
+        load_grammar_library(Path::new(grammar), language_name).unwrap_or_else(|err| {
             eprintln!("Error loading parser: {}", err);
             exit(-2)
-        });
+        })
+    } else if let Some(grammar_dir) = cmd_args.value_of_os("grammar-dir") {
+        load_grammar_dir(Path::new(grammar_dir), language_path, scope).unwrap_or_else(|err| {
+            eprintln!("Error loading parser: {}", err);
+            exit(-2)
+        })
+    } else {
+        select_language(language_path, scope).unwrap_or_else(|| exit_with_line_merge(&cmd_args))
+    };
 
     let mut parser = Parser::new();
     parser.set_language(language).unwrap_or_else(|err| {
@@ -91,6 +148,13 @@ fn main() {
     });
 
     let ignore_whitespace = cmd_args.is_present("ignore-whitespace");
+    let syntax_error_handling = if cmd_args.is_present("strict") {
+        SyntaxErrorHandling::Strict
+    } else if cmd_args.is_present("opaque-syntax-errors") {
+        SyntaxErrorHandling::Opaque
+    } else {
+        SyntaxErrorHandling::Keep
+    };
     let extra_blocks = cmd_args.is_present("extra-blocks");
     let elision_whitelist = if cmd_args.is_present("no-elisions") {
         Some(HashSet::new())
@@ -98,22 +162,129 @@ fn main() {
         let whitelist_file = read_file(whitelist_filename);
         let mut whitelist = HashSet::new();
         for kind_str in whitelist_file.split(|c| char::from(*c).is_ascii_whitespace()) {
-            let kind_str = String::from_utf8_lossy(kind_str);
-            let kind_id = language.id_for_node_kind(&kind_str, true);
-            if kind_id == 0 {
-                eprintln!("Unknown node kind `{}` for parser", kind_str);
-                exit(-2);
-            }
+            let kind_id = node_kind_id(language, &String::from_utf8_lossy(kind_str))
+                .unwrap_or_else(|err| {
+                    eprintln!("Invalid elision whitelist: {}", err);
+                    exit(-2)
+                });
             whitelist.insert(kind_id);
         }
         Some(whitelist)
     } else {
         None
     };
-    let align_subtree_algorithm = if cmd_args.is_present("patience") {
-        PATIENCE_ALIGNMENT
-    } else {
-        MINIMAL_ALIGNMENT
+    let commutative_kinds: HashSet<_> = cmd_args
+        .values_of("commutative-kinds")
+        .into_iter()
+        .flatten()
+        .map(|kind_name| {
+            node_kind_id(language, kind_name).unwrap_or_else(|err| {
+                eprintln!("Invalid commutative node kinds: {}", err);
+                exit(-2)
+            })
+        })
+        .collect();
+    let key_fields = parse_kind_fields(cmd_args.values_of("key-fields"), language, "key");
+    let name_fields = parse_kind_fields(cmd_args.values_of("anchor-names"), language, "name");
+    let default_cost_model = CostModel::default();
+    let number_arg = |name, default| {
+        cmd_args
+            .value_of(name)
+            .map(|value| value.parse().unwrap())
+            .unwrap_or(default)
+    };
+    let cost_model = CostModel {
+        node_weight: number_arg("node-weight", default_cost_model.node_weight),
+        leaf_weight: number_arg("leaf-weight", default_cost_model.leaf_weight),
+        spine_leaf_weight: number_arg("spine-weight", default_cost_model.spine_leaf_weight),
+        root_incentive: number_arg("root-incentive", default_cost_model.root_incentive),
+        kind_multipliers: cmd_args
+            .values_of("kind-multipliers")
+            .into_iter()
+            .flatten()
+            .map(|kind_multiplier| {
+                let (kind_name, multiplier) = kind_multiplier
+                    .split_once('=')
+                    .and_then(|(kind_name, multiplier)| {
+                        Some((kind_name, multiplier.parse().ok().filter(|&mul| mul > 0)?))
+                    })
+                    .unwrap_or_else(|| {
+                        eprintln!(
+                            "Invalid kind multiplier `{}`, expected kind=positive number",
+                            kind_multiplier
+                        );
+                        exit(-2)
+                    });
+                let kind = node_kind_id(language, kind_name).unwrap_or_else(|err| {
+                    eprintln!("Invalid kind multipliers: {}", err);
+                    exit(-2)
+                });
+                (kind, multiplier)
+            })
+            .collect(),
+    };
+    cost_model.validate().unwrap_or_else(|err| {
+        match err {
+            Error::ZeroKindMultiplier(kind) => eprintln!(
+                "Invalid weights: the multiplier of node kind `{}` must be positive",
+                language.node_kind_for_id(kind).unwrap_or("?")
+            ),
+            _ => eprintln!("Invalid weights: {}", err),
+        }
+        exit(-2)
+    });
+    let alignment_algorithm = |name| -> Option<Box<dyn SubtreeAlignment>> {
+        Some(match name {
+            "minimal" => {
+                let default_budget = MinimalAlignment::default();
+                Box::new(MinimalAlignment {
+                    max_cells: number_arg("max-alignment-cells", default_budget.max_cells),
+                    max_steps: number_arg("max-alignment-steps", default_budget.max_steps),
+                })
+            }
+            "patience" => Box::new(PatienceAlignment),
+            "myers" => Box::new(MyersAlignment),
+            "histogram" => Box::new(HistogramAlignment),
+            _ => return None,
+        })
+    };
+    let default_algorithm = ["patience", "myers", "histogram"]
+        .into_iter()
+        .find(|name| cmd_args.is_present(name))
+        .unwrap_or("minimal");
+    let kind_alignments = cmd_args
+        .values_of("kind-algorithms")
+        .into_iter()
+        .flatten()
+        .map(|kind_algorithm| {
+            let (kind_name, algorithm) = kind_algorithm
+                .split_once('=')
+                .and_then(|(kind_name, name)| Some((kind_name, alignment_algorithm(name)?)))
+                .unwrap_or_else(|| {
+                    eprintln!(
+                        "Invalid kind algorithm `{}`, expected kind=minimal|patience|myers|histogram",
+                        kind_algorithm
+                    );
+                    exit(-2)
+                });
+            let kind = node_kind_id(language, kind_name).unwrap_or_else(|err| {
+                eprintln!("Invalid kind algorithms: {}", err);
+                exit(-2)
+            });
+            (kind, algorithm)
+        })
+        .collect();
+    let large_file = cmd_args.is_present("large-file");
+    let diff_options = DiffOptions {
+        elision_whitelist,
+        alignment: alignment_algorithm(default_algorithm).unwrap(),
+        kind_alignments,
+        commutative_kinds: commutative_kinds.clone(),
+        key_fields: key_fields.clone(),
+        name_fields,
+        cost_model,
+        verify_hashes: cmd_args.is_present("verify-hashes"),
+        share_subtrees: true,
     };
     let color_mode = if cmd_args.is_present("text-colored") {
         ColorMode::TextColored
@@ -124,38 +295,66 @@ fn main() {
     };
 
     let origin_src = read_file(origin_filename);
-    let origin_tree = parse_tree(
-        &origin_src,
-        origin_filename,
-        &mut parser,
-        ignore_whitespace,
-        extra_blocks,
-    );
-
     let first_modified_filename = cmd_args.value_of_os("first-modified-file").unwrap();
     let first_modified_src = read_file(first_modified_filename);
-    let first_modified_tree = parse_tree(
-        &first_modified_src,
-        first_modified_filename,
-        &mut parser,
-        ignore_whitespace,
-        extra_blocks,
-    );
+
+    let mut parse = |source, filename: &OsStr| {
+        let filename = filename.to_string_lossy();
+        match parse_source(
+            source,
+            &mut parser,
+            ignore_whitespace,
+            syntax_error_handling,
+        ) {
+            Ok((tree, syntax_errors)) => {
+                for syntax_error in syntax_errors {
+                    eprintln!("Warning: {}:{}", filename, syntax_error);
+                }
+                Ok(if extra_blocks {
+                    add_extra_blocks(&tree)
+                } else {
+                    tree
+                })
+            }
+            Err(Error::SyntaxErrors(syntax_errors)) => {
+                let mut message = format!(
+                    "Unable to parse {}: {}",
+                    filename,
+                    Error::SyntaxErrors(syntax_errors.clone())
+                );
+                for syntax_error in syntax_errors {
+                    message += &format!("\n{}:{}", filename, syntax_error);
+                }
+                Err(message)
+            }
+            Err(err) => Err(format!("Unable to parse {}: {}", filename, err)),
+        }
+    };
 
     match cmd_args.value_of_os("second-modified-file") {
         None => {
-            let diff_tree = compute_diff(
-                &origin_tree,
-                &first_modified_tree,
-                &elision_whitelist,
-                align_subtree_algorithm,
-            );
+            let exit_on_parse_error = |err| {
+                eprintln!("{}", err);
+                exit(-2)
+            };
+            let origin_tree =
+                parse(&origin_src, origin_filename).unwrap_or_else(exit_on_parse_error);
+            let first_modified_tree = parse(&first_modified_src, first_modified_filename)
+                .unwrap_or_else(exit_on_parse_error);
+            let diff = if large_file {
+                compute_chunked_diff(&origin_tree, &first_modified_tree, &diff_options)
+            } else {
+                compute_diff(&origin_tree, &first_modified_tree, &diff_options)
+            };
+            let (diff_tree, diagnostics) = diff.unwrap_or_else(|err| {
+                eprintln!("Unable to diff files: {}", err);
+                exit(-2)
+            });
+            warn_approximations(diagnostics, first_modified_filename);
             if cmd_args.is_present("standalone") {
-                let standalone_tree = remove_metavars(
-                    merge_diffs(&diff_tree, &diff_tree, MergeOptions::default()).unwrap(),
-                    &origin_tree,
-                )
-                .unwrap();
+                let standalone_tree = merge_diffs(&diff_tree, &diff_tree, MergeOptions::default())
+                    .and_then(|merged_diff| remove_metavars(merged_diff, &origin_tree))
+                    .unwrap_or_else(exit_on_merge_error);
                 print_tree(&standalone_tree, color_mode);
             } else {
                 print_tree(&diff_tree, color_mode);
@@ -163,82 +362,566 @@ fn main() {
         }
         Some(second_modified_filename) => {
             let second_modified_src = read_file(second_modified_filename);
-            let second_modified_tree = parse_tree(
-                &second_modified_src,
-                second_modified_filename,
-                &mut parser,
-                ignore_whitespace,
-                extra_blocks,
-            );
-
-            let first_diff = compute_diff(
-                &origin_tree,
-                &first_modified_tree,
-                &elision_whitelist,
-                align_subtree_algorithm,
-            );
-            let second_diff = compute_diff(
-                &origin_tree,
-                &second_modified_tree,
-                &elision_whitelist,
-                align_subtree_algorithm,
-            );
-
-            let mut merged_diff = merge_diffs(
-                &first_diff,
-                &second_diff,
-                MergeOptions {
-                    allow_nested_deletions: cmd_args.is_present("allow-nested-deletions"),
-                    ordered_insertions: cmd_args.is_present("ordered-insertions"),
+            let merge_options = MergeOptions {
+                allow_nested_deletions: cmd_args.is_present("allow-nested-deletions"),
+                delete_conflicts: match cmd_args.value_of("delete-conflicts") {
+                    Some("deletion") => Some(DeleteConflictResolution::DeletionWins),
+                    Some("modification") => Some(DeleteConflictResolution::ModificationWins),
+                    _ => None,
                 },
-            )
-            .unwrap();
-            canonicalize_metavars(&mut merged_diff);
-            let nb_conflicts = count_conflicts(&merged_diff);
+                ins_conflicts: match cmd_args.value_of("ins-conflicts") {
+                    Some("left") => Some(InsConflictResolution::Left),
+                    Some("right") => Some(InsConflictResolution::Right),
+                    Some("both") => Some(InsConflictResolution::Both),
+                    _ => None,
+                },
+                metavar_conflicts: match cmd_args.value_of("metavar-conflicts") {
+                    Some("left") => Some(MetavarConflictResolution::Left),
+                    Some("right") => Some(MetavarConflictResolution::Right),
+                    _ => None,
+                },
+                insert_order_conflicts: match cmd_args.value_of("insert-order-conflicts") {
+                    _ if cmd_args.is_present("ordered-insertions") => {
+                        Some(InsertOrderResolution::LeftFirst)
+                    }
+                    Some("left-first") => Some(InsertOrderResolution::LeftFirst),
+                    Some("right-first") => Some(InsertOrderResolution::RightFirst),
+                    Some("drop") => Some(InsertOrderResolution::Drop),
+                    _ => None,
+                },
+                commutative_kinds,
+                key_fields,
+                sort_commutative_insertions: cmd_args.is_present("sort-commutative"),
+                ..MergeOptions::default()
+            };
+
+            let conflict_refinement = match cmd_args.value_of("refine-conflicts") {
+                Some("tokens") => Some(ConflictRefinement::Tokens),
+                Some("lines") => Some(ConflictRefinement::Lines),
+                _ => None,
+            };
+
+            let parsed_trees = [
+                parse(&origin_src, origin_filename),
+                parse(&first_modified_src, first_modified_filename),
+                parse(&second_modified_src, second_modified_filename),
+            ];
+            if syntax_error_handling == SyntaxErrorHandling::Strict {
+                // Do not even fall back to a line-based merge
+                for parse_error in parsed_trees.iter().filter_map(|tree| tree.as_ref().err()) {
+                    eprintln!("{}", parse_error);
+                }
+                if parsed_trees.iter().any(|tree| tree.is_err()) {
+                    exit(-2)
+                }
+            }
+
+            let structural_merge = (|| {
+                let [origin_tree, first_modified_tree, second_modified_tree] = parsed_trees;
+                let (origin_tree, first_modified_tree, second_modified_tree) =
+                    (origin_tree?, first_modified_tree?, second_modified_tree?);
+                let (merged_diff, [first_diagnostics, second_diagnostics]) = if large_file {
+                    merge_chunked(
+                        &origin_tree,
+                        &first_modified_tree,
+                        &second_modified_tree,
+                        &diff_options,
+                        &merge_options,
+                    )
+                } else {
+                    let [(first_diff, first_diagnostics), (second_diff, second_diagnostics)] =
+                        compute_diff_pair(
+                            &origin_tree,
+                            &first_modified_tree,
+                            &second_modified_tree,
+                            &diff_options,
+                        )
+                        .map_err(|err| format!("Unable to diff files: {}", err))?;
+                    (
+                        merge_diffs(&first_diff, &second_diff, merge_options),
+                        [first_diagnostics, second_diagnostics],
+                    )
+                };
+                warn_approximations(first_diagnostics, first_modified_filename);
+                warn_approximations(second_diagnostics, second_modified_filename);
+                let merged_diff = merged_diff
+                    .and_then(|mut merged_diff| {
+                        canonicalize_metavars(&mut merged_diff);
+                        if let Some(refinement) = conflict_refinement {
+                            merged_diff = remove_metavars(merged_diff, &origin_tree)?;
+                            refine_conflicts(&mut merged_diff, refinement);
+                        }
+                        Ok(merged_diff)
+                    })
+                    .map_err(|err| format!("Unable to merge files: {}", err))?;
+                Ok((origin_tree, merged_diff))
+            })();
 
-            if !cmd_args.is_present("quiet") {
-                if nb_conflicts == 0 && cmd_args.is_present("merge-files") {
-                    let merged_tree = apply_patch(merged_diff, &origin_tree).unwrap();
-                    print_tree(&merged_tree, color_mode);
+            let conflict_marker_options = conflict_marker_options(&cmd_args);
+            let unresolved_side = unresolved_side(&cmd_args);
+            let sources = [&origin_src[..], &first_modified_src, &second_modified_src];
+            let validate = |merged_tree: &SynNode| {
+                validate_reparse(
+                    merged_tree,
+                    &mut parser,
+                    ignore_whitespace,
+                    syntax_error_handling,
+                )
+            };
+
+            if cmd_args.is_present("git-merge-driver") {
+                let merged_file = build_merged_file(structural_merge, sources, validate);
+                let mut nb_conflicts = 0;
+                write_file_atomically(first_modified_filename, |file| {
+                    nb_conflicts = write_merged_file(
+                        &merged_file,
+                        unresolved_side,
+                        &conflict_marker_options,
+                        file,
+                    )?;
+                    Ok(())
+                });
+                exit(if nb_conflicts == 0 { 0 } else { 1 })
+            }
+
+            let nb_conflicts = if cmd_args.is_present("conflict-report") {
+                let (origin_tree, merged_diff) =
+                    structural_merge.unwrap_or_else(exit_on_fallback_error);
+                let conflict_tree =
+                    remove_metavars(merged_diff, &origin_tree).unwrap_or_else(exit_on_merge_error);
+                let report = report_conflicts(
+                    &conflict_tree,
+                    &language,
+                    &origin_src,
+                    &first_modified_src,
+                    &second_modified_src,
+                );
+                report
+                    .write_json(std::io::stdout().lock())
+                    .unwrap_or_else(|err| {
+                        eprintln!("Unable to write output: {}", err);
+                        exit(-1)
+                    });
+                count_conflicts(&conflict_tree)
+            } else if cmd_args.is_present("quiet") {
+                build_merged_file(structural_merge, sources, validate).nb_conflicts()
+            } else {
+                let print_merged_file = cmd_args.is_present("merge-files")
+                    && (cmd_args.is_present("conflict-style")
+                        || cmd_args.is_present("unresolved-side")
+                        || match &structural_merge {
+                            Ok((_, merged_diff)) => count_conflicts(merged_diff) == 0,
+                            Err(_) => true,
+                        });
+                if print_merged_file {
+                    let merged_file = build_merged_file(structural_merge, sources, validate);
+                    // Refinement and line merges inside hunks decide how many conflicts are
+                    // actually written
+                    write_merged_file(
+                        &merged_file,
+                        unresolved_side,
+                        &conflict_marker_options,
+                        std::io::stdout().lock(),
+                    )
+                    .unwrap_or_else(|err| {
+                        eprintln!("Unable to write output: {}", err);
+                        exit(-1)
+                    })
                 } else {
+                    let (origin_tree, merged_diff) =
+                        structural_merge.unwrap_or_else(exit_on_fallback_error);
+                    let nb_conflicts = count_conflicts(&merged_diff);
                     let out_tree = if cmd_args.is_present("standalone") {
-                        remove_metavars(merged_diff, &origin_tree).unwrap()
+                        remove_metavars(merged_diff, &origin_tree)
+                            .unwrap_or_else(exit_on_merge_error)
                     } else {
                         merged_diff
                     };
-
                     print_tree(&out_tree, color_mode);
+                    nb_conflicts
                 }
-            }
+            };
 
             exit(min(nb_conflicts, 127).try_into().unwrap())
         }
     }
 }
 
-fn read_file(filename: &OsStr) -> Vec<u8> {
-    read(filename).unwrap_or_else(|err| {
-        eprintln!("Unable to read {}: {}", filename.to_string_lossy(), err);
-        exit(-1)
+/// Merged file content ready to be written
+enum MergedFile<'t> {
+    Clean(SynNode<'t>),
+    /// Merge without conflicts whose printed source does not parse back to the merged tree,
+    /// counted as a single conflict
+    Unverified(SynNode<'t>),
+    Conflicts(MergedSpineNode<'t>, usize),
+    LineBased(LineMerge<'t>),
+}
+
+impl<'t> MergedFile<'t> {
+    fn nb_conflicts(&self) -> usize {
+        match self {
+            MergedFile::Clean(_) => 0,
+            MergedFile::Unverified(_) => 1,
+            MergedFile::Conflicts(_, nb_conflicts) => *nb_conflicts,
+            MergedFile::LineBased(line_merge) => line_merge.count_conflicts(),
+        }
+    }
+}
+
+/// Print a warning for each approximation made while computing a difference
+fn warn_approximations(diagnostics: Vec<AlignmentDiagnostic>, modified_filename: &OsStr) {
+    for diagnostic in diagnostics {
+        eprintln!(
+            "Warning: {}: {}",
+            modified_filename.to_string_lossy(),
+            diagnostic
+        );
+    }
+}
+
+/// Finish the structural merge into a file, or fall back to a line-based merge if any of its
+/// steps failed. Merges without conflicts are checked by parsing their printed source again.
+fn build_merged_file<'t>(
+    structural_merge: Result<(SynNode<'t>, MergedSpineNode<'t>), String>,
+    [origin_src, first_modified_src, second_modified_src]: [&'t [u8]; 3],
+    validate: impl FnOnce(&SynNode) -> Result<(), Error>,
+) -> MergedFile<'t> {
+    structural_merge
+        .and_then(|(origin_tree, merged_diff)| {
+            let nb_conflicts = count_conflicts(&merged_diff);
+            if nb_conflicts == 0 {
+                apply_patch(merged_diff, &origin_tree).map(|merged_tree| {
+                    match validate(&merged_tree) {
+                        Ok(()) => MergedFile::Clean(merged_tree),
+                        Err(err) => {
+                            eprintln!("Invalid merged file: {}", err);
+                            MergedFile::Unverified(merged_tree)
+                        }
+                    }
+                })
+            } else {
+                remove_metavars(merged_diff, &origin_tree)
+                    .map(|conflict_tree| MergedFile::Conflicts(conflict_tree, nb_conflicts))
+            }
+            .map_err(|err| format!("Unable to merge files: {}", err))
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("{}, falling back to a line-based merge", err);
+            MergedFile::LineBased(merge_lines(
+                origin_src,
+                first_modified_src,
+                second_modified_src,
+            ))
+        })
+}
+
+/// Write a merged file and return the number of conflicts left inside the written text
+fn write_merged_file(
+    merged_file: &MergedFile,
+    unresolved_side: Option<ConflictSide>,
+    conflict_marker_options: &ConflictMarkerOptions,
+    mut output: impl Write,
+) -> std::io::Result<usize> {
+    match (merged_file, unresolved_side) {
+        (MergedFile::Clean(merged_tree), _) => {
+            merged_tree.write_with(&mut PlainTreeFormatter::new(output))?;
+            Ok(0)
+        }
+        (MergedFile::Unverified(merged_tree), _) => {
+            merged_tree.write_with(&mut PlainTreeFormatter::new(output))?;
+            Ok(1)
+        }
+        (MergedFile::Conflicts(conflict_tree, nb_conflicts), Some(side)) => {
+            write_with_chosen_side(conflict_tree, side, output)?;
+            Ok(*nb_conflicts)
+        }
+        (MergedFile::Conflicts(conflict_tree, _), None) => {
+            write_with_conflict_markers(conflict_tree, conflict_marker_options, output)
+        }
+        (MergedFile::LineBased(line_merge), Some(side)) => {
+            line_merge.write_with_chosen_side(side, &mut output)?;
+            Ok(line_merge.count_conflicts())
+        }
+        (MergedFile::LineBased(line_merge), None) => {
+            line_merge.write_with_conflict_markers(conflict_marker_options, output)
+        }
+    }
+}
+
+/// Options of the textual conflict markers given on the command line
+fn conflict_marker_options(cmd_args: &ArgMatches) -> ConflictMarkerOptions {
+    let label = |name| {
+        if cmd_args.is_present("git-merge-driver") {
+            String::from(match name {
+                "first-modified-file" => "ours",
+                "original-file" => "base",
+                _ => "theirs",
+            })
+        } else {
+            cmd_args.value_of_lossy(name).unwrap().into_owned()
+        }
+    };
+    ConflictMarkerOptions {
+        style: match cmd_args.value_of("conflict-style") {
+            Some("diff3") => ConflictStyle::Diff3,
+            _ => ConflictStyle::Merge,
+        },
+        marker_size: cmd_args
+            .value_of("marker-size")
+            .map(|size| size.parse().unwrap())
+            .unwrap_or(7),
+        left_label: label("first-modified-file"),
+        base_label: label("original-file"),
+        right_label: label("second-modified-file"),
+    }
+}
+
+/// Side taken for the content of conflicts given on the command line, if any
+fn unresolved_side(cmd_args: &ArgMatches) -> Option<ConflictSide> {
+    match cmd_args.value_of("unresolved-side") {
+        Some("left") => Some(ConflictSide::Left),
+        Some("base") => Some(ConflictSide::Base),
+        Some("right") => Some(ConflictSide::Right),
+        _ => None,
+    }
+}
+
+/// Merge the files line by line when no grammar can parse them, as `git merge-file` would, and
+/// exit with the number of conflicts. Exit with an error if the command is not a merge
+/// printing files.
+fn exit_with_line_merge(cmd_args: &ArgMatches) -> ! {
+    let second_modified_filename = match cmd_args.value_of_os("second-modified-file") {
+        Some(filename)
+            if ["git-merge-driver", "merge-files", "quiet"]
+                .iter()
+                .any(|mode| cmd_args.is_present(mode)) =>
+        {
+            filename
+        }
+        _ => exit(-2),
+    };
+    eprintln!("No grammar for the input files, falling back to a line-based merge");
+    let origin_src = read_file(cmd_args.value_of_os("original-file").unwrap());
+    let first_modified_filename = cmd_args.value_of_os("first-modified-file").unwrap();
+    let first_modified_src = read_file(first_modified_filename);
+    let second_modified_src = read_file(second_modified_filename);
+    let merged_file = MergedFile::LineBased(merge_lines(
+        &origin_src,
+        &first_modified_src,
+        &second_modified_src,
+    ));
+
+    let unresolved_side = unresolved_side(cmd_args);
+    let conflict_marker_options = conflict_marker_options(cmd_args);
+    if cmd_args.is_present("git-merge-driver") {
+        let mut nb_conflicts = 0;
+        write_file_atomically(first_modified_filename, |file| {
+            nb_conflicts = write_merged_file(
+                &merged_file,
+                unresolved_side,
+                &conflict_marker_options,
+                file,
+            )?;
+            Ok(())
+        });
+        exit(if nb_conflicts == 0 { 0 } else { 1 })
+    }
+    let nb_conflicts = if cmd_args.is_present("quiet") {
+        merged_file.nb_conflicts()
+    } else {
+        write_merged_file(
+            &merged_file,
+            unresolved_side,
+            &conflict_marker_options,
+            std::io::stdout().lock(),
+        )
+        .unwrap_or_else(|err| {
+            eprintln!("Unable to write output: {}", err);
+            exit(-1)
+        })
+    };
+    exit(min(nb_conflicts, 127).try_into().unwrap())
+}
+
+fn exit_on_merge_error<T>(err: Error) -> T {
+    eprintln!("Unable to merge files: {}", err);
+    exit(-3)
+}
+
+fn exit_on_fallback_error<T>(err: String) -> T {
+    eprintln!("{}", err);
+    exit(-3)
+}
+
+fn parse_kind_fields<'a>(
+    values: Option<impl Iterator<Item = &'a str>>,
+    language: Language,
+    what: &str,
+) -> HashMap<NodeKind, FieldId> {
+    values
+        .into_iter()
+        .flatten()
+        .map(|kind_field| {
+            let (kind_name, field_name) = kind_field.split_once('.').unwrap_or_else(|| {
+                eprintln!(
+                    "Invalid {} field `{}`, expected kind.field",
+                    what, kind_field
+                );
+                exit(-2)
+            });
+            node_kind_id(language, kind_name)
+                .and_then(|kind| Ok((kind, field_id(language, field_name)?)))
+                .unwrap_or_else(|err| {
+                    eprintln!("Invalid {} fields: {}", what, err);
+                    exit(-2)
+                })
+        })
+        .collect()
+}
+
+fn is_number(value: String) -> Result<(), String> {
+    value
+        .parse::<usize>()
+        .map(|_| ())
+        .map_err(|_| format!("`{}` is not a positive number", value))
+}
+
+/// Select the language of `path` among the embedded grammars and then the tree-sitter
+/// configuration. Returns None, after explaining why, if no grammar could be loaded.
+fn select_language(path: &Path, scope: Option<&str>) -> Option<Language> {
+    builtin_language(path, scope).or_else(|| {
+        load_configured_language(path, scope)
+            .map_err(|err| {
+                eprintln!("{}", err);
+                let builtin_scopes: Vec<_> = builtin_language_scopes().collect();
+                if builtin_scopes.is_empty() {
+                    eprintln!(
+                        "No grammar is embedded in this build, enable them with cargo features"
+                    );
+                } else {
+                    eprintln!("Embedded grammars: {}", builtin_scopes.join(", "));
+                }
+            })
+            .ok()
     })
 }
 
-fn parse_tree<'t>(
-    source: &'t [u8],
-    filename: &OsStr,
-    parser: &mut Parser,
-    ignore_whitespace: bool,
-    extra_blocks: bool,
-) -> SynNode<'t> {
-    let origin_tree = parse_source(source, parser, ignore_whitespace).unwrap_or_else(|| {
-        eprintln!("Unable to parse {}", filename.to_string_lossy());
-        exit(-2)
+fn load_configured_language(path: &Path, scope: Option<&str>) -> Result<Language, String> {
+    let config =
+        Config::load().map_err(|err| format!("Error loading tree-sitter config: {}", err))?;
+    let parser_config = config
+        .get()
+        .map_err(|err| format!("Error loading tree-sitter config: {}", err))?;
+    let mut lang_loader =
+        Loader::new().map_err(|err| format!("Error initializing parser loader: {}", err))?;
+    lang_loader
+        .find_all_languages(&parser_config)
+        .map_err(|err| format!("Error loading parser list: {}", err))?;
+    lang_loader
+        .select_language(path, Path::new(""), scope)
+        .map_err(|err| format!("Error loading parser: {}", err))
+}
+
+/// Load a language from a compiled parser shared library exporting `tree_sitter_<name>`
+fn load_grammar_library(library_path: &Path, name: &str) -> Result<Language, String> {
+    let library = unsafe { Library::new(library_path) }
+        .map_err(|err| format!("Unable to open {}: {}", library_path.display(), err))?;
+    let language_fn_name = format!("tree_sitter_{}", name.replace('-', "_"));
+    let language = unsafe {
+        let language_fn: Symbol<unsafe extern "C" fn() -> Language> = library
+            .get(language_fn_name.as_bytes())
+            .map_err(|err| format!("Unable to find {}: {}", language_fn_name, err))?;
+        language_fn()
+    };
+    // The language refers to static data of the library, it must stay loaded
+    std::mem::forget(library);
+    Ok(language)
+}
+
+/// Load a language from a single grammar directory, compiling it if needed, without looking
+/// at the parser directories of the tree-sitter configuration
+fn load_grammar_dir(
+    grammar_dir: &Path,
+    path: &Path,
+    scope: Option<&str>,
+) -> Result<Language, String> {
+    let mut lang_loader = Loader::new().map_err(|err| err.to_string())?;
+    lang_loader
+        .find_language_configurations_at_path(grammar_dir)
+        .map_err(|err| format!("Unable to read {}: {}", grammar_dir.display(), err))?;
+    // Files not matching the file types of the grammar fall back to the grammar directory
+    lang_loader
+        .select_language(path, grammar_dir, scope)
+        .map_err(|err| err.to_string())
+}
+
+fn install_git_merge_driver(global: bool) {
+    let exe_path = std::env::current_exe().unwrap_or_else(|err| {
+        eprintln!("Unable to find the syndiff executable path: {}", err);
+        exit(-1)
     });
-    if extra_blocks {
-        add_extra_blocks(&origin_tree)
-    } else {
-        origin_tree
+    let driver_cmd = format!(
+        "'{}' --git-merge-driver --marker-size %L --path %P %O %A %B",
+        exe_path.to_string_lossy().replace('\'', "'\\''")
+    );
+    for (key, value) in [
+        ("merge.syndiff.name", "syndiff syntactic merge"),
+        ("merge.syndiff.driver", &driver_cmd),
+    ] {
+        let mut git_cmd = Command::new("git");
+        git_cmd.arg("config");
+        if global {
+            git_cmd.arg("--global");
+        }
+        let status = git_cmd.arg(key).arg(value).status().unwrap_or_else(|err| {
+            eprintln!("Unable to run git config: {}", err);
+            exit(-1)
+        });
+        if !status.success() {
+            eprintln!("git config {} failed", key);
+            exit(-1)
+        }
     }
+    println!(
+        "Registered the syndiff merge driver. Enable it with `merge=syndiff` attributes in \
+        .gitattributes, for example:\n*.rs merge=syndiff"
+    );
+}
+
+fn write_file_atomically(
+    filename: &OsStr,
+    write_fn: impl FnOnce(&mut File) -> std::io::Result<()>,
+) {
+    // Write in a temporary file of the same directory and rename it over the destination, so
+    // that the destination is never left half written.
+    let path = Path::new(filename);
+    let mut tmp_filename = OsString::from(".");
+    tmp_filename.push(path.file_name().unwrap_or(filename));
+    tmp_filename.push(".syndiff.tmp");
+    let tmp_path = path.with_file_name(tmp_filename);
+
+    let write_result = File::create(&tmp_path).and_then(|mut file| {
+        write_fn(&mut file)?;
+        file.flush()?;
+        if let Ok(metadata) = path.metadata() {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()
+    });
+    write_result
+        .and_then(|_| rename(&tmp_path, path))
+        .unwrap_or_else(|err| {
+            let _ = std::fs::remove_file(&tmp_path);
+            eprintln!("Unable to write {}: {}", path.display(), err);
+            exit(-1)
+        })
+}
+
+fn read_file(filename: &OsStr) -> Vec<u8> {
+    read(filename).unwrap_or_else(|err| {
+        eprintln!("Unable to read {}: {}", filename.to_string_lossy(), err);
+        exit(-1)
+    })
 }
 
 enum ColorMode {
@@ -1,6 +1,7 @@
 use super::colors::{Colored, ColoredChangeNode, ColoredSpineNode, ColoredSpineSeqNode};
 use crate::generic_tree::{FieldId, Subtree, Tree};
-use crate::Metavariable;
+use crate::{Error, Metavariable};
+use recursive::recursive;
 
 type InsNode<'t> = ColoredChangeNode<'t>;
 type DelNode<'t> = ColoredChangeNode<'t>;
@@ -32,6 +33,7 @@ pub enum AlignedSpineSeqNode<'t> {
     InsertOrderConflict(Vec<Subtree<InsNode<'t>>>, Vec<Subtree<InsNode<'t>>>),
 }
 
+#[recursive]
 fn split_spine<'t>(
     tree: ColoredSpineNode<'t>,
     next_metavar: &mut usize,
@@ -90,12 +92,13 @@ fn split_spine_subtrees<'t>(
     (del_seq, ins_seq)
 }
 
+#[recursive]
 fn merge_spines<'t>(
     left: ColoredSpineNode<'t>,
     right: ColoredSpineNode<'t>,
     next_metavar: &mut usize,
-) -> Option<AlignedSpineNode<'t>> {
-    Some(match (left, right) {
+) -> Result<AlignedSpineNode<'t>, Error> {
+    Ok(match (left, right) {
         (ColoredSpineNode::Spine(left_spine), ColoredSpineNode::Spine(right_spine)) => {
             AlignedSpineNode::Spine(Tree::merge_into(left_spine, right_spine, |l, r| {
                 merge_spine_subtrees(l, r, next_metavar)
@@ -135,7 +138,7 @@ enum FlatDelSubtree<'t> {
 
 fn flatten_del(seq: Vec<ColoredSpineSeqNode>) -> impl Iterator<Item = FlatDelSubtree> {
     seq.into_iter()
-        .map::<Box<dyn Iterator<Item = FlatDelSubtree>>, _>(|subtree| match subtree {
+        .flat_map::<Box<dyn Iterator<Item = FlatDelSubtree>>, _>(|subtree| match subtree {
             ColoredSpineSeqNode::Zipped(spine) => {
                 Box::new(std::iter::once(FlatDelSubtree::Zipped(spine)))
             }
@@ -146,14 +149,13 @@ fn flatten_del(seq: Vec<ColoredSpineSeqNode>) -> impl Iterator<Item = FlatDelSub
                 Box::new(std::iter::once(FlatDelSubtree::Inserted(ins_list)))
             }
         })
-        .flatten()
 }
 
 fn merge_spine_subtrees<'t>(
     left: Vec<ColoredSpineSeqNode<'t>>,
     right: Vec<ColoredSpineSeqNode<'t>>,
     next_metavar: &mut usize,
-) -> Option<Vec<AlignedSpineSeqNode<'t>>> {
+) -> Result<Vec<AlignedSpineSeqNode<'t>>, Error> {
     let mut left_iter = flatten_del(left).peekable();
     let mut right_iter = flatten_del(right).peekable();
     let mut merged_subtrees = Vec::new();
@@ -180,12 +182,14 @@ fn merge_spine_subtrees<'t>(
                 AlignedSpineSeqNode::Inserted(into_ins_list(right_iter.next().unwrap()))
             }
             _ => {
-                // No insertion either in left or right, consume both or return None if not
-                // possible
-                match (left_iter.next()?, right_iter.next()?) {
+                // No insertion either in left or right, consume both or fail if not possible
+                match (
+                    left_iter.next().ok_or(Error::FieldMismatch)?,
+                    right_iter.next().ok_or(Error::FieldMismatch)?,
+                ) {
                     (FlatDelSubtree::Zipped(left_spine), FlatDelSubtree::Zipped(right_spine)) => {
                         if left_spine.field != right_spine.field {
-                            return None;
+                            return Err(Error::FieldMismatch);
                         }
                         AlignedSpineSeqNode::Zipped(Subtree {
                             field: left_spine.field,
@@ -194,7 +198,7 @@ fn merge_spine_subtrees<'t>(
                     }
                     (FlatDelSubtree::Deleted(left_del), FlatDelSubtree::Deleted(right_del)) => {
                         if left_del.field != right_del.field {
-                            return None;
+                            return Err(Error::FieldMismatch);
                         }
                         AlignedSpineSeqNode::BothDeleted(
                             left_del.field,
@@ -205,7 +209,7 @@ fn merge_spine_subtrees<'t>(
                     (FlatDelSubtree::Deleted(del), FlatDelSubtree::Zipped(spine))
                     | (FlatDelSubtree::Zipped(spine), FlatDelSubtree::Deleted(del)) => {
                         if del.field != spine.field {
-                            return None;
+                            return Err(Error::FieldMismatch);
                         }
                         let (spine_del, spine_ins) = split_spine(spine.node, next_metavar);
                         AlignedSpineSeqNode::DeleteConflict(
@@ -219,9 +223,10 @@ fn merge_spine_subtrees<'t>(
             }
         })
     }
-    Some(merged_subtrees)
+    Ok(merged_subtrees)
 }
 
+#[recursive]
 fn align_spine_with_unchanged<'t>(
     tree: ColoredSpineNode<'t>,
     next_metavar: &mut usize,
@@ -265,7 +270,7 @@ pub fn align_spines<'t>(
     left: ColoredSpineNode<'t>,
     right: ColoredSpineNode<'t>,
     mut next_metavar: usize,
-) -> Option<(AlignedSpineNode<'t>, usize)> {
+) -> Result<(AlignedSpineNode<'t>, usize), Error> {
     let merged = merge_spines(left, right, &mut next_metavar)?;
-    Some((merged, next_metavar))
+    Ok((merged, next_metavar))
 }
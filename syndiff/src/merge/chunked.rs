@@ -0,0 +1,107 @@
+use super::metavar_renamer::rename_merged_metavars;
+use super::{merge_diffs, MergeOptions, MergedSpineNode};
+use crate::diff::{compute_diff_pair, TopLevelChunk, TopLevelChunks};
+use crate::generic_tree::Tree;
+use crate::parallel::{join, map_slice};
+use crate::syn_tree::SynNode;
+use crate::{AlignmentDiagnostic, DiffOptions, DiffSpineNode, Error};
+
+/// Large-file mode of a three-way merge: the top-level children identical in the three trees
+/// and unique in each of them are kept, and the diffs of each modified tree are only computed
+/// and merged on the chunks of children between them, in parallel with the `parallel` feature.
+/// Moves from one chunk to another are not detected.
+///
+/// Also returns the alignment diagnostics of the diffs of both modified trees. The merge fails
+/// without diagnostics if the cost model of `diff_options` is inadmissible.
+pub fn merge_chunked<'t>(
+    origin_tree: &SynNode<'t>,
+    first_modified_tree: &SynNode<'t>,
+    second_modified_tree: &SynNode<'t>,
+    diff_options: &DiffOptions,
+    merge_options: &MergeOptions,
+) -> (
+    Result<MergedSpineNode<'t>, Error>,
+    [Vec<AlignmentDiagnostic>; 2],
+) {
+    if let Err(err) = diff_options.cost_model.validate() {
+        return (Err(err), [Vec::new(), Vec::new()]);
+    }
+    let trees = [origin_tree, first_modified_tree, second_modified_tree];
+    let chunks = match TopLevelChunks::split(&trees, diff_options) {
+        Some(chunks) => chunks,
+        None => {
+            let diff_pair = compute_diff_pair(
+                origin_tree,
+                first_modified_tree,
+                second_modified_tree,
+                diff_options,
+            );
+            let [(first_diff, first_diagnostics), (second_diff, second_diagnostics)] =
+                match diff_pair {
+                    Ok(diff_pair) => diff_pair,
+                    Err(err) => return (Err(err), [Vec::new(), Vec::new()]),
+                };
+            return (
+                merge_diffs(&first_diff, &second_diff, merge_options.clone()),
+                [first_diagnostics, second_diagnostics],
+            );
+        }
+    };
+
+    let chunk_merges = map_slice(&chunks.chunks, |chunk| {
+        let (first_diff, second_diff) = match chunk {
+            TopLevelChunk::Unchanged(range) => (
+                (chunks.unchanged_seq(range.clone()), Vec::new()),
+                (chunks.unchanged_seq(range.clone()), Vec::new()),
+            ),
+            TopLevelChunk::Changed(ranges) => join(
+                || chunks.diff_chunk(ranges, 1, diff_options),
+                || chunks.diff_chunk(ranges, 2, diff_options),
+            ),
+        };
+        let spine = |seq: Vec<_>| DiffSpineNode::Spine(Tree::Node(chunks.kind(), seq.into()));
+        let merged = merge_diffs(
+            &spine(first_diff.0),
+            &spine(second_diff.0),
+            merge_options.clone(),
+        );
+        (merged, [first_diff.1, second_diff.1])
+    });
+
+    // Number the metavariables of each chunk after those of the previous chunks
+    let mut merged_seq = Vec::new();
+    let mut merge_error = None;
+    let mut diagnostics = [Vec::new(), Vec::new()];
+    let mut next_metavar = 0;
+    for (merged, chunk_diagnostics) in chunk_merges {
+        for (all_diagnostics, chunk_diagnostics) in diagnostics.iter_mut().zip(chunk_diagnostics) {
+            for diagnostic in chunk_diagnostics {
+                if !all_diagnostics.contains(&diagnostic) {
+                    all_diagnostics.push(diagnostic)
+                }
+            }
+        }
+        match merged {
+            Ok(mut merged) if merge_error.is_none() => {
+                next_metavar = rename_merged_metavars(&mut merged, next_metavar);
+                match merged {
+                    MergedSpineNode::Spine(Tree::Node(_, seq)) => merged_seq.extend(seq),
+                    // Merging two zipped roots always gives a zipped root
+                    _ => merge_error = Some(Error::SpineKindMismatch),
+                }
+            }
+            Ok(_) => (),
+            Err(err) => merge_error = merge_error.or(Some(err)),
+        }
+    }
+    match merge_error {
+        Some(err) => (Err(err), diagnostics),
+        None => (
+            Ok(MergedSpineNode::Spine(Tree::Node(
+                chunks.kind(),
+                merged_seq.into(),
+            ))),
+            diagnostics,
+        ),
+    }
+}
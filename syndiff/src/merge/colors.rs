@@ -1,7 +1,8 @@
 use crate::diff::{ChangeNode, DiffSpineNode, DiffSpineSeqNode};
 use crate::generic_tree::{Subtree, Tree};
 use crate::tree_formatter::{TreeFormattable, TreeFormatter};
-use crate::Metavariable;
+use crate::{Error, Metavariable};
+use recursive::recursive;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Color {
@@ -67,9 +68,9 @@ impl<T> Colored<T> {
     pub fn merge<L, R>(
         left: Colored<L>,
         right: Colored<R>,
-        merge_fn: impl FnOnce(L, R) -> Option<T>,
-    ) -> Option<Colored<T>> {
-        Some(Colored {
+        merge_fn: impl FnOnce(L, R) -> Result<T, Error>,
+    ) -> Result<Colored<T>, Error> {
+        Ok(Colored {
             data: merge_fn(left.data, right.data)?,
             color: left.color | right.color,
         })
@@ -100,6 +101,7 @@ pub enum ColoredSpineSeqNode<'t> {
 }
 
 impl<'t> ColoredChangeNode<'t> {
+    #[recursive]
     fn with_color(tree: &ChangeNode<'t>, color: Color) -> Self {
         match tree {
             ChangeNode::InPlace(node) => ColoredChangeNode::InPlace(Colored {
@@ -112,6 +114,7 @@ impl<'t> ColoredChangeNode<'t> {
 }
 
 impl<'t> ColoredSpineNode<'t> {
+    #[recursive]
     pub fn with_color(tree: &DiffSpineNode<'t>, color: Color) -> Self {
         match tree {
             DiffSpineNode::Spine(node) => ColoredSpineNode::Spine(
@@ -1,5 +1,16 @@
-use super::{DelNode, MergedInsNode, MergedSpineNode, MergedSpineSeqNode};
+use super::conflict_markers::{render_side_text, ConflictSide, MetavarConflicts, RegionTexts};
+use super::{
+    DelNode, InsNode, MergedInsNode, MergedSpineNode, MergedSpineSeqNode, MetavarInsReplacement,
+};
+use crate::generic_tree::{FieldId, NodeKind, Tree};
+use crate::tree_formatter::TreeFormattable;
+use memchr::memmem;
+use recursive::recursive;
+use std::io::Write;
+use std::ops::{Range, RangeInclusive};
+use tree_sitter::Language;
 
+#[recursive]
 fn count_conflicts_in_del_node(node: &DelNode, counter: &mut usize) {
     match node {
         DelNode::InPlace(del) => del
@@ -13,6 +24,7 @@ fn count_conflicts_in_del_node(node: &DelNode, counter: &mut usize) {
     }
 }
 
+#[recursive]
 fn count_conflicts_in_merged_ins_node(node: &MergedInsNode, counter: &mut usize) {
     match node {
         MergedInsNode::InPlace(ins) => {
@@ -26,6 +38,7 @@ fn count_conflicts_in_merged_ins_node(node: &MergedInsNode, counter: &mut usize)
     }
 }
 
+#[recursive]
 fn count_conflicts_in_spine_node(node: &MergedSpineNode, counter: &mut usize) {
     match node {
         MergedSpineNode::Spine(spine) => {
@@ -63,3 +76,537 @@ pub fn count_conflicts(tree: &MergedSpineNode) -> usize {
     count_conflicts_in_spine_node(tree, &mut counter);
     counter
 }
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConflictKind {
+    /// Subtree replaced differently by both sides (`MergedInsNode::Conflict`)
+    Ins,
+    /// Subtree deleted by one side and modified by the other
+    Delete,
+    /// Concurrent insertions at the same place
+    InsertOrder,
+    /// Moved subtree with incompatible modifications
+    Metavariable,
+}
+
+impl ConflictKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConflictKind::Ins => "conflict",
+            ConflictKind::Delete => "delete_conflict",
+            ConflictKind::InsertOrder => "insert_order_conflict",
+            ConflictKind::Metavariable => "mv_conflict",
+        }
+    }
+}
+
+/// Range of a conflict inside one of the merged files. Lines are numbered from 1 and the line
+/// range is inclusive.
+///
+/// Merged trees do not keep the positions of their tokens in the source files, so ranges are
+/// found by searching the text of the conflict hunk in each file and may be approximate, see
+/// `ConflictReport::write_json`.
+#[derive(Clone, Debug)]
+pub struct SourceRange {
+    pub bytes: Range<usize>,
+    pub lines: RangeInclusive<usize>,
+}
+
+/// Closest ancestor of a conflict that has a `name` field, like a function or a type definition
+#[derive(Clone, Debug)]
+pub struct NamedAncestor {
+    pub kind: &'static str,
+    pub name: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConflictInfo {
+    pub kind: ConflictKind,
+    /// Kinds of the root nodes of the subtrees involved in the conflict
+    pub node_kinds: Vec<&'static str>,
+    pub ancestor: Option<NamedAncestor>,
+    /// Range in the original file, empty if the conflict does not remove anything from it
+    pub orig_range: SourceRange,
+    /// Range in the left file, `None` if the left file has no content in the conflict or does
+    /// not contain the left side of its hunk before the next change, for instance when it
+    /// combines moves of both sides
+    pub left_range: Option<SourceRange>,
+    /// Range in the right file, `None` if the right file has no content in the conflict or
+    /// does not contain the right side of its hunk before the next change
+    pub right_range: Option<SourceRange>,
+}
+
+pub struct ConflictReport {
+    pub conflicts: Vec<ConflictInfo>,
+}
+
+struct ConflictReporter<'a, 'm, 't> {
+    language: &'a Language,
+    name_field: Option<FieldId>,
+    sources: [&'a [u8]; 3],
+    line_starts: [Vec<usize>; 3],
+    metavar_conflicts: MetavarConflicts<'m, 't>,
+    ancestors: Vec<NamedAncestor>,
+    /// Position reached in each of the three files
+    positions: [usize; 3],
+    /// Last visited change, located once the spine text following it is known
+    pending_change: Option<PendingChange>,
+    conflicts: Vec<ConflictInfo>,
+}
+
+/// Change whose conflicts are written as a single conflict hunk, with the text of each of its
+/// versions and where this text is expected in each file
+struct PendingChange {
+    texts: RegionTexts,
+    starts: [usize; 3],
+    first_conflict: usize,
+}
+
+fn del_root_kind<'t>(node: &DelNode<'t>) -> Option<NodeKind> {
+    match node {
+        DelNode::InPlace(del) => match del.data {
+            Tree::Node(kind, _) => Some(kind),
+            Tree::Leaf(_) => None,
+        },
+        DelNode::Elided(_) => None,
+        DelNode::MetavariableConflict(_, del, _) => del_root_kind(del),
+    }
+}
+
+impl<'a, 'm, 't> ConflictReporter<'a, 'm, 't> {
+    fn source_range(&self, file: usize, bytes: Range<usize>) -> SourceRange {
+        let line_of = |pos| self.line_starts[file].partition_point(|start| *start <= pos);
+        let start_line = line_of(bytes.start);
+        let end_line = if bytes.end > bytes.start {
+            line_of(bytes.end - 1)
+        } else {
+            start_line
+        };
+        SourceRange {
+            bytes,
+            lines: start_line..=end_line,
+        }
+    }
+
+    fn root_kind(&self, node: &InsNode) -> Option<NodeKind> {
+        match node {
+            InsNode::InPlace(ins) => match ins.data {
+                Tree::Node(kind, _) => Some(kind),
+                Tree::Leaf(_) => None,
+            },
+            // Unresolved metavariables stand for their original content
+            InsNode::Elided(mv) => self
+                .metavar_conflicts
+                .contents
+                .get(&mv.data)
+                .and_then(|del| del_root_kind(del)),
+            InsNode::Inlined(repl) => self.root_kind(&repl.data),
+        }
+    }
+
+    /// Advance in the three files over a token of the spine, which is present in all of them
+    fn advance_spine_token(&mut self, tok: &[u8]) {
+        for pos in &mut self.positions {
+            *pos += tok.len();
+        }
+    }
+
+    /// Locate the text of a version of a change in its file, expected at `pos`. When the merge
+    /// combined moves of both sides, or when tokens were compared ignoring whitespace, the text
+    /// of a side can differ from its file there, its first occurrence before `bound`, where the
+    /// next change is expected, is then taken.
+    fn locate(&self, file: usize, text: &[u8], pos: usize, bound: usize) -> Option<Range<usize>> {
+        let source = self.sources[file];
+        if source[pos.min(source.len())..].starts_with(text) {
+            return Some(pos..pos + text.len());
+        }
+        if text.is_empty() || pos >= source.len() {
+            return None;
+        }
+        let window = &source[pos..bound.max(pos + text.len()).min(source.len())];
+        memmem::find(window, text).map(|start| pos + start..pos + start + text.len())
+    }
+
+    /// Locate the pending change, now that the position of the following change or of the end
+    /// of the files is known, and set the ranges of its conflicts
+    fn locate_pending_change(&mut self) {
+        let pending = match self.pending_change.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let mut ranges: [Option<Range<usize>>; 3] = Default::default();
+        for (file, text) in pending.texts.iter().enumerate() {
+            let start = pending.starts[file];
+            let expected_end = (start + text.len()).min(self.sources[file].len());
+            ranges[file] = self.locate(file, text, start, self.positions[file]);
+            // The spine tokens visited since the change follow its actual text
+            if let Some(range) = &ranges[file] {
+                self.positions[file] = self.positions[file] - expected_end + range.end;
+            }
+        }
+
+        let [orig_bytes, left_bytes, right_bytes] = ranges;
+        let orig_end = (pending.starts[0] + pending.texts[0].len()).min(self.sources[0].len());
+        let orig_range = self.source_range(0, orig_bytes.unwrap_or(orig_end..orig_end));
+        let side_range = |file, bytes: Option<Range<usize>>| {
+            bytes
+                .filter(|bytes| !bytes.is_empty())
+                .map(|bytes| self.source_range(file, bytes))
+        };
+        let (left_range, right_range) = (side_range(1, left_bytes), side_range(2, right_bytes));
+        for conflict in &mut self.conflicts[pending.first_conflict..] {
+            conflict.orig_range = orig_range.clone();
+            conflict.left_range = left_range.clone();
+            conflict.right_range = right_range.clone();
+        }
+    }
+
+    /// Visit a change, reporting the conflicts inside it with `report_inside`, and advance in
+    /// the three files over the text of each version of the change
+    fn visit_change(
+        &mut self,
+        change: &impl TreeFormattable,
+        report_inside: impl FnOnce(&mut Self),
+    ) {
+        self.locate_pending_change();
+        let texts = self
+            .metavar_conflicts
+            .region_texts(change)
+            .unwrap_or_default();
+        let starts = self.positions;
+        for (file, text) in texts.iter().enumerate() {
+            self.positions[file] = (starts[file] + text.len()).min(self.sources[file].len());
+        }
+        self.pending_change = Some(PendingChange {
+            texts,
+            starts,
+            first_conflict: self.conflicts.len(),
+        });
+        report_inside(self);
+    }
+
+    fn push_named_ancestor<'c, T: TreeFormattable + 'c>(
+        &mut self,
+        kind: NodeKind,
+        mut children: impl Iterator<Item = (Option<FieldId>, &'c T)>,
+        side: ConflictSide,
+    ) -> bool {
+        let name_child = match self.name_field {
+            Some(name_field) => children.find(|(field, _)| *field == Some(name_field)),
+            None => None,
+        };
+        match (name_child, self.language.node_kind_for_id(kind)) {
+            (Some((_, name_child)), Some(kind)) => {
+                let name = render_side_text(name_child, side).unwrap_or_default();
+                self.ancestors.push(NamedAncestor {
+                    kind,
+                    name: String::from_utf8_lossy(&name).trim().to_string(),
+                });
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn push_conflict(
+        &mut self,
+        kind: ConflictKind,
+        node_kinds: impl IntoIterator<Item = Option<NodeKind>>,
+    ) {
+        let mut kind_names = Vec::new();
+        for kind in node_kinds.into_iter().flatten() {
+            if let Some(kind_name) = self.language.node_kind_for_id(kind) {
+                if !kind_names.contains(&kind_name) {
+                    kind_names.push(kind_name)
+                }
+            }
+        }
+
+        // The ranges are those of the enclosing change, set once it is located
+        let orig_pos = self.positions[0];
+        self.conflicts.push(ConflictInfo {
+            kind,
+            node_kinds: kind_names,
+            ancestor: self.ancestors.last().cloned(),
+            orig_range: self.source_range(0, orig_pos..orig_pos),
+            left_range: None,
+            right_range: None,
+        })
+    }
+
+    #[recursive]
+    fn report_in_del_node(&mut self, node: &DelNode) {
+        match node {
+            DelNode::InPlace(del) => {
+                let named = match &del.data {
+                    Tree::Node(kind, children) => self.push_named_ancestor(
+                        *kind,
+                        children.iter().map(|ch| (ch.field, &ch.node)),
+                        ConflictSide::Base,
+                    ),
+                    Tree::Leaf(_) => false,
+                };
+                del.data.visit(|ch| self.report_in_del_node(&ch.node));
+                if named {
+                    self.ancestors.pop();
+                }
+            }
+            DelNode::Elided(_) => (),
+            DelNode::MetavariableConflict(_, del, repl) => {
+                let repl_kind = match repl {
+                    MetavarInsReplacement::InferFromDel => None,
+                    MetavarInsReplacement::Inlined(ins) => self.root_kind(ins),
+                };
+                self.push_conflict(ConflictKind::Metavariable, [del_root_kind(del), repl_kind]);
+                self.report_in_del_node(del);
+            }
+        }
+    }
+
+    #[recursive]
+    fn report_in_merged_ins_node(&mut self, node: &MergedInsNode) {
+        match node {
+            MergedInsNode::InPlace(ins) => {
+                let named = match ins {
+                    Tree::Node(kind, children) => self.push_named_ancestor(
+                        *kind,
+                        children.iter().map(|ch| (ch.field, &ch.node)),
+                        ConflictSide::Left,
+                    ),
+                    Tree::Leaf(_) => false,
+                };
+                ins.visit(|ch| self.report_in_merged_ins_node(&ch.node));
+                if named {
+                    self.ancestors.pop();
+                }
+            }
+            MergedInsNode::Elided(_) | MergedInsNode::SingleIns(_) => (),
+            MergedInsNode::Conflict(left_ins, right_ins) => self.push_conflict(
+                ConflictKind::Ins,
+                [self.root_kind(left_ins), self.root_kind(right_ins)],
+            ),
+        }
+    }
+
+    #[recursive]
+    fn report_in_spine_node(&mut self, node: &MergedSpineNode) {
+        match node {
+            MergedSpineNode::Spine(spine) => match spine {
+                Tree::Node(kind, children) => {
+                    let named = self.push_named_ancestor(
+                        *kind,
+                        children.iter().filter_map(|ch| match ch {
+                            MergedSpineSeqNode::Zipped(sub) => Some((sub.field, &sub.node)),
+                            _ => None,
+                        }),
+                        ConflictSide::Left,
+                    );
+                    for ch in children {
+                        self.report_in_spine_seq_node(ch)
+                    }
+                    if named {
+                        self.ancestors.pop();
+                    }
+                }
+                Tree::Leaf(tok) => self.advance_spine_token(tok.bytes()),
+            },
+            MergedSpineNode::Unchanged => (),
+            MergedSpineNode::Changed(del, ins) => self.visit_change(node, |reporter| {
+                reporter.report_in_del_node(del);
+                reporter.report_in_merged_ins_node(ins);
+            }),
+        }
+    }
+
+    fn report_in_spine_seq_node(&mut self, node: &MergedSpineSeqNode) {
+        match node {
+            MergedSpineSeqNode::Zipped(spine) => self.report_in_spine_node(&spine.node),
+            MergedSpineSeqNode::Deleted(del_list) => self.visit_change(node, |reporter| {
+                for del in del_list {
+                    reporter.report_in_del_node(&del.node);
+                }
+            }),
+            MergedSpineSeqNode::DeleteConflict(_, del, ins) => {
+                self.visit_change(node, |reporter| {
+                    reporter.push_conflict(
+                        ConflictKind::Delete,
+                        [del_root_kind(del), reporter.root_kind(ins)],
+                    );
+                    reporter.report_in_del_node(del);
+                })
+            }
+            MergedSpineSeqNode::Inserted(_) => self.visit_change(node, |_| ()),
+            MergedSpineSeqNode::InsertOrderConflict(left_ins, right_ins) => {
+                self.visit_change(node, |reporter| {
+                    let node_kinds: Vec<_> = left_ins
+                        .iter()
+                        .chain(right_ins)
+                        .map(|ins| reporter.root_kind(&ins.node))
+                        .collect();
+                    reporter.push_conflict(ConflictKind::InsertOrder, node_kinds)
+                })
+            }
+        }
+    }
+}
+
+/// List the conflicts of a merged tree, with their location in the original file and in both
+/// modified files. The location of a conflict is the one of the conflict hunk written for it,
+/// so conflicts nested in the same change share the same location.
+///
+/// The tree must have been made standalone by `remove_metavars` beforehand.
+pub fn report_conflicts(
+    tree: &MergedSpineNode,
+    language: &Language,
+    orig_source: &[u8],
+    left_source: &[u8],
+    right_source: &[u8],
+) -> ConflictReport {
+    let sources = [orig_source, left_source, right_source];
+    let line_starts = sources.map(|source| {
+        std::iter::once(0)
+            .chain(
+                source
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, c)| **c == b'\n')
+                    .map(|(pos, _)| pos + 1),
+            )
+            .collect()
+    });
+    let mut reporter = ConflictReporter {
+        language,
+        name_field: language.field_id_for_name("name"),
+        sources,
+        line_starts,
+        metavar_conflicts: MetavarConflicts::collect(tree).unwrap_or_default(),
+        ancestors: Vec::new(),
+        positions: [0; 3],
+        pending_change: None,
+        conflicts: Vec::new(),
+    };
+    reporter.report_in_spine_node(tree);
+    reporter.locate_pending_change();
+    ConflictReport {
+        conflicts: reporter.conflicts,
+    }
+}
+
+fn write_json_string(output: &mut impl Write, string: &str) -> std::io::Result<()> {
+    write!(output, "\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => write!(output, "\\\"")?,
+            '\\' => write!(output, "\\\\")?,
+            '\n' => write!(output, "\\n")?,
+            '\t' => write!(output, "\\t")?,
+            c if c.is_control() => write!(output, "\\u{:04x}", c as u32)?,
+            c => write!(output, "{}", c)?,
+        }
+    }
+    write!(output, "\"")
+}
+
+fn write_json_range(output: &mut impl Write, range: Option<&SourceRange>) -> std::io::Result<()> {
+    match range {
+        Some(range) => write!(
+            output,
+            "{{\"bytes\": [{}, {}], \"lines\": [{}, {}]}}",
+            range.bytes.start,
+            range.bytes.end,
+            range.lines.start(),
+            range.lines.end()
+        ),
+        None => write!(output, "null"),
+    }
+}
+
+impl ConflictReport {
+    /// Write the report as a JSON document with one entry per conflict and overall statistics:
+    ///
+    /// ```text
+    /// {
+    ///   "conflicts": [
+    ///     {
+    ///       "kind": "conflict" | "delete_conflict" | "insert_order_conflict" | "mv_conflict",
+    ///       "node_kinds": [<node kind>, ...],
+    ///       "ancestor": {"kind": <node kind>, "name": <name>} | null,
+    ///       "orig": <range>,
+    ///       "left": <range> | null,
+    ///       "right": <range> | null
+    ///     }
+    ///   ],
+    ///   "stats": {
+    ///     "total": <count>,
+    ///     "conflict": <count>,
+    ///     "delete_conflict": <count>,
+    ///     "insert_order_conflict": <count>,
+    ///     "mv_conflict": <count>
+    ///   }
+    /// }
+    /// ```
+    ///
+    /// A range is `{"bytes": [start, end], "lines": [first, last]}`, with an exclusive byte
+    /// range and an inclusive line range numbered from 1. All the conflicts of the same hunk
+    /// share its ranges.
+    ///
+    /// Ranges are approximations: the text of each version of the hunk is searched in its file
+    /// from the end of the previous hunk. They are exact when this text is found where the
+    /// hunk is expected. When tokens were compared ignoring whitespace, or when the hunk
+    /// combines moves of both sides, its first occurrence before the next hunk is reported
+    /// instead, or `null` if there is none.
+    pub fn write_json(&self, mut output: impl Write) -> std::io::Result<()> {
+        writeln!(output, "{{")?;
+        write!(output, "  \"conflicts\": [")?;
+        for (i, conflict) in self.conflicts.iter().enumerate() {
+            write!(output, "{}\n    {{", if i == 0 { "" } else { "," })?;
+            write!(output, "\n      \"kind\": ")?;
+            write_json_string(&mut output, conflict.kind.name())?;
+            write!(output, ",\n      \"node_kinds\": [")?;
+            for (i, kind) in conflict.node_kinds.iter().enumerate() {
+                if i != 0 {
+                    write!(output, ", ")?;
+                }
+                write_json_string(&mut output, kind)?;
+            }
+            write!(output, "],\n      \"ancestor\": ")?;
+            match &conflict.ancestor {
+                Some(ancestor) => {
+                    write!(output, "{{\"kind\": ")?;
+                    write_json_string(&mut output, ancestor.kind)?;
+                    write!(output, ", \"name\": ")?;
+                    write_json_string(&mut output, &ancestor.name)?;
+                    write!(output, "}}")?;
+                }
+                None => write!(output, "null")?,
+            }
+            write!(output, ",\n      \"orig\": ")?;
+            write_json_range(&mut output, Some(&conflict.orig_range))?;
+            write!(output, ",\n      \"left\": ")?;
+            write_json_range(&mut output, conflict.left_range.as_ref())?;
+            write!(output, ",\n      \"right\": ")?;
+            write_json_range(&mut output, conflict.right_range.as_ref())?;
+            write!(output, "\n    }}")?;
+        }
+        if !self.conflicts.is_empty() {
+            write!(output, "\n  ")?;
+        }
+        writeln!(output, "],")?;
+
+        writeln!(output, "  \"stats\": {{")?;
+        writeln!(output, "    \"total\": {},", self.conflicts.len())?;
+        let kinds = [
+            ConflictKind::Ins,
+            ConflictKind::Delete,
+            ConflictKind::InsertOrder,
+            ConflictKind::Metavariable,
+        ];
+        for (i, kind) in kinds.iter().enumerate() {
+            let count = self.conflicts.iter().filter(|c| c.kind == *kind).count();
+            let sep = if i + 1 == kinds.len() { "" } else { "," };
+            writeln!(output, "    \"{}\": {}{}", kind.name(), count, sep)?;
+        }
+        writeln!(output, "  }}")?;
+        writeln!(output, "}}")
+    }
+}
@@ -0,0 +1,728 @@
+use super::colors::Color;
+use super::line_merge::merge_lines;
+use super::{DelNode, InsNode, MergedSpineNode, MergedSpineSeqNode, MetavarInsReplacement};
+use crate::tree_formatter::{TreeFormattable, TreeFormatter};
+use crate::Metavariable;
+use recursive::recursive;
+use std::collections::HashMap;
+use std::io::Write;
+
+type Result = std::io::Result<()>;
+/// Texts of the left and right sides of a conflict
+type SideTexts = [Vec<u8>; 2];
+/// Texts of the base, left and right versions of a region
+pub(super) type RegionTexts = [Vec<u8>; 3];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConflictStyle {
+    /// Only show both sides of the conflict, like git `merge` conflict style
+    Merge,
+    /// Also show the original version between the two sides, like git `diff3` conflict style
+    Diff3,
+}
+
+pub struct ConflictMarkerOptions {
+    pub style: ConflictStyle,
+    pub marker_size: usize,
+    pub left_label: String,
+    pub base_label: String,
+    pub right_label: String,
+}
+
+impl Default for ConflictMarkerOptions {
+    fn default() -> Self {
+        ConflictMarkerOptions {
+            style: ConflictStyle::Merge,
+            marker_size: 7,
+            left_label: String::from("left"),
+            base_label: String::from("base"),
+            right_label: String::from("right"),
+        }
+    }
+}
+
+/// Version of the file taken for the content of a conflict
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConflictSide {
+    Base,
+    Left,
+    Right,
+}
+
+enum TextSegment {
+    Merged(Vec<u8>),
+    Conflict {
+        base: Vec<u8>,
+        left: Vec<u8>,
+        right: Vec<u8>,
+    },
+}
+
+#[derive(Default)]
+struct MetavarConflict {
+    base: Vec<u8>,
+    left: Option<Vec<u8>>,
+    right: Option<Vec<u8>>,
+}
+
+struct ConflictMarkerTreeFormatter<'m> {
+    buffer: Vec<u8>,
+    side: Option<ConflictSide>,
+    replacement_side: Option<ConflictSide>,
+    found_replacement: bool,
+    deleted_region: RegionTexts,
+    deleted_region_pos: usize,
+    seen_color: Option<Color>,
+    segments: Vec<TextSegment>,
+    metavar_conflicts: &'m HashMap<Metavariable, MetavarConflict>,
+}
+
+impl<'m> ConflictMarkerTreeFormatter<'m> {
+    fn new(metavar_conflicts: &'m HashMap<Metavariable, MetavarConflict>) -> Self {
+        ConflictMarkerTreeFormatter {
+            buffer: Vec::new(),
+            side: None,
+            replacement_side: None,
+            found_replacement: false,
+            deleted_region: RegionTexts::default(),
+            deleted_region_pos: 0,
+            seen_color: None,
+            segments: Vec::new(),
+            metavar_conflicts,
+        }
+    }
+
+    /// Render a subtree into a separate buffer, taking the given side for every nested conflict.
+    /// Also returns the first side color encountered in the subtree.
+    fn render_side(
+        &mut self,
+        side: ConflictSide,
+        write_fn: impl FnOnce(&mut Self) -> Result,
+    ) -> std::io::Result<(Vec<u8>, Option<Color>)> {
+        let prev_buffer = std::mem::take(&mut self.buffer);
+        let prev_side = self.side.replace(side);
+        let prev_seen_color = self.seen_color.take();
+        let write_result = write_fn(self);
+        let seen_color = std::mem::replace(&mut self.seen_color, prev_seen_color);
+        self.side = prev_side;
+        let rendered = std::mem::replace(&mut self.buffer, prev_buffer);
+        write_result?;
+        Ok((rendered, seen_color))
+    }
+
+    /// Render a subtree modified by a single side, choosing this side for every nested conflict.
+    /// Also returns the side that performed the modification.
+    fn render_modification(
+        &mut self,
+        write_fn: impl Fn(&mut Self) -> Result,
+    ) -> std::io::Result<(Vec<u8>, ConflictSide)> {
+        let (rendered, color) = self.render_side(ConflictSide::Left, &write_fn)?;
+        match color {
+            Some(Color::Right) => Ok((
+                self.render_side(ConflictSide::Right, write_fn)?.0,
+                ConflictSide::Right,
+            )),
+            _ => Ok((rendered, ConflictSide::Left)),
+        }
+    }
+
+    /// Render a deleted tree, and when some of its parts were replaced by a side but these
+    /// replacements could not be merged, also render the version of each side.
+    /// Also returns the first side color encountered, telling which side deleted the tree.
+    fn render_deletion(
+        &mut self,
+        write_del: impl Fn(&mut Self) -> Result,
+    ) -> std::io::Result<(Vec<u8>, Option<Color>, Option<SideTexts>)> {
+        let prev_found_replacement = std::mem::replace(&mut self.found_replacement, false);
+        let (base, deleting_color) = self.render_side(ConflictSide::Base, &write_del)?;
+        let found_replacement =
+            std::mem::replace(&mut self.found_replacement, prev_found_replacement);
+        if !found_replacement {
+            return Ok((base, deleting_color, None));
+        }
+
+        let prev_replacement_side = self.replacement_side;
+        let mut render_with_replacements = |side| {
+            self.replacement_side = Some(side);
+            let rendered = self.render_side(ConflictSide::Base, &write_del);
+            self.replacement_side = prev_replacement_side;
+            rendered.map(|(text, _)| text)
+        };
+        let left = render_with_replacements(ConflictSide::Left)?;
+        let right = render_with_replacements(ConflictSide::Right)?;
+        Ok((base, deleting_color, Some([left, right])))
+    }
+
+    /// Record text deleted without conflict, it becomes part of an adjacent conflict.
+    /// The side that did not delete the text still has it in its version of the conflict, so
+    /// that each side of the hunk is the full text of this side over the region of the base.
+    fn push_deleted(&mut self, deleted: Vec<u8>, deleting_color: Option<Color>) {
+        let kept_by = |side| match deleting_color {
+            Some(Color::Left) => side == ConflictSide::Right,
+            Some(Color::Right) => side == ConflictSide::Left,
+            _ => false,
+        };
+        let region = [
+            deleted.clone(),
+            if kept_by(ConflictSide::Left) {
+                deleted.clone()
+            } else {
+                Vec::new()
+            },
+            if kept_by(ConflictSide::Right) {
+                deleted
+            } else {
+                Vec::new()
+            },
+        ];
+
+        if self.buffer.is_empty() {
+            if let Some(TextSegment::Conflict { base, left, right }) = self.segments.last_mut() {
+                for (side, text) in [base, left, right].into_iter().zip(region) {
+                    side.extend(text);
+                }
+                return;
+            }
+        }
+        if self.deleted_region_pos != self.buffer.len() {
+            self.deleted_region = RegionTexts::default();
+            self.deleted_region_pos = self.buffer.len();
+        }
+        for (side, text) in self.deleted_region.iter_mut().zip(region) {
+            side.extend(text);
+        }
+    }
+
+    fn push_conflict(&mut self, base: Vec<u8>, left: Vec<u8>, right: Vec<u8>) {
+        if left == right {
+            self.buffer.extend(left);
+            return;
+        }
+        let mut region = std::mem::take(&mut self.deleted_region);
+        let [base, left, right] = if self.deleted_region_pos == self.buffer.len() {
+            for (side, text) in region.iter_mut().zip([base, left, right]) {
+                side.extend(text);
+            }
+            region
+        } else {
+            [base, left, right]
+        };
+        if !self.buffer.is_empty() {
+            let merged = std::mem::take(&mut self.buffer);
+            self.segments.push(TextSegment::Merged(merged));
+        }
+        self.segments
+            .push(TextSegment::Conflict { base, left, right })
+    }
+
+    fn finish(mut self) -> Vec<TextSegment> {
+        if !self.buffer.is_empty() {
+            self.segments.push(TextSegment::Merged(self.buffer));
+        }
+        self.segments
+    }
+}
+
+impl<'m> TreeFormatter for ConflictMarkerTreeFormatter<'m> {
+    type Output = Vec<u8>;
+    fn output(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+
+    fn write_metavariable(&mut self, mv: Metavariable) -> Result {
+        let conflict = match self.metavar_conflicts.get(&mv) {
+            Some(conflict) => conflict,
+            None => return write!(self.output(), "${}", mv.0),
+        };
+        let base = &conflict.base;
+        let left = conflict.left.as_ref().unwrap_or(base);
+        let right = conflict.right.as_ref().unwrap_or(base);
+        match self.side {
+            None => {
+                let (base, left, right) = (base.clone(), left.clone(), right.clone());
+                self.push_conflict(base, left, right);
+                Ok(())
+            }
+            Some(ConflictSide::Base) => self.output().write_all(base),
+            Some(ConflictSide::Left) => self.output().write_all(left),
+            Some(ConflictSide::Right) => self.output().write_all(right),
+        }
+    }
+
+    fn write_colored(
+        &mut self,
+        color: Color,
+        write_tree: impl FnOnce(&mut Self) -> Result,
+    ) -> Result {
+        if self.seen_color.is_none() && (color == Color::Left || color == Color::Right) {
+            self.seen_color = Some(color)
+        }
+        write_tree(self)
+    }
+
+    fn write_unchanged(&mut self) -> Result {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "unchanged nodes cannot be written as source text, remove metavariables first",
+        ))
+    }
+
+    fn write_changed(
+        &mut self,
+        write_del: impl Fn(&mut Self) -> Result,
+        write_ins: impl Fn(&mut Self) -> Result,
+    ) -> Result {
+        match self.side {
+            None => {
+                let (mut left, _) = self.render_side(ConflictSide::Left, &write_ins)?;
+                let (mut right, _) = self.render_side(ConflictSide::Right, &write_ins)?;
+                let (base, _, replaced) = self.render_deletion(write_del)?;
+                if let Some([left_del, right_del]) = replaced {
+                    // The side that replaced a part of the deleted tree keeps its replacement
+                    if left_del != base {
+                        left = left_del;
+                    }
+                    if right_del != base {
+                        right = right_del;
+                    }
+                }
+                self.push_conflict(base, left, right);
+                Ok(())
+            }
+            Some(ConflictSide::Base) => write_del(self),
+            Some(_) => write_ins(self),
+        }
+    }
+
+    fn write_deleted(&mut self, write_del: impl Fn(&mut Self) -> Result) -> Result {
+        match self.side {
+            None => {
+                // Deleted trees are not printed unless a side replaced some of their parts
+                match self.render_deletion(write_del)? {
+                    (base, _, Some([left, right])) => {
+                        let left = if left != base { left } else { Vec::new() };
+                        let right = if right != base { right } else { Vec::new() };
+                        self.push_conflict(base, left, right);
+                    }
+                    (base, deleting_color, None) => self.push_deleted(base, deleting_color),
+                }
+                Ok(())
+            }
+            Some(ConflictSide::Base) => write_del(self),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn write_inserted(&mut self, write_ins: impl FnOnce(&mut Self) -> Result) -> Result {
+        match self.side {
+            Some(ConflictSide::Base) => Ok(()),
+            _ => write_ins(self),
+        }
+    }
+
+    fn write_mv_conflict(
+        &mut self,
+        _: Metavariable,
+        write_del: impl FnOnce(&mut Self) -> Result,
+        write_repl: Option<impl Fn(&mut Self) -> Result>,
+    ) -> Result {
+        match (self.side, write_repl) {
+            (Some(ConflictSide::Base), Some(write_repl)) => {
+                self.found_replacement = true;
+                match self.replacement_side {
+                    Some(side) => {
+                        let (repl, repl_side) = self.render_modification(write_repl)?;
+                        if repl_side == side {
+                            self.output().write_all(&repl)
+                        } else {
+                            write_del(self)
+                        }
+                    }
+                    None => write_del(self),
+                }
+            }
+            _ => write_del(self),
+        }
+    }
+
+    fn write_ins_conflict(
+        &mut self,
+        write_confl_left: impl FnOnce(&mut Self) -> Result,
+        write_confl_right: impl FnOnce(&mut Self) -> Result,
+    ) -> Result {
+        match self.side {
+            None => {
+                let (left, _) = self.render_side(ConflictSide::Left, write_confl_left)?;
+                let (right, _) = self.render_side(ConflictSide::Right, write_confl_right)?;
+                self.push_conflict(Vec::new(), left, right);
+                Ok(())
+            }
+            Some(ConflictSide::Right) => write_confl_right(self),
+            Some(_) => write_confl_left(self),
+        }
+    }
+
+    fn write_del_conflict(
+        &mut self,
+        write_del: impl FnOnce(&mut Self) -> Result,
+        write_ins: impl Fn(&mut Self) -> Result,
+    ) -> Result {
+        match self.side {
+            None => {
+                let (base, _) = self.render_side(ConflictSide::Base, write_del)?;
+                match self.render_modification(write_ins)? {
+                    (modified, ConflictSide::Right) => {
+                        self.push_conflict(base, Vec::new(), modified)
+                    }
+                    (modified, _) => self.push_conflict(base, modified, Vec::new()),
+                }
+                Ok(())
+            }
+            Some(ConflictSide::Base) => write_del(self),
+            Some(side) => {
+                // Only keep the modified tree if it comes from the selected side
+                let (modified, modified_side) = self.render_modification(write_ins)?;
+                if modified_side == side {
+                    self.buffer.extend(modified);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn write_ord_conflict(
+        &mut self,
+        write_confl_left: impl FnOnce(&mut Self) -> Result,
+        write_confl_right: impl FnOnce(&mut Self) -> Result,
+    ) -> Result {
+        match self.side {
+            None => {
+                let (left, _) = self.render_side(ConflictSide::Left, write_confl_left)?;
+                let (right, _) = self.render_side(ConflictSide::Right, write_confl_right)?;
+                self.push_conflict(Vec::new(), left, right);
+                Ok(())
+            }
+            Some(ConflictSide::Base) => Ok(()),
+            Some(ConflictSide::Left) => write_confl_left(self),
+            Some(ConflictSide::Right) => write_confl_right(self),
+        }
+    }
+}
+
+struct MetavarConflictCollector<'a, 't> {
+    conflicts: HashMap<Metavariable, (&'a DelNode<'t>, Vec<&'a InsNode<'t>>)>,
+}
+
+impl<'a, 't> MetavarConflictCollector<'a, 't> {
+    #[recursive]
+    fn collect_in_del(&mut self, node: &'a DelNode<'t>) {
+        match node {
+            DelNode::InPlace(del) => del.data.visit(|ch| self.collect_in_del(&ch.node)),
+            DelNode::Elided(_) => (),
+            DelNode::MetavariableConflict(mv, del, repl) => {
+                let (_, repl_list) = self.conflicts.entry(*mv).or_insert((del, Vec::new()));
+                if let MetavarInsReplacement::Inlined(ins) = repl {
+                    repl_list.push(ins)
+                }
+                self.collect_in_del(del)
+            }
+        }
+    }
+
+    #[recursive]
+    fn collect_in_spine(&mut self, node: &'a MergedSpineNode<'t>) {
+        match node {
+            MergedSpineNode::Spine(spine) => spine.visit(|ch| self.collect_in_spine_seq(ch)),
+            MergedSpineNode::Unchanged => (),
+            MergedSpineNode::Changed(del, _) => self.collect_in_del(del),
+        }
+    }
+
+    fn collect_in_spine_seq(&mut self, node: &'a MergedSpineSeqNode<'t>) {
+        match node {
+            MergedSpineSeqNode::Zipped(spine) => self.collect_in_spine(&spine.node),
+            MergedSpineSeqNode::Deleted(del_list) => {
+                for del in del_list {
+                    self.collect_in_del(&del.node)
+                }
+            }
+            MergedSpineSeqNode::DeleteConflict(_, del, _) => self.collect_in_del(del),
+            MergedSpineSeqNode::Inserted(_) | MergedSpineSeqNode::InsertOrderConflict(..) => (),
+        }
+    }
+
+    fn render_conflicts(self) -> std::io::Result<HashMap<Metavariable, MetavarConflict>> {
+        // First render the original content of each metavariable, it is then used in place of
+        // the unresolved metavariables nested inside the replacements.
+        let mut base_conflicts = HashMap::new();
+        for (mv, (del, _)) in &self.conflicts {
+            let no_conflicts = HashMap::new();
+            let mut fmt = ConflictMarkerTreeFormatter::new(&no_conflicts);
+            let base = fmt
+                .render_side(ConflictSide::Base, |fmt| del.write_with(fmt))?
+                .0;
+            base_conflicts.insert(
+                *mv,
+                MetavarConflict {
+                    base,
+                    ..MetavarConflict::default()
+                },
+            );
+        }
+
+        let mut rendered_conflicts = HashMap::new();
+        for (mv, (_, repl_list)) in self.conflicts {
+            let mut fmt = ConflictMarkerTreeFormatter::new(&base_conflicts);
+            let mut conflict = MetavarConflict {
+                base: base_conflicts[&mv].base.clone(),
+                ..MetavarConflict::default()
+            };
+            for ins in repl_list {
+                match fmt.render_modification(|fmt| ins.write_with(fmt))? {
+                    (repl_text, ConflictSide::Right) => conflict.right = Some(repl_text),
+                    (repl_text, _) if conflict.left.is_some() => conflict.right = Some(repl_text),
+                    (repl_text, _) => conflict.left = Some(repl_text),
+                }
+            }
+            rendered_conflicts.insert(mv, conflict);
+        }
+        Ok(rendered_conflicts)
+    }
+}
+
+fn write_marker(output: &mut impl Write, marker: u8, size: usize, label: &str) -> Result {
+    output.write_all(&vec![marker; size])?;
+    if !label.is_empty() {
+        write!(output, " {}", label)?;
+    }
+    writeln!(output)
+}
+
+fn write_conflict_side(output: &mut impl Write, text: &[u8]) -> Result {
+    output.write_all(text)?;
+    if !text.is_empty() && !text.ends_with(b"\n") {
+        writeln!(output)?;
+    }
+    Ok(())
+}
+
+pub(super) fn write_conflict_hunk(
+    output: &mut impl Write,
+    options: &ConflictMarkerOptions,
+    base: &[u8],
+    left: &[u8],
+    right: &[u8],
+) -> Result {
+    if left == right {
+        return output.write_all(left);
+    }
+    write_marker(output, b'<', options.marker_size, &options.left_label)?;
+    write_conflict_side(output, left)?;
+    if options.style == ConflictStyle::Diff3 {
+        write_marker(output, b'|', options.marker_size, &options.base_label)?;
+        write_conflict_side(output, base)?;
+    }
+    write_marker(output, b'=', options.marker_size, "")?;
+    write_conflict_side(output, right)?;
+    write_marker(output, b'>', options.marker_size, &options.right_label)
+}
+
+/// Write a conflict extended to full lines. Only the lines modified by both sides are kept
+/// between conflict markers, the other ones are merged line by line.
+fn write_refined_conflict_hunk(
+    output: &mut impl Write,
+    options: &ConflictMarkerOptions,
+    base: &[u8],
+    left: &[u8],
+    right: &[u8],
+) -> std::io::Result<usize> {
+    merge_lines(base, left, right).write_with_conflict_markers(options, output)
+}
+
+fn write_segments(
+    segments: Vec<TextSegment>,
+    options: &ConflictMarkerOptions,
+    output: &mut impl Write,
+) -> std::io::Result<usize> {
+    // Conflict markers must stand on their own lines: extend each conflict to the full lines it
+    // touches, fusing conflicts sharing a line.
+    let mut nb_conflicts = 0;
+    let mut pending = Vec::new();
+    let mut open_conflict: Option<[Vec<u8>; 3]> = None;
+    for segment in segments {
+        match (segment, &mut open_conflict) {
+            (TextSegment::Merged(text), None) => pending.extend(text),
+            (TextSegment::Merged(text), Some(sides)) => {
+                match text.iter().position(|c| *c == b'\n') {
+                    Some(line_end) => {
+                        for side in sides.iter_mut() {
+                            side.extend(&text[..=line_end]);
+                        }
+                        let [base, left, right] = open_conflict.take().unwrap();
+                        nb_conflicts +=
+                            write_refined_conflict_hunk(output, options, &base, &left, &right)?;
+                        pending.extend(&text[line_end + 1..]);
+                    }
+                    None => {
+                        for side in sides.iter_mut() {
+                            side.extend(&text);
+                        }
+                    }
+                }
+            }
+            (TextSegment::Conflict { base, left, right }, Some(sides)) => {
+                sides[0].extend(base);
+                sides[1].extend(left);
+                sides[2].extend(right);
+            }
+            (TextSegment::Conflict { base, left, right }, None) => {
+                let line_start = pending
+                    .iter()
+                    .rposition(|c| *c == b'\n')
+                    .map(|pos| pos + 1)
+                    .unwrap_or(0);
+                output.write_all(&pending[..line_start])?;
+                let line_head = pending.split_off(line_start);
+                pending.clear();
+                open_conflict = Some([base, left, right].map(|side| {
+                    let mut full_side = line_head.clone();
+                    full_side.extend(side);
+                    full_side
+                }));
+            }
+        }
+    }
+    match open_conflict {
+        Some([base, left, right]) => {
+            nb_conflicts += write_refined_conflict_hunk(output, options, &base, &left, &right)?
+        }
+        None => output.write_all(&pending)?,
+    }
+    Ok(nb_conflicts)
+}
+
+/// Render a standalone subtree as source text, taking the given side for every conflict
+pub(super) fn render_side_text(
+    tree: &impl TreeFormattable,
+    side: ConflictSide,
+) -> std::io::Result<Vec<u8>> {
+    let no_conflicts = HashMap::new();
+    let mut fmt = ConflictMarkerTreeFormatter::new(&no_conflicts);
+    Ok(fmt.render_side(side, |fmt| tree.write_with(fmt))?.0)
+}
+
+/// Conflicting metavariables of a merged tree, with their original content and their text in
+/// each version
+#[derive(Default)]
+pub(super) struct MetavarConflicts<'a, 't> {
+    pub contents: HashMap<Metavariable, &'a DelNode<'t>>,
+    texts: HashMap<Metavariable, MetavarConflict>,
+}
+
+impl<'a, 't> MetavarConflicts<'a, 't> {
+    pub(super) fn collect(tree: &'a MergedSpineNode<'t>) -> std::io::Result<Self> {
+        let mut collector = MetavarConflictCollector {
+            conflicts: HashMap::new(),
+        };
+        collector.collect_in_spine(tree);
+        let contents = collector
+            .conflicts
+            .iter()
+            .map(|(mv, (del, _))| (*mv, *del))
+            .collect();
+        Ok(MetavarConflicts {
+            contents,
+            texts: collector.render_conflicts()?,
+        })
+    }
+
+    /// Text of a part of the merged tree in the base, left and right files. If this part
+    /// contains a conflict, these are the sides of its conflict hunk. Otherwise the side
+    /// that did not perform the modification still has the base text.
+    pub(super) fn region_texts(&self, tree: &impl TreeFormattable) -> std::io::Result<RegionTexts> {
+        let mut fmt = ConflictMarkerTreeFormatter::new(&self.texts);
+        let (base, deleting_color) =
+            fmt.render_side(ConflictSide::Base, |fmt| tree.write_with(fmt))?;
+        let (_, modifying_color) =
+            fmt.render_side(ConflictSide::Left, |fmt| tree.write_with(fmt))?;
+        tree.write_with(&mut fmt)?;
+        let segments = fmt.finish();
+
+        if segments
+            .iter()
+            .any(|segment| matches!(segment, TextSegment::Conflict { .. }))
+        {
+            let mut texts = RegionTexts::default();
+            for segment in segments {
+                match segment {
+                    TextSegment::Merged(text) => {
+                        for side in &mut texts {
+                            side.extend(&text)
+                        }
+                    }
+                    TextSegment::Conflict { base, left, right } => {
+                        for (side, text) in texts.iter_mut().zip([base, left, right]) {
+                            side.extend(text)
+                        }
+                    }
+                }
+            }
+            return Ok(texts);
+        }
+
+        let mut modified = Vec::new();
+        for segment in segments {
+            if let TextSegment::Merged(text) = segment {
+                modified.extend(text)
+            }
+        }
+        Ok(match modifying_color.or(deleting_color) {
+            Some(Color::Left) => [base.clone(), modified, base],
+            Some(Color::Right) => [base.clone(), base, modified],
+            _ => [base, modified.clone(), modified],
+        })
+    }
+}
+
+fn render_segments(tree: &MergedSpineNode) -> std::io::Result<Vec<TextSegment>> {
+    let metavar_conflicts = MetavarConflicts::collect(tree)?;
+    let mut fmt = ConflictMarkerTreeFormatter::new(&metavar_conflicts.texts);
+    tree.write_with(&mut fmt)?;
+    Ok(fmt.finish())
+}
+
+/// Write a merged tree as source text, printing each remaining conflict as a textual conflict
+/// hunk delimited by standard conflict markers. Inside each conflict, lines changed by a
+/// single side are merged textually so that only overlapping lines remain between markers.
+/// Returns the number of conflict hunks written.
+///
+/// The tree must have been made standalone by `remove_metavars` beforehand.
+pub fn write_with_conflict_markers(
+    tree: &MergedSpineNode,
+    options: &ConflictMarkerOptions,
+    mut output: impl Write,
+) -> std::io::Result<usize> {
+    write_segments(render_segments(tree)?, options, &mut output)
+}
+
+/// Write a merged tree as source text, where every conflict-free change is applied but the
+/// content of each remaining conflict is taken from the given side.
+///
+/// The tree must have been made standalone by `remove_metavars` beforehand.
+pub fn write_with_chosen_side(
+    tree: &MergedSpineNode,
+    side: ConflictSide,
+    mut output: impl Write,
+) -> Result {
+    for segment in render_segments(tree)? {
+        match segment {
+            TextSegment::Merged(text) => output.write_all(&text)?,
+            TextSegment::Conflict { base, left, right } => output.write_all(match side {
+                ConflictSide::Base => &base,
+                ConflictSide::Left => &left,
+                ConflictSide::Right => &right,
+            })?,
+        }
+    }
+    Ok(())
+}
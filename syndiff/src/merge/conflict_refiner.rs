@@ -0,0 +1,203 @@
+use super::colors::Colored;
+use super::line_merge::three_way_chunks;
+use super::{DelNode, InsNode, MergedInsNode, MergedSpineNode, MergedSpineSeqNode};
+use crate::generic_tree::{Subtree, Token, Tree};
+use recursive::recursive;
+
+/// Granularity of the textual merge used to refine conflicts
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConflictRefinement {
+    /// Merge the token sequences of both sides
+    Tokens,
+    /// Merge the lines of both sides, where a line is the sequence of tokens ending with a
+    /// newline
+    Lines,
+}
+
+#[derive(Clone, Copy)]
+enum Side {
+    Left,
+    Right,
+}
+
+type Tokens<'t> = Vec<Token<'t>>;
+
+fn collect_tree_tokens<'t, T>(
+    tree: &Tree<'t, Subtree<T>>,
+    tokens: &mut Tokens<'t>,
+    collect_fn: impl Fn(&T, &mut Tokens<'t>) -> Option<()>,
+) -> Option<()> {
+    match tree {
+        Tree::Node(_, children) => {
+            for ch in children {
+                collect_fn(&ch.node, tokens)?
+            }
+        }
+        Tree::Leaf(tok) => tokens.push(*tok),
+    }
+    Some(())
+}
+
+// Token collectors return None when they find a metavariable whose content is unknown
+#[recursive]
+fn collect_del_tokens<'t>(del: &DelNode<'t>, tokens: &mut Tokens<'t>) -> Option<()> {
+    match del {
+        DelNode::InPlace(del) => collect_tree_tokens(&del.data, tokens, collect_del_tokens),
+        DelNode::Elided(_) | DelNode::MetavariableConflict(..) => None,
+    }
+}
+
+#[recursive]
+fn collect_ins_tokens<'t>(ins: &InsNode<'t>, tokens: &mut Tokens<'t>) -> Option<()> {
+    match ins {
+        InsNode::InPlace(ins) => collect_tree_tokens(&ins.data, tokens, collect_ins_tokens),
+        InsNode::Inlined(repl) => collect_ins_tokens(&repl.data, tokens),
+        InsNode::Elided(_) => None,
+    }
+}
+
+#[recursive]
+fn collect_merged_ins_tokens<'t>(
+    ins: &MergedInsNode<'t>,
+    side: Side,
+    tokens: &mut Tokens<'t>,
+) -> Option<()> {
+    match ins {
+        MergedInsNode::InPlace(ins) => collect_tree_tokens(ins, tokens, |ch, tokens| {
+            collect_merged_ins_tokens(ch, side, tokens)
+        }),
+        MergedInsNode::Elided(_) => None,
+        MergedInsNode::SingleIns(ins) => collect_ins_tokens(ins, tokens),
+        MergedInsNode::Conflict(left, right) => match side {
+            Side::Left => collect_ins_tokens(left, tokens),
+            Side::Right => collect_ins_tokens(right, tokens),
+        },
+    }
+}
+
+/// Merge three versions of a sequence, or return None if both sides changed the same elements
+fn merge_sequences<'s, T: Eq>(orig: &'s [T], left: &'s [T], right: &'s [T]) -> Option<Vec<&'s T>> {
+    let mut merged = Vec::new();
+    for chunk in three_way_chunks(orig, left, right) {
+        let base = &orig[chunk.orig];
+        let left = &left[chunk.left];
+        let right = &right[chunk.right];
+        if right == base || left == right {
+            merged.extend(left)
+        } else if left == base {
+            merged.extend(right)
+        } else {
+            return None;
+        }
+    }
+    Some(merged)
+}
+
+fn split_token_lines<'a, 't>(tokens: &'a [Token<'t>]) -> Vec<&'a [Token<'t>]> {
+    tokens.split_inclusive(|tok| tok.has_newline()).collect()
+}
+
+fn merge_tokens<'t>(
+    base: &[Token<'t>],
+    left: &[Token<'t>],
+    right: &[Token<'t>],
+    refinement: ConflictRefinement,
+) -> Option<Tokens<'t>> {
+    match refinement {
+        ConflictRefinement::Tokens => Some(
+            merge_sequences(base, left, right)?
+                .into_iter()
+                .copied()
+                .collect(),
+        ),
+        ConflictRefinement::Lines => {
+            let base_lines = split_token_lines(base);
+            let left_lines = split_token_lines(left);
+            let right_lines = split_token_lines(right);
+            let merged_lines = merge_sequences(&base_lines, &left_lines, &right_lines)?;
+            Some(
+                merged_lines
+                    .into_iter()
+                    .flat_map(|line| line.iter().copied())
+                    .collect(),
+            )
+        }
+    }
+}
+
+#[recursive]
+fn has_ins_conflict(ins: &MergedInsNode) -> bool {
+    match ins {
+        MergedInsNode::InPlace(ins) => {
+            let mut found_conflict = false;
+            ins.visit(|ch| found_conflict |= has_ins_conflict(&ch.node));
+            found_conflict
+        }
+        MergedInsNode::Conflict(..) => true,
+        MergedInsNode::Elided(_) | MergedInsNode::SingleIns(_) => false,
+    }
+}
+
+/// Try to merge textually the conflicting versions of a changed subtree.
+/// On success, the merged subtree becomes a flat sequence of tokens.
+fn refine_changed<'t>(
+    del: &DelNode<'t>,
+    ins: &MergedInsNode<'t>,
+    refinement: ConflictRefinement,
+) -> Option<InsNode<'t>> {
+    let del_tree = match del {
+        DelNode::InPlace(del) => &del.data,
+        _ => return None,
+    };
+    let mut base = Vec::new();
+    collect_del_tokens(del, &mut base)?;
+    let mut left = Vec::new();
+    collect_merged_ins_tokens(ins, Side::Left, &mut left)?;
+    let mut right = Vec::new();
+    collect_merged_ins_tokens(ins, Side::Right, &mut right)?;
+
+    let merged = merge_tokens(&base, &left, &right, refinement)?;
+    let merged_tree = match (del_tree, merged.as_slice()) {
+        (Tree::Node(kind, _), _) => Tree::Node(
+            *kind,
+            merged
+                .into_iter()
+                .map(|tok| Subtree {
+                    field: None,
+                    node: InsNode::InPlace(Colored::new_both(Tree::Leaf(tok))),
+                })
+                .collect(),
+        ),
+        (Tree::Leaf(_), [tok]) => Tree::Leaf(*tok),
+        (Tree::Leaf(_), _) => return None,
+    };
+    Some(InsNode::InPlace(Colored::new_both(merged_tree)))
+}
+
+#[recursive]
+fn refine_in_spine(node: &mut MergedSpineNode, refinement: ConflictRefinement) {
+    match node {
+        MergedSpineNode::Spine(spine) => spine.visit_mut(|ch| {
+            if let MergedSpineSeqNode::Zipped(ch) = ch {
+                refine_in_spine(&mut ch.node, refinement)
+            }
+        }),
+        MergedSpineNode::Unchanged => (),
+        MergedSpineNode::Changed(del, ins) => {
+            if has_ins_conflict(ins) {
+                if let Some(merged) = refine_changed(del, ins, refinement) {
+                    *ins = MergedInsNode::SingleIns(merged)
+                }
+            }
+        }
+    }
+}
+
+/// Resolve the conflicts between the replacements of a subtree when a finer textual three-way
+/// merge against the deleted subtree is clean. Only conflicts where both sides modified the
+/// same tokens or lines are kept.
+///
+/// The tree must have been made standalone by `remove_metavars` beforehand.
+pub fn refine_conflicts(tree: &mut MergedSpineNode, refinement: ConflictRefinement) {
+    refine_in_spine(tree, refinement)
+}
@@ -0,0 +1,543 @@
+use super::colors::{Color, Colored};
+use super::subst::infer_ins_from_del;
+use super::{
+    DelNode, InsNode, MergeOptions, MergedInsNode, MergedSpineNode, MergedSpineSeqNode,
+    MetavarInsReplacement,
+};
+use crate::generic_tree::{FieldId, NodeKind, Subtree, Tree};
+use crate::Metavariable;
+use recursive::recursive;
+use std::collections::{HashMap, HashSet};
+
+/// Resolution of a subtree deleted by one side and modified by the other
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeleteConflictResolution {
+    /// Delete the subtree, dropping the modification
+    DeletionWins,
+    /// Keep the modified subtree
+    ModificationWins,
+}
+
+/// Resolution of a subtree replaced differently by both sides
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InsConflictResolution {
+    Left,
+    Right,
+    /// Keep both replacements, left first. Only possible for a whole changed subtree without
+    /// field name inside a sequence, other conflicts are left unresolved.
+    Both,
+}
+
+/// Resolution of a subtree moved by one side and modified by the other in an incompatible way
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MetavarConflictResolution {
+    /// Keep the version of the subtree from the left side
+    Left,
+    /// Keep the version of the subtree from the right side
+    Right,
+}
+
+/// Resolution of concurrent insertions at the same place
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InsertOrderResolution {
+    LeftFirst,
+    RightFirst,
+    /// Drop the insertions of both sides
+    Drop,
+}
+
+enum MetavarSubst<'t> {
+    Pending(InsNode<'t>),
+    Processing,
+    Computed(InsNode<'t>),
+}
+
+struct ConflictResolver<'t, 'o> {
+    delete_conflicts: Option<DeleteConflictResolution>,
+    ins_conflicts: Option<InsConflictResolution>,
+    insert_order_conflicts: Option<InsertOrderResolution>,
+    commutative_kinds: &'o HashSet<NodeKind>,
+    key_fields: &'o HashMap<NodeKind, FieldId>,
+    sort_commutative_insertions: bool,
+    metavar_side: Option<Color>,
+    metavar_dels: HashMap<Metavariable, DelNode<'t>>,
+    metavar_subst: HashMap<Metavariable, MetavarSubst<'t>>,
+}
+
+#[recursive]
+fn side_color(node: &InsNode) -> Option<Color> {
+    let color = match node {
+        InsNode::InPlace(ins) => ins.color,
+        InsNode::Elided(mv) => mv.color,
+        InsNode::Inlined(repl) => repl.color,
+    };
+    match color {
+        Color::Left | Color::Right => Some(color),
+        Color::White | Color::Both => match node {
+            InsNode::InPlace(ins) => {
+                let mut sub_color = None;
+                ins.data.visit(|ch| {
+                    if sub_color.is_none() {
+                        sub_color = side_color(&ch.node)
+                    }
+                });
+                sub_color
+            }
+            InsNode::Elided(_) => None,
+            InsNode::Inlined(repl) => side_color(&repl.data),
+        },
+    }
+}
+
+#[recursive]
+fn is_same_ins(left: &InsNode, right: &InsNode) -> bool {
+    match (left, right) {
+        (InsNode::Inlined(left), right) => is_same_ins(&left.data, right),
+        (left, InsNode::Inlined(right)) => is_same_ins(left, &right.data),
+        (InsNode::InPlace(left), InsNode::InPlace(right)) => {
+            Tree::compare_subtrees(&left.data, &right.data, is_same_ins)
+        }
+        (InsNode::Elided(left), InsNode::Elided(right)) => left.data == right.data,
+        (InsNode::InPlace(_), InsNode::Elided(_)) | (InsNode::Elided(_), InsNode::InPlace(_)) => {
+            false
+        }
+    }
+}
+
+fn push_deleted<'t>(seq: &mut Vec<MergedSpineSeqNode<'t>>, del: Subtree<DelNode<'t>>) {
+    if let Some(MergedSpineSeqNode::Deleted(prev_del_seq)) = seq.last_mut() {
+        prev_del_seq.push(del)
+    } else {
+        seq.push(MergedSpineSeqNode::Deleted(vec![del]))
+    }
+}
+
+impl<'t, 'o> ConflictResolver<'t, 'o> {
+    fn is_kept_replacement(&self, repl: &MetavarInsReplacement<'t>) -> bool {
+        match repl {
+            MetavarInsReplacement::InferFromDel => false,
+            MetavarInsReplacement::Inlined(ins) => {
+                self.metavar_side.is_some() && side_color(ins) == self.metavar_side
+            }
+        }
+    }
+
+    /// Compute the version of a deleted tree seen by the side chosen for metavariable conflicts
+    #[recursive]
+    fn side_version_of_del(&self, node: &DelNode<'t>) -> InsNode<'t> {
+        match node {
+            DelNode::InPlace(del) => InsNode::InPlace(Colored::new_white(
+                del.data.map_subtrees(|sub| self.side_version_of_del(sub)),
+            )),
+            DelNode::Elided(mv) => InsNode::Elided(Colored::new_white(mv.data)),
+            DelNode::MetavariableConflict(_, del, repl) => match repl {
+                MetavarInsReplacement::Inlined(ins) if self.is_kept_replacement(repl) => {
+                    ins.clone()
+                }
+                _ => self.side_version_of_del(del),
+            },
+        }
+    }
+
+    #[recursive]
+    fn has_kept_replacement(&self, node: &DelNode<'t>) -> bool {
+        match node {
+            DelNode::InPlace(del) => {
+                let mut found = false;
+                del.data
+                    .visit(|ch| found = found || self.has_kept_replacement(&ch.node));
+                found
+            }
+            DelNode::Elided(_) => false,
+            DelNode::MetavariableConflict(_, del, repl) => {
+                self.is_kept_replacement(repl) || self.has_kept_replacement(del)
+            }
+        }
+    }
+
+    #[recursive]
+    fn collect_metavar_conflicts(&mut self, node: &DelNode<'t>) {
+        match node {
+            DelNode::InPlace(del) => del
+                .data
+                .visit(|ch| self.collect_metavar_conflicts(&ch.node)),
+            DelNode::Elided(_) => (),
+            DelNode::MetavariableConflict(mv, del, repl) => {
+                let subst = match repl {
+                    MetavarInsReplacement::Inlined(ins) if self.is_kept_replacement(repl) => {
+                        ins.clone()
+                    }
+                    _ => self.side_version_of_del(del),
+                };
+                self.metavar_dels.insert(*mv, (**del).clone());
+                self.metavar_subst.insert(*mv, MetavarSubst::Pending(subst));
+                self.collect_metavar_conflicts(del)
+            }
+        }
+    }
+
+    #[recursive]
+    fn collect_metavar_conflicts_in_spine(&mut self, node: &MergedSpineNode<'t>) {
+        match node {
+            MergedSpineNode::Spine(spine) => spine.visit(|ch| match ch {
+                MergedSpineSeqNode::Zipped(spine) => {
+                    self.collect_metavar_conflicts_in_spine(&spine.node)
+                }
+                MergedSpineSeqNode::Deleted(del_list) => {
+                    for del in del_list {
+                        self.collect_metavar_conflicts(&del.node)
+                    }
+                }
+                MergedSpineSeqNode::DeleteConflict(_, del, _) => {
+                    self.collect_metavar_conflicts(del)
+                }
+                MergedSpineSeqNode::Inserted(_) | MergedSpineSeqNode::InsertOrderConflict(..) => (),
+            }),
+            MergedSpineNode::Unchanged => (),
+            MergedSpineNode::Changed(del, _) => self.collect_metavar_conflicts(del),
+        }
+    }
+
+    fn find_metavar_subst(&mut self, mv: Metavariable) -> Option<InsNode<'t>> {
+        let subst_state = self.metavar_subst.get_mut(&mv)?;
+        let subst = match std::mem::replace(subst_state, MetavarSubst::Processing) {
+            MetavarSubst::Computed(subst) => subst,
+            MetavarSubst::Pending(mut subst) => {
+                self.substitute_in_ins_node(&mut subst);
+                subst
+            }
+            MetavarSubst::Processing => {
+                // The chosen side replacements form a cycle, break it by taking the original
+                // content of the metavariable
+                infer_ins_from_del(&self.metavar_dels[&mv])
+            }
+        };
+        self.metavar_subst
+            .insert(mv, MetavarSubst::Computed(subst.clone()));
+        Some(subst)
+    }
+
+    #[recursive]
+    fn substitute_in_ins_node(&mut self, node: &mut InsNode<'t>) {
+        match node {
+            InsNode::InPlace(ins) => ins
+                .data
+                .visit_mut(|sub| self.substitute_in_ins_node(&mut sub.node)),
+            InsNode::Elided(mv) => {
+                if let Some(subst) = self.find_metavar_subst(mv.data) {
+                    *node = InsNode::Inlined(Colored {
+                        data: Box::new(subst),
+                        color: mv.color,
+                    })
+                }
+            }
+            InsNode::Inlined(repl) => self.substitute_in_ins_node(&mut repl.data),
+        }
+    }
+
+    #[recursive]
+    fn resolve_in_del_node(&mut self, node: &mut DelNode<'t>) {
+        match node {
+            DelNode::InPlace(del) => del
+                .data
+                .visit_mut(|sub| self.resolve_in_del_node(&mut sub.node)),
+            DelNode::Elided(_) => (),
+            DelNode::MetavariableConflict(mv, del, _) => {
+                self.resolve_in_del_node(del);
+                if self.metavar_side.is_some() {
+                    *node = std::mem::replace(del, DelNode::Elided(Colored::new_white(*mv)))
+                }
+            }
+        }
+    }
+
+    /// Version of a deleted tree seen by the side chosen for metavariable conflicts, if it does
+    /// not match the original
+    fn kept_replacements_of_del(&mut self, node: &DelNode<'t>) -> Option<InsNode<'t>> {
+        if !self.has_kept_replacement(node) {
+            return None;
+        }
+        let mut side_version = self.side_version_of_del(node);
+        self.substitute_in_ins_node(&mut side_version);
+        Some(side_version)
+    }
+
+    #[recursive]
+    fn resolve_in_merged_ins_node(&mut self, node: &mut MergedInsNode<'t>) {
+        match node {
+            MergedInsNode::InPlace(ins) => {
+                ins.visit_mut(|sub| self.resolve_in_merged_ins_node(&mut sub.node))
+            }
+            MergedInsNode::Elided(mv) => {
+                if let Some(subst) = self.find_metavar_subst(*mv) {
+                    *node = MergedInsNode::SingleIns(subst)
+                }
+            }
+            MergedInsNode::SingleIns(ins) => self.substitute_in_ins_node(ins),
+            MergedInsNode::Conflict(left_ins, right_ins) => {
+                self.substitute_in_ins_node(left_ins);
+                self.substitute_in_ins_node(right_ins);
+                if is_same_ins(left_ins, right_ins) {
+                    // Metavariable substitutions made both sides identical
+                    *node = MergedInsNode::SingleIns(left_ins.clone());
+                    return;
+                }
+                match self.ins_conflicts {
+                    Some(InsConflictResolution::Left) => {
+                        *node = MergedInsNode::SingleIns(left_ins.clone())
+                    }
+                    Some(InsConflictResolution::Right) => {
+                        *node = MergedInsNode::SingleIns(right_ins.clone())
+                    }
+                    // Resolved by the parent sequence if any
+                    Some(InsConflictResolution::Both) | None => (),
+                }
+            }
+        }
+    }
+
+    #[recursive]
+    fn resolve_in_spine_node(&mut self, node: &mut MergedSpineNode<'t>) {
+        match node {
+            MergedSpineNode::Spine(spine) => match spine {
+                Tree::Node(kind, children) => self.resolve_in_spine_seq(*kind, children),
+                Tree::Leaf(_) => (),
+            },
+            MergedSpineNode::Unchanged => (),
+            MergedSpineNode::Changed(del, ins) => {
+                if let Some(side_version) = self.kept_replacements_of_del(del) {
+                    // The chosen side replaced a part of the deleted tree, its version of the
+                    // whole tree takes precedence over the merged insertion
+                    *ins = MergedInsNode::SingleIns(side_version)
+                }
+                self.resolve_in_del_node(del);
+                self.resolve_in_merged_ins_node(ins);
+            }
+        }
+    }
+
+    /// Text of the key child of an inserted node, if its kind has a key field
+    fn ins_key(&self, node: &InsNode) -> Option<Vec<u8>> {
+        match node {
+            InsNode::InPlace(ins) => match &ins.data {
+                Tree::Node(kind, children) => {
+                    let key_field = self.key_fields.get(kind)?;
+                    let key = children.iter().find(|ch| ch.field == Some(*key_field))?;
+                    let mut key_text = Vec::new();
+                    push_ins_text(&key.node, &mut key_text);
+                    Some(key_text)
+                }
+                Tree::Leaf(_) => None,
+            },
+            InsNode::Elided(_) => None,
+            InsNode::Inlined(repl) => self.ins_key(&repl.data),
+        }
+    }
+
+    /// Check if all the subtrees inserted on both sides have keys, and that no key is
+    /// inserted on both sides
+    fn have_distinct_keys(
+        &self,
+        left_ins_seq: &[Subtree<InsNode>],
+        right_ins_seq: &[Subtree<InsNode>],
+    ) -> bool {
+        if self.key_fields.is_empty() {
+            return false;
+        }
+        let mut left_keys = HashSet::new();
+        for ins in left_ins_seq.iter().filter(|ins| !is_leaf_ins(&ins.node)) {
+            match self.ins_key(&ins.node) {
+                Some(key) => left_keys.insert(key),
+                None => return false,
+            };
+        }
+        right_ins_seq
+            .iter()
+            .filter(|ins| !is_leaf_ins(&ins.node))
+            .all(|ins| match self.ins_key(&ins.node) {
+                Some(key) => !left_keys.contains(&key),
+                None => false,
+            })
+    }
+
+    fn resolve_in_spine_seq(&mut self, kind: NodeKind, seq: &mut Vec<MergedSpineSeqNode<'t>>) {
+        for node in std::mem::take(seq) {
+            match node {
+                MergedSpineSeqNode::Zipped(mut spine) => {
+                    self.resolve_in_spine_node(&mut spine.node);
+                    match spine.node {
+                        MergedSpineNode::Changed(del, MergedInsNode::Conflict(left, right))
+                            if self.ins_conflicts == Some(InsConflictResolution::Both)
+                                && spine.field.is_none() =>
+                        {
+                            let field = spine.field;
+                            push_deleted(seq, Subtree { field, node: del });
+                            seq.push(MergedSpineSeqNode::Inserted(vec![
+                                Subtree { field, node: left },
+                                Subtree { field, node: right },
+                            ]))
+                        }
+                        _ => seq.push(MergedSpineSeqNode::Zipped(spine)),
+                    }
+                }
+                MergedSpineSeqNode::Deleted(mut del_seq) => {
+                    // If the chosen side replaced a part of the deleted sequence, its version of
+                    // the whole sequence is inserted back
+                    let side_version = if del_seq
+                        .iter()
+                        .any(|del| self.has_kept_replacement(&del.node))
+                    {
+                        Some(
+                            del_seq
+                                .iter()
+                                .map(|del| {
+                                    del.as_ref().map(|del| {
+                                        let mut side_version = self.side_version_of_del(del);
+                                        self.substitute_in_ins_node(&mut side_version);
+                                        side_version
+                                    })
+                                })
+                                .collect(),
+                        )
+                    } else {
+                        None
+                    };
+                    for del in &mut del_seq {
+                        self.resolve_in_del_node(&mut del.node);
+                    }
+                    for del in del_seq {
+                        push_deleted(seq, del);
+                    }
+                    if let Some(side_version) = side_version {
+                        seq.push(MergedSpineSeqNode::Inserted(side_version))
+                    }
+                }
+                MergedSpineSeqNode::DeleteConflict(field, mut del, mut ins) => {
+                    self.resolve_in_del_node(&mut del);
+                    self.substitute_in_ins_node(&mut ins);
+                    match self.delete_conflicts {
+                        Some(DeleteConflictResolution::DeletionWins) => {
+                            push_deleted(seq, Subtree { field, node: del })
+                        }
+                        Some(DeleteConflictResolution::ModificationWins) => {
+                            seq.push(MergedSpineSeqNode::Zipped(Subtree {
+                                field,
+                                node: MergedSpineNode::Changed(del, MergedInsNode::SingleIns(ins)),
+                            }))
+                        }
+                        None => seq.push(MergedSpineSeqNode::DeleteConflict(field, del, ins)),
+                    }
+                }
+                MergedSpineSeqNode::Inserted(mut ins_seq) => {
+                    for ins in &mut ins_seq {
+                        self.substitute_in_ins_node(&mut ins.node)
+                    }
+                    seq.push(MergedSpineSeqNode::Inserted(ins_seq))
+                }
+                MergedSpineSeqNode::InsertOrderConflict(mut left_ins_seq, mut right_ins_seq) => {
+                    for ins_seq in [&mut left_ins_seq, &mut right_ins_seq] {
+                        for ins in ins_seq {
+                            self.substitute_in_ins_node(&mut ins.node)
+                        }
+                    }
+                    if self.commutative_kinds.contains(&kind)
+                        || self.have_distinct_keys(&left_ins_seq, &right_ins_seq)
+                    {
+                        // Children order does not matter, keep all insertions
+                        left_ins_seq.append(&mut right_ins_seq);
+                        if self.sort_commutative_insertions {
+                            left_ins_seq = sort_insertions(left_ins_seq);
+                        }
+                        seq.push(MergedSpineSeqNode::Inserted(left_ins_seq));
+                        continue;
+                    }
+                    match self.insert_order_conflicts {
+                        Some(InsertOrderResolution::LeftFirst) => {
+                            seq.push(MergedSpineSeqNode::Inserted(left_ins_seq));
+                            seq.push(MergedSpineSeqNode::Inserted(right_ins_seq));
+                        }
+                        Some(InsertOrderResolution::RightFirst) => {
+                            seq.push(MergedSpineSeqNode::Inserted(right_ins_seq));
+                            seq.push(MergedSpineSeqNode::Inserted(left_ins_seq));
+                        }
+                        Some(InsertOrderResolution::Drop) => (),
+                        None => seq.push(MergedSpineSeqNode::InsertOrderConflict(
+                            left_ins_seq,
+                            right_ins_seq,
+                        )),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn is_leaf_ins(node: &InsNode) -> bool {
+    matches!(node, InsNode::InPlace(ins) if matches!(ins.data, Tree::Leaf(_)))
+}
+
+#[recursive]
+fn push_ins_text(node: &InsNode, text: &mut Vec<u8>) {
+    match node {
+        InsNode::InPlace(ins) => match &ins.data {
+            Tree::Node(_, children) => {
+                for ch in children {
+                    push_ins_text(&ch.node, text)
+                }
+            }
+            Tree::Leaf(tok) => text.extend_from_slice(tok.bytes()),
+        },
+        InsNode::Elided(_) => (),
+        InsNode::Inlined(repl) => push_ins_text(&repl.data, text),
+    }
+}
+
+/// Sort inserted subtrees by their text. Leaves such as spaces and separators are not sorted,
+/// they stay attached to the following subtree, or to the last one at the end of the sequence.
+fn sort_insertions(ins_seq: Vec<Subtree<InsNode>>) -> Vec<Subtree<InsNode>> {
+    let mut items: Vec<(Vec<u8>, Vec<Subtree<InsNode>>)> = Vec::new();
+    let mut pending_leaves = Vec::new();
+    for ins in ins_seq {
+        if is_leaf_ins(&ins.node) {
+            pending_leaves.push(ins);
+        } else {
+            let mut key = Vec::new();
+            push_ins_text(&ins.node, &mut key);
+            pending_leaves.push(ins);
+            items.push((key, std::mem::take(&mut pending_leaves)));
+        }
+    }
+    match items.last_mut() {
+        Some((_, last_item)) => last_item.append(&mut pending_leaves),
+        None => return pending_leaves,
+    }
+    items.sort_by(|(key1, _), (key2, _)| key1.cmp(key2));
+    items.into_iter().flat_map(|(_, item)| item).collect()
+}
+
+pub fn resolve_conflicts(tree: &mut MergedSpineNode, options: &MergeOptions) {
+    #[allow(deprecated)]
+    let ordered_insertions = options.ordered_insertions;
+    let mut resolver = ConflictResolver {
+        delete_conflicts: options.delete_conflicts,
+        ins_conflicts: options.ins_conflicts,
+        insert_order_conflicts: options
+            .insert_order_conflicts
+            .or_else(|| ordered_insertions.then_some(InsertOrderResolution::LeftFirst)),
+        commutative_kinds: &options.commutative_kinds,
+        key_fields: &options.key_fields,
+        sort_commutative_insertions: options.sort_commutative_insertions,
+        metavar_side: options
+            .metavar_conflicts
+            .map(|resolution| match resolution {
+                MetavarConflictResolution::Left => Color::Left,
+                MetavarConflictResolution::Right => Color::Right,
+            }),
+        metavar_dels: HashMap::new(),
+        metavar_subst: HashMap::new(),
+    };
+    if resolver.metavar_side.is_some() {
+        resolver.collect_metavar_conflicts_in_spine(tree);
+    }
+    resolver.resolve_in_spine_node(tree);
+}
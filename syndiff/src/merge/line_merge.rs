@@ -0,0 +1,192 @@
+use super::conflict_markers::{write_conflict_hunk, ConflictMarkerOptions, ConflictSide};
+use crate::lcs::common_subsequence;
+use std::io::Write;
+use std::ops::Range;
+
+enum LineChunk<'a> {
+    Merged(&'a [u8]),
+    Conflict {
+        base: &'a [u8],
+        left: &'a [u8],
+        right: &'a [u8],
+    },
+}
+
+/// Result of a line-based three-way merge, as computed by `diff3 -m` or `git merge-file`.
+pub struct LineMerge<'a> {
+    chunks: Vec<LineChunk<'a>>,
+}
+
+/// Lines of a text, each line keeping its end of line character
+struct Lines<'a> {
+    text: &'a [u8],
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a [u8]) -> Self {
+        let mut starts = vec![0];
+        for (pos, c) in text.iter().enumerate() {
+            if *c == b'\n' && pos + 1 < text.len() {
+                starts.push(pos + 1);
+            }
+        }
+        if text.is_empty() {
+            starts.clear();
+        }
+        Lines { text, starts }
+    }
+
+    fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    fn line(&self, index: usize) -> &'a [u8] {
+        self.range(index..index + 1)
+    }
+
+    fn range(&self, lines: Range<usize>) -> &'a [u8] {
+        let byte_pos = |index| self.starts.get(index).copied().unwrap_or(self.text.len());
+        &self.text[byte_pos(lines.start)..byte_pos(lines.end)]
+    }
+
+    fn as_vec(&self) -> Vec<&'a [u8]> {
+        (0..self.len()).map(|index| self.line(index)).collect()
+    }
+}
+
+/// For each element of `orig`, position of the matching element in `modified` if any
+fn match_elements<T: Eq>(orig: &[T], modified: &[T]) -> Vec<Option<usize>> {
+    let mut matches = vec![None; orig.len()];
+    for (orig_pos, modified_pos) in common_subsequence(orig, modified) {
+        matches[orig_pos] = Some(modified_pos);
+    }
+    matches
+}
+
+/// Aligned ranges of the three versions of a sequence in a three-way merge
+pub(super) struct MergeChunk {
+    pub orig: Range<usize>,
+    pub left: Range<usize>,
+    pub right: Range<usize>,
+}
+
+/// Split three versions of a sequence into chunks alternating between elements kept by both
+/// sides and elements changed by at least one side, like `diff3` does.
+pub(super) fn three_way_chunks<T: Eq>(orig: &[T], left: &[T], right: &[T]) -> Vec<MergeChunk> {
+    let left_matches = match_elements(orig, left);
+    let right_matches = match_elements(orig, right);
+
+    let mut chunks = Vec::new();
+    let (mut orig_pos, mut left_pos, mut right_pos) = (0, 0, 0);
+    loop {
+        // Elements kept by both sides
+        let stable_len = (orig_pos..orig.len())
+            .take_while(|&pos| {
+                left_matches[pos] == Some(left_pos + pos - orig_pos)
+                    && right_matches[pos] == Some(right_pos + pos - orig_pos)
+            })
+            .count();
+        if stable_len > 0 {
+            chunks.push(MergeChunk {
+                orig: orig_pos..orig_pos + stable_len,
+                left: left_pos..left_pos + stable_len,
+                right: right_pos..right_pos + stable_len,
+            });
+            orig_pos += stable_len;
+            left_pos += stable_len;
+            right_pos += stable_len;
+        }
+
+        // Elements changed by at least one side, up to the next element kept by both sides
+        let (orig_end, left_end, right_end) = (orig_pos..orig.len())
+            .find_map(|pos| Some((pos, left_matches[pos]?, right_matches[pos]?)))
+            .unwrap_or((orig.len(), left.len(), right.len()));
+        if (orig_end, left_end, right_end) == (orig_pos, left_pos, right_pos) {
+            break;
+        }
+        chunks.push(MergeChunk {
+            orig: orig_pos..orig_end,
+            left: left_pos..left_end,
+            right: right_pos..right_end,
+        });
+        orig_pos = orig_end;
+        left_pos = left_end;
+        right_pos = right_end;
+    }
+    chunks
+}
+
+/// Merge three versions of a text line by line. Lines changed by a single side are taken from
+/// this side, and overlapping changes of both sides become conflicts.
+pub fn merge_lines<'a>(orig: &'a [u8], left: &'a [u8], right: &'a [u8]) -> LineMerge<'a> {
+    let orig = Lines::new(orig);
+    let left = Lines::new(left);
+    let right = Lines::new(right);
+    let chunks = three_way_chunks(&orig.as_vec(), &left.as_vec(), &right.as_vec())
+        .into_iter()
+        .map(|chunk| {
+            let base = orig.range(chunk.orig);
+            let left_text = left.range(chunk.left);
+            let right_text = right.range(chunk.right);
+            if left_text == base || left_text == right_text {
+                LineChunk::Merged(right_text)
+            } else if right_text == base {
+                LineChunk::Merged(left_text)
+            } else {
+                LineChunk::Conflict {
+                    base,
+                    left: left_text,
+                    right: right_text,
+                }
+            }
+        })
+        .collect();
+    LineMerge { chunks }
+}
+
+impl<'a> LineMerge<'a> {
+    pub fn count_conflicts(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter(|chunk| matches!(chunk, LineChunk::Conflict { .. }))
+            .count()
+    }
+
+    /// Write the merged text, delimiting each conflict with standard conflict markers.
+    /// Returns the number of conflicts written.
+    pub fn write_with_conflict_markers(
+        &self,
+        options: &ConflictMarkerOptions,
+        mut output: impl Write,
+    ) -> std::io::Result<usize> {
+        for chunk in &self.chunks {
+            match chunk {
+                LineChunk::Merged(text) => output.write_all(text)?,
+                LineChunk::Conflict { base, left, right } => {
+                    write_conflict_hunk(&mut output, options, base, left, right)?
+                }
+            }
+        }
+        Ok(self.count_conflicts())
+    }
+
+    /// Write the merged text, taking the content of each conflict from the given side.
+    pub fn write_with_chosen_side(
+        &self,
+        side: ConflictSide,
+        mut output: impl Write,
+    ) -> std::io::Result<()> {
+        for chunk in &self.chunks {
+            output.write_all(match chunk {
+                LineChunk::Merged(text) => text,
+                LineChunk::Conflict { base, left, right } => match side {
+                    ConflictSide::Base => base,
+                    ConflictSide::Left => left,
+                    ConflictSide::Right => right,
+                },
+            })?
+        }
+        Ok(())
+    }
+}
@@ -2,13 +2,16 @@ use super::colors::{Color, Colored};
 use super::merge_ins::{InsMergedSpineNode, InsMergedSpineSeqNode};
 use super::{DelNode, MergedSpineNode, MergedSpineSeqNode};
 use crate::generic_tree::{Subtree, Tree};
+use crate::Error;
+use recursive::recursive;
 
+#[recursive]
 fn merge_del_nodes<'t>(
     left: DelNode<'t>,
     right: DelNode<'t>,
     metavars_del: &mut [Option<DelNode<'t>>],
-) -> Option<DelNode<'t>> {
-    Some(match (left, right) {
+) -> Result<DelNode<'t>, Error> {
+    Ok(match (left, right) {
         (DelNode::InPlace(left), DelNode::InPlace(right)) => {
             DelNode::InPlace(Colored::merge(left, right, |left, right| {
                 Tree::merge_subtrees_into(left, right, |l, r| merge_del_nodes(l, r, metavars_del))
@@ -44,11 +47,12 @@ fn merge_del_nodes<'t>(
     })
 }
 
+#[recursive]
 fn merge_del_in_spine<'t>(
     spine: InsMergedSpineNode<'t>,
     metavars_del: &mut [Option<DelNode<'t>>],
-) -> Option<MergedSpineNode<'t>> {
-    Some(match spine {
+) -> Result<MergedSpineNode<'t>, Error> {
+    Ok(match spine {
         InsMergedSpineNode::Spine(s) => MergedSpineNode::Spine(
             s.try_convert_into(|ch| merge_del_in_spine_seq(ch, metavars_del))?,
         ),
@@ -63,7 +67,7 @@ fn merge_del_in_spine<'t>(
 fn merge_del_in_spine_seq<'t>(
     spine_seq: Vec<InsMergedSpineSeqNode<'t>>,
     metavars_del: &mut [Option<DelNode<'t>>],
-) -> Option<Vec<MergedSpineSeqNode<'t>>> {
+) -> Result<Vec<MergedSpineSeqNode<'t>>, Error> {
     let mut merged_vec = Vec::new();
     for seq_node in spine_seq {
         match seq_node {
@@ -95,9 +99,10 @@ fn merge_del_in_spine_seq<'t>(
             }
         }
     }
-    Some(merged_vec)
+    Ok(merged_vec)
 }
 
+#[recursive]
 fn add_color(color: Color, node: &mut DelNode) {
     match node {
         DelNode::InPlace(del) => {
@@ -112,9 +117,9 @@ fn add_color(color: Color, node: &mut DelNode) {
 pub fn merge_del(
     input: InsMergedSpineNode,
     nb_metavars: usize,
-) -> Option<(MergedSpineNode, Vec<Option<DelNode>>)> {
+) -> Result<(MergedSpineNode, Vec<Option<DelNode>>), Error> {
     let mut metavars_del = Vec::new();
     metavars_del.resize_with(nb_metavars, || None);
     let output = merge_del_in_spine(input, &mut metavars_del)?;
-    Some((output, metavars_del))
+    Ok((output, metavars_del))
 }
@@ -2,6 +2,8 @@ use super::align_spine::{AlignedSpineNode, AlignedSpineSeqNode, InsSpineNode, In
 use super::colors::{Colored, ColoredChangeNode as ChangeNode};
 use super::{DelNode, InsNode, MergedInsNode, MetavarInsReplacement};
 use crate::generic_tree::{FieldId, Subtree, Tree};
+use crate::Error;
+use recursive::recursive;
 
 pub enum InsMergedSpineNode<'t> {
     Spine(Tree<'t, InsMergedSpineSeqNode<'t>>),
@@ -19,6 +21,7 @@ pub enum InsMergedSpineSeqNode<'t> {
 
 pub type MetavarInsReplacementList<'t> = Vec<MetavarInsReplacement<'t>>;
 
+#[recursive]
 fn merge_ins_nodes<'t>(left: InsNode<'t>, right: InsNode<'t>) -> MergedInsNode<'t> {
     match (left, right) {
         (InsNode::InPlace(left_node), InsNode::InPlace(right_node))
@@ -26,7 +29,7 @@ fn merge_ins_nodes<'t>(left: InsNode<'t>, right: InsNode<'t>) -> MergedInsNode<'
         {
             MergedInsNode::InPlace(
                 Tree::merge_subtrees_into(left_node.data, right_node.data, |l, r| {
-                    Some(merge_ins_nodes(l, r))
+                    Ok(merge_ins_nodes(l, r))
                 })
                 .unwrap(),
             )
@@ -35,6 +38,7 @@ fn merge_ins_nodes<'t>(left: InsNode<'t>, right: InsNode<'t>) -> MergedInsNode<'
     }
 }
 
+#[recursive]
 fn flatten_ins_spine(ins_spine: InsSpineNode) -> InsNode {
     match ins_spine {
         InsSpineNode::Spine(ins_subtree) => InsNode::InPlace(Colored::new_white(
@@ -63,6 +67,7 @@ fn flatten_ins_spine_seq(ins_spine_seq: Vec<InsSpineSeqNode>) -> Vec<Subtree<Ins
     ins_seq
 }
 
+#[recursive]
 fn can_inline_ins_in_del(
     ins: &InsSpineNode,
     del: &ChangeNode,
@@ -104,6 +109,7 @@ fn can_inline_ins_seq_in_del(
         })
 }
 
+#[recursive]
 fn inline_ins_in_del<'t>(
     ins: InsSpineNode<'t>,
     del: ChangeNode<'t>,
@@ -140,24 +146,21 @@ fn inline_ins_seq_in_del<'t>(
     ins_spine_seq: Vec<InsSpineSeqNode<'t>>,
     del_seq: Vec<Subtree<ChangeNode<'t>>>,
     metavars_status: &mut [MetavarInsReplacementList<'t>],
-) -> Option<Vec<Subtree<DelNode<'t>>>> {
-    Some(
-        ins_spine_seq
-            .into_iter()
-            .zip(del_seq)
-            .map(|(ins_spine_seq_node, del)| match ins_spine_seq_node {
-                InsSpineSeqNode::Zipped(ins_spine) => {
-                    del.map(|del| inline_ins_in_del(ins_spine.node, del, metavars_status))
-                }
-                InsSpineSeqNode::Deleted => {
-                    del.map(|del| register_kept_metavars(del, metavars_status))
-                }
-                _ => panic!("InsSpineSeq cannot be inlined in the deletion tree"),
-            })
-            .collect(),
-    )
+) -> Result<Vec<Subtree<DelNode<'t>>>, Error> {
+    Ok(ins_spine_seq
+        .into_iter()
+        .zip(del_seq)
+        .map(|(ins_spine_seq_node, del)| match ins_spine_seq_node {
+            InsSpineSeqNode::Zipped(ins_spine) => {
+                del.map(|del| inline_ins_in_del(ins_spine.node, del, metavars_status))
+            }
+            InsSpineSeqNode::Deleted => del.map(|del| register_kept_metavars(del, metavars_status)),
+            _ => panic!("InsSpineSeq cannot be inlined in the deletion tree"),
+        })
+        .collect())
 }
 
+#[recursive]
 fn register_kept_metavars<'t>(
     del: ChangeNode<'t>,
     metavars_status: &mut [MetavarInsReplacementList<'t>],
@@ -179,6 +182,7 @@ fn register_kept_metavars<'t>(
     }
 }
 
+#[recursive]
 fn merge_ins_in_spine<'t>(
     node: AlignedSpineNode<'t>,
     metavars_status: &mut [MetavarInsReplacementList<'t>],
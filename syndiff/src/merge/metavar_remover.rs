@@ -4,7 +4,8 @@ use super::{
     DelNode, InsNode, MergedInsNode, MergedSpineNode, MergedSpineSeqNode, MetavarInsReplacement,
 };
 use crate::generic_tree::{Subtree, Tree};
-use crate::{Metavariable, SynNode};
+use crate::{Error, Metavariable, SynNode};
+use recursive::recursive;
 
 struct MetavarRemover<'t> {
     metavar_replacements: Vec<Option<InsNode<'t>>>,
@@ -14,32 +15,36 @@ struct MetavarRemover<'t> {
 fn merge_with_syn<'t, T>(
     tree: Tree<'t, T>,
     source: &SynNode<'t>,
-    merge_child_fn: impl FnOnce(Vec<T>, &[Subtree<SynNode<'t>>]) -> Option<Vec<T>>,
-) -> Option<Tree<'t, T>> {
+    merge_child_fn: impl FnOnce(Vec<T>, &[Subtree<SynNode<'t>>]) -> Result<Vec<T>, Error>,
+) -> Result<Tree<'t, T>, Error> {
     match (tree, &source.0) {
         (Tree::Node(tree_kind, tree_ch), Tree::Node(source_kind, source_ch))
             if tree_kind == *source_kind =>
         {
-            Some(Tree::Node(tree_kind, merge_child_fn(tree_ch, source_ch)?))
+            Ok(Tree::Node(
+                tree_kind,
+                merge_child_fn(tree_ch.into_vec(), source_ch)?.into(),
+            ))
         }
         (Tree::Leaf(tree_tok), Tree::Leaf(source_tok)) if tree_tok == *source_tok => {
-            Some(Tree::Leaf(tree_tok))
+            Ok(Tree::Leaf(tree_tok))
         }
-        _ => None,
+        _ => Err(Error::SpineKindMismatch),
     }
 }
 
 impl<'t> MetavarRemover<'t> {
+    #[recursive]
     fn remove_metavars_in_del_node(
         &mut self,
         del: DelNode<'t>,
         source: &SynNode<'t>,
-    ) -> Option<DelNode<'t>> {
-        Some(match del {
+    ) -> Result<DelNode<'t>, Error> {
+        Ok(match del {
             DelNode::InPlace(d) => DelNode::InPlace(Colored {
                 data: merge_with_syn(d.data, source, |del_ch, src_ch| {
                     if del_ch.len() != src_ch.len() {
-                        return None;
+                        return Err(Error::FieldMismatch);
                     }
                     del_ch
                         .into_iter()
@@ -86,12 +91,13 @@ impl<'t> MetavarRemover<'t> {
         })
     }
 
+    #[recursive]
     fn remove_metavars_in_spine_node(
         &mut self,
         diff: MergedSpineNode<'t>,
         source: &SynNode<'t>,
-    ) -> Option<MergedSpineNode<'t>> {
-        Some(match diff {
+    ) -> Result<MergedSpineNode<'t>, Error> {
+        Ok(match diff {
             MergedSpineNode::Spine(spine) => {
                 MergedSpineNode::Spine(merge_with_syn(spine, source, |spine_ch, source_ch| {
                     self.remove_metavars_in_spine_seq(spine_ch, source_ch)
@@ -108,14 +114,14 @@ impl<'t> MetavarRemover<'t> {
         &mut self,
         spine_seq: Vec<MergedSpineSeqNode<'t>>,
         source_seq: &[Subtree<SynNode<'t>>],
-    ) -> Option<Vec<MergedSpineSeqNode<'t>>> {
+    ) -> Result<Vec<MergedSpineSeqNode<'t>>, Error> {
         let mut source_iter = source_seq.iter();
         let result_seq = spine_seq
             .into_iter()
             .map(|diff_node| {
-                Some(match diff_node {
+                Ok(match diff_node {
                     MergedSpineSeqNode::Zipped(node) => {
-                        let source_node = source_iter.next()?;
+                        let source_node = source_iter.next().ok_or(Error::FieldMismatch)?;
                         MergedSpineSeqNode::Zipped(Subtree::merge(
                             node,
                             source_node.as_ref(),
@@ -126,17 +132,17 @@ impl<'t> MetavarRemover<'t> {
                         del_list
                             .into_iter()
                             .map(|del| {
-                                let source_node = source_iter.next()?;
+                                let source_node = source_iter.next().ok_or(Error::FieldMismatch)?;
                                 Subtree::merge(del, source_node.as_ref(), |del, source| {
                                     self.remove_metavars_in_del_node(del, source)
                                 })
                             })
-                            .collect::<Option<_>>()?,
+                            .collect::<Result<_, _>>()?,
                     ),
                     MergedSpineSeqNode::DeleteConflict(field, del, ins) => {
-                        let source_node = source_iter.next()?;
+                        let source_node = source_iter.next().ok_or(Error::FieldMismatch)?;
                         if source_node.field != field {
-                            return None;
+                            return Err(Error::FieldMismatch);
                         }
                         MergedSpineSeqNode::DeleteConflict(
                             field,
@@ -154,7 +160,7 @@ impl<'t> MetavarRemover<'t> {
         if source_iter.next().is_none() {
             result_seq
         } else {
-            None
+            Err(Error::FieldMismatch)
         }
     }
 
@@ -175,6 +181,7 @@ impl<'t> MetavarRemover<'t> {
         }
     }
 
+    #[recursive]
     fn replace_metavars_in_ins_node(&self, node: &mut InsNode<'t>) {
         match node {
             InsNode::InPlace(ins) => ins
@@ -185,6 +192,7 @@ impl<'t> MetavarRemover<'t> {
         }
     }
 
+    #[recursive]
     fn replace_metavars_in_merged_ins_node(&self, node: &mut MergedInsNode<'t>) {
         match node {
             MergedInsNode::InPlace(ins) => {
@@ -202,6 +210,7 @@ impl<'t> MetavarRemover<'t> {
         }
     }
 
+    #[recursive]
     fn replace_metavars_in_del_node(&self, node: &mut DelNode<'t>) {
         match node {
             DelNode::InPlace(del) => del
@@ -218,6 +227,7 @@ impl<'t> MetavarRemover<'t> {
         }
     }
 
+    #[recursive]
     fn replace_metavars_in_spine_node(&self, node: &mut MergedSpineNode<'t>) {
         match node {
             MergedSpineNode::Spine(spine) => {
@@ -264,7 +274,7 @@ impl<'t> MetavarRemover<'t> {
 pub fn remove_metavars<'t>(
     diff: MergedSpineNode<'t>,
     source: &SynNode<'t>,
-) -> Option<MergedSpineNode<'t>> {
+) -> Result<MergedSpineNode<'t>, Error> {
     let mut remover = MetavarRemover {
         metavar_replacements: Vec::new(),
         metavar_conflict: Vec::new(),
@@ -3,6 +3,7 @@ use super::{
     DelNode, InsNode, MergedInsNode, MergedSpineNode, MergedSpineSeqNode, MetavarInsReplacement,
 };
 use crate::Metavariable;
+use recursive::recursive;
 
 pub struct MetavarRenamer {
     new_metavars: Vec<Option<Metavariable>>,
@@ -22,6 +23,7 @@ impl MetavarRenamer {
     }
 }
 
+#[recursive]
 fn rename_metavars_in_change(change: &mut ColoredChangeNode, renamer: &mut MetavarRenamer) {
     match change {
         ColoredChangeNode::InPlace(change) => change
@@ -31,6 +33,7 @@ fn rename_metavars_in_change(change: &mut ColoredChangeNode, renamer: &mut Metav
     }
 }
 
+#[recursive]
 fn rename_metavars_in_diff_spine(spine: &mut ColoredSpineNode, renamer: &mut MetavarRenamer) {
     match spine {
         ColoredSpineNode::Spine(spine) => {
@@ -65,6 +68,7 @@ fn rename_metavars_in_diff_spine_subtree(
     }
 }
 
+#[recursive]
 fn rename_metavars_in_del(del: &mut DelNode, renamer: &mut MetavarRenamer) {
     match del {
         DelNode::InPlace(del) => del
@@ -81,6 +85,7 @@ fn rename_metavars_in_del(del: &mut DelNode, renamer: &mut MetavarRenamer) {
     }
 }
 
+#[recursive]
 fn rename_metavars_in_ins(ins: &mut InsNode, renamer: &mut MetavarRenamer) {
     match ins {
         InsNode::InPlace(ins) => ins
@@ -91,6 +96,7 @@ fn rename_metavars_in_ins(ins: &mut InsNode, renamer: &mut MetavarRenamer) {
     }
 }
 
+#[recursive]
 fn rename_metavars_in_merged_ins(ins: &mut MergedInsNode, renamer: &mut MetavarRenamer) {
     match ins {
         MergedInsNode::InPlace(ins) => {
@@ -105,6 +111,7 @@ fn rename_metavars_in_merged_ins(ins: &mut MergedInsNode, renamer: &mut MetavarR
     }
 }
 
+#[recursive]
 fn rename_metavars_in_merged_spine(spine: &mut MergedSpineNode, renamer: &mut MetavarRenamer) {
     match spine {
         MergedSpineNode::Spine(spine) => {
@@ -160,9 +167,16 @@ pub fn rename_metavars(input: &mut ColoredSpineNode, first_metavar: usize) -> us
 }
 
 pub fn canonicalize_metavars(input: &mut MergedSpineNode) {
+    rename_merged_metavars(input, 0);
+}
+
+/// Number the metavariables of a merged tree in order of appearance from `first_metavar`, and
+/// return the first metavariable left unused
+pub(super) fn rename_merged_metavars(input: &mut MergedSpineNode, first_metavar: usize) -> usize {
     let mut renamer = MetavarRenamer {
         new_metavars: Vec::new(),
-        next_metavar: 0,
+        next_metavar: first_metavar,
     };
     rename_metavars_in_merged_spine(input, &mut renamer);
+    renamer.next_metavar
 }
@@ -1,6 +1,11 @@
 mod align_spine;
+mod chunked;
 mod colors;
 mod conflict_counter;
+mod conflict_markers;
+mod conflict_refiner;
+mod conflict_resolver;
+mod line_merge;
 mod merge_del;
 mod merge_ins;
 mod metavar_remover;
@@ -9,8 +14,22 @@ mod patch;
 mod subst;
 mod tree;
 
-pub use colors::{Color, Colored, ColoredSpineNode};
-pub use conflict_counter::count_conflicts;
+pub use chunked::merge_chunked;
+pub use colors::{Color, ColoredSpineNode};
+pub use conflict_counter::{
+    count_conflicts, report_conflicts, ConflictInfo, ConflictKind, ConflictReport, NamedAncestor,
+    SourceRange,
+};
+pub use conflict_markers::{
+    write_with_chosen_side, write_with_conflict_markers, ConflictMarkerOptions, ConflictSide,
+    ConflictStyle,
+};
+pub use conflict_refiner::{refine_conflicts, ConflictRefinement};
+pub use conflict_resolver::{
+    DeleteConflictResolution, InsConflictResolution, InsertOrderResolution,
+    MetavarConflictResolution,
+};
+pub use line_merge::{merge_lines, LineMerge};
 pub use metavar_remover::remove_metavars;
 pub use metavar_renamer::canonicalize_metavars;
 pub use patch::apply_patch;
@@ -18,24 +37,48 @@ pub use tree::{
     DelNode, InsNode, MergedInsNode, MergedSpineNode, MergedSpineSeqNode, MetavarInsReplacement,
 };
 
-use crate::DiffSpineNode;
+use crate::generic_tree::{FieldId, NodeKind};
+use crate::{DiffSpineNode, Error};
 use align_spine::align_spines;
+use conflict_resolver::resolve_conflicts;
 use merge_del::merge_del;
 use merge_ins::merge_ins;
 use metavar_renamer::rename_metavars;
+use std::collections::{HashMap, HashSet};
 use subst::apply_metavar_substitutions;
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct MergeOptions {
     pub allow_nested_deletions: bool,
+    /// How to resolve subtrees deleted by one side and modified by the other, kept as conflicts
+    /// if `None`
+    pub delete_conflicts: Option<DeleteConflictResolution>,
+    /// How to resolve subtrees replaced differently by both sides
+    pub ins_conflicts: Option<InsConflictResolution>,
+    /// How to resolve moved subtrees with incompatible modifications
+    pub metavar_conflicts: Option<MetavarConflictResolution>,
+    /// How to resolve concurrent insertions at the same place
+    pub insert_order_conflicts: Option<InsertOrderResolution>,
+    /// Keep concurrent insertions at the same place, left first, when `insert_order_conflicts`
+    /// is `None`
+    #[deprecated(note = "use `insert_order_conflicts: Some(InsertOrderResolution::LeftFirst)`")]
     pub ordered_insertions: bool,
+    /// Kinds of the nodes whose children are unordered. Concurrent insertions inside them are
+    /// all kept, left first, instead of creating insert order conflicts.
+    pub commutative_kinds: HashSet<NodeKind>,
+    /// Field holding the key of the nodes of each kind. Concurrent insertions of nodes with
+    /// different keys are all kept, left first, instead of creating insert order conflicts.
+    pub key_fields: HashMap<NodeKind, FieldId>,
+    /// Sort by their text the concurrent insertions kept inside commutative nodes or with
+    /// different keys
+    pub sort_commutative_insertions: bool,
 }
 
 pub fn merge_diffs<'t>(
     left: &DiffSpineNode<'t>,
     right: &DiffSpineNode<'t>,
     options: MergeOptions,
-) -> Option<MergedSpineNode<'t>> {
+) -> Result<MergedSpineNode<'t>, Error> {
     let mut left = ColoredSpineNode::with_color(left, Color::Left);
     let mut right = ColoredSpineNode::with_color(right, Color::Right);
     let left_end_mv = rename_metavars(&mut left, 0);
@@ -44,12 +87,8 @@ pub fn merge_diffs<'t>(
 
     let (ins_merged, ins_subst) = merge_ins(aligned, nb_metavars, options.allow_nested_deletions);
     let (mut merged, del_subst) = merge_del(ins_merged, nb_metavars)?;
-    apply_metavar_substitutions(
-        &mut merged,
-        del_subst,
-        ins_subst,
-        options.ordered_insertions,
-    );
+    apply_metavar_substitutions(&mut merged, del_subst, ins_subst);
+    resolve_conflicts(&mut merged, &options);
 
-    Some(merged)
+    Ok(merged)
 }
@@ -1,62 +1,71 @@
 use super::metavar_remover::remove_metavars;
 use super::{InsNode, MergedInsNode, MergedSpineNode, MergedSpineSeqNode};
 use crate::generic_tree::Subtree;
-use crate::SynNode;
+use crate::{Error, SynNode};
+use recursive::recursive;
 
-fn standalone_ins_to_syn(node: InsNode) -> Option<SynNode> {
+#[recursive]
+fn standalone_ins_to_syn(node: InsNode) -> Result<SynNode, Error> {
     match node {
-        InsNode::InPlace(ins) => Some(SynNode(ins.data.try_convert_into(|ch| {
+        InsNode::InPlace(ins) => Ok(SynNode(ins.data.try_convert_into(|ch| {
             ch.into_iter()
                 .map(|sub| sub.try_map(standalone_ins_to_syn))
                 .collect()
         })?)),
         InsNode::Inlined(repl) => standalone_ins_to_syn(*repl.data),
-        InsNode::Elided(_) => None,
+        InsNode::Elided(_) => Err(Error::UnresolvedConflict),
     }
 }
 
-fn standalone_merged_ins_to_syn(node: MergedInsNode) -> Option<SynNode> {
+#[recursive]
+fn standalone_merged_ins_to_syn(node: MergedInsNode) -> Result<SynNode, Error> {
     match node {
-        MergedInsNode::InPlace(ins) => Some(SynNode(ins.try_convert_into(|ch| {
+        MergedInsNode::InPlace(ins) => Ok(SynNode(ins.try_convert_into(|ch| {
             ch.into_iter()
                 .map(|sub| sub.try_map(standalone_merged_ins_to_syn))
                 .collect()
         })?)),
         MergedInsNode::SingleIns(ins) => standalone_ins_to_syn(ins),
-        MergedInsNode::Elided(_) | MergedInsNode::Conflict(..) => None,
+        MergedInsNode::Elided(_) | MergedInsNode::Conflict(..) => Err(Error::UnresolvedConflict),
     }
 }
 
-fn keep_only_ins_from_standalone_spine(spine: MergedSpineNode) -> Option<SynNode> {
+#[recursive]
+fn keep_only_ins_from_standalone_spine(spine: MergedSpineNode) -> Result<SynNode, Error> {
     match spine {
-        MergedSpineNode::Spine(spine) => Some(SynNode(
+        MergedSpineNode::Spine(spine) => Ok(SynNode(
             spine.try_convert_into(keep_only_ins_from_standalone_spine_seq)?,
         )),
-        MergedSpineNode::Unchanged => None,
+        MergedSpineNode::Unchanged => Err(Error::UnresolvedConflict),
         MergedSpineNode::Changed(_, ins) => standalone_merged_ins_to_syn(ins),
     }
 }
 
 fn keep_only_ins_from_standalone_spine_seq<'t>(
     seq: Vec<MergedSpineSeqNode<'t>>,
-) -> Option<Vec<Subtree<SynNode<'t>>>> {
+) -> Result<Vec<Subtree<SynNode<'t>>>, Error> {
     seq.into_iter()
-        .flat_map::<Box<dyn Iterator<Item = Option<Subtree<SynNode<'t>>>>>, _>(|node| match node {
-            MergedSpineSeqNode::Zipped(spine) => Box::new(std::iter::once(
-                spine.try_map(keep_only_ins_from_standalone_spine),
-            )),
-            MergedSpineSeqNode::Deleted(_) => Box::new(std::iter::empty()),
-            MergedSpineSeqNode::Inserted(ins_list) => Box::new(
-                ins_list
-                    .into_iter()
-                    .map(|sub| sub.try_map(standalone_ins_to_syn)),
-            ),
-            _ => Box::new(std::iter::once(None)),
+        .flat_map::<Box<dyn Iterator<Item = Result<Subtree<SynNode<'t>>, Error>>>, _>(|node| {
+            match node {
+                MergedSpineSeqNode::Zipped(spine) => Box::new(std::iter::once(
+                    spine.try_map(keep_only_ins_from_standalone_spine),
+                )),
+                MergedSpineSeqNode::Deleted(_) => Box::new(std::iter::empty()),
+                MergedSpineSeqNode::Inserted(ins_list) => Box::new(
+                    ins_list
+                        .into_iter()
+                        .map(|sub| sub.try_map(standalone_ins_to_syn)),
+                ),
+                _ => Box::new(std::iter::once(Err(Error::UnresolvedConflict))),
+            }
         })
         .collect()
 }
 
-pub fn apply_patch<'t>(diff: MergedSpineNode<'t>, source: &SynNode<'t>) -> Option<SynNode<'t>> {
+pub fn apply_patch<'t>(
+    diff: MergedSpineNode<'t>,
+    source: &SynNode<'t>,
+) -> Result<SynNode<'t>, Error> {
     let standalone_diff = remove_metavars(diff, source)?;
     keep_only_ins_from_standalone_spine(standalone_diff)
 }
@@ -4,7 +4,8 @@ use super::{
     DelNode, InsNode, MergedInsNode, MergedSpineNode, MergedSpineSeqNode, MetavarInsReplacement,
 };
 use crate::generic_tree::{Subtree, Tree};
-use crate::Metavariable;
+use crate::{Error, Metavariable};
+use recursive::recursive;
 
 enum ComputableSubst<T, U> {
     Pending(U),
@@ -37,6 +38,7 @@ impl<'t> Substituter<'t> {
     }
 
     // Warning: The colors on the returned tree are arbitrary and should be replaced or discarded
+    #[recursive]
     fn find_del_subst(&mut self, mv: Metavariable) -> DelNode<'t> {
         let repl = match std::mem::replace(&mut self.del_subst[mv.0], ComputableSubst::Processing) {
             ComputableSubst::Computed(repl_del) => repl_del,
@@ -55,6 +57,7 @@ impl<'t> Substituter<'t> {
         repl
     }
 
+    #[recursive]
     fn find_ins_subst(&mut self, mv: Colored<Metavariable>) -> InsNode<'t> {
         let subst =
             match std::mem::replace(&mut self.ins_subst[mv.data.0], ComputableSubst::Processing) {
@@ -82,6 +85,7 @@ impl<'t> Substituter<'t> {
                         repl_ins
                             .into_iter()
                             .try_fold(last_ins, |acc, ins| merge_id_ins(&acc, &ins))
+                            .ok()
                     } else {
                         None
                     }
@@ -127,6 +131,7 @@ impl<'t> Substituter<'t> {
         }
     }
 
+    #[recursive]
     fn substitute_in_del_node(&mut self, node: &mut DelNode<'t>) {
         match node {
             DelNode::InPlace(del) => del
@@ -144,6 +149,7 @@ impl<'t> Substituter<'t> {
         }
     }
 
+    #[recursive]
     fn substitute_in_ins_node(&mut self, node: &mut InsNode<'t>) {
         match node {
             InsNode::InPlace(ins) => ins
@@ -154,6 +160,7 @@ impl<'t> Substituter<'t> {
         }
     }
 
+    #[recursive]
     fn substitute_in_merged_ins_node(&mut self, node: &mut MergedInsNode<'t>) {
         match node {
             MergedInsNode::InPlace(ins) => {
@@ -168,23 +175,18 @@ impl<'t> Substituter<'t> {
                 self.substitute_in_ins_node(right_ins);
 
                 // Try to solve the insertion conflict after substitution
-                if let Some(merged_ins) = merge_id_ins(left_ins, right_ins) {
+                if let Ok(merged_ins) = merge_id_ins(left_ins, right_ins) {
                     *node = MergedInsNode::SingleIns(merged_ins)
                 }
             }
         }
     }
 
-    fn substitute_in_spine_node(
-        &mut self,
-        node: &mut MergedSpineNode<'t>,
-        ordered_insertions: bool,
-    ) {
+    #[recursive]
+    fn substitute_in_spine_node(&mut self, node: &mut MergedSpineNode<'t>) {
         match node {
             MergedSpineNode::Spine(spine) => match spine {
-                Tree::Node(_, children) => {
-                    self.substitute_in_spine_seq(children, ordered_insertions)
-                }
+                Tree::Node(_, children) => self.substitute_in_spine_seq(children),
                 Tree::Leaf(_) => (),
             },
             MergedSpineNode::Unchanged => (),
@@ -195,15 +197,11 @@ impl<'t> Substituter<'t> {
         }
     }
 
-    fn substitute_in_spine_seq(
-        &mut self,
-        seq: &mut Vec<MergedSpineSeqNode<'t>>,
-        ordered_insertions: bool,
-    ) {
+    fn substitute_in_spine_seq(&mut self, seq: &mut Vec<MergedSpineSeqNode<'t>>) {
         for node in std::mem::take(seq) {
             match node {
                 MergedSpineSeqNode::Zipped(mut spine) => {
-                    self.substitute_in_spine_node(&mut spine.node, ordered_insertions);
+                    self.substitute_in_spine_node(&mut spine.node);
                     seq.push(MergedSpineSeqNode::Zipped(spine))
                 }
                 MergedSpineSeqNode::Deleted(mut del_seq) => {
@@ -253,16 +251,12 @@ impl<'t> Substituter<'t> {
                             .map(|(l, r)| Subtree::merge(l.as_ref(), r.as_ref(), merge_id_ins))
                             .collect()
                     } else {
-                        None
+                        Err(Error::FieldMismatch)
                     } {
-                        Some(merged_ins_seq) => {
+                        Ok(merged_ins_seq) => {
                             seq.push(MergedSpineSeqNode::Inserted(merged_ins_seq))
                         }
-                        None if ordered_insertions => {
-                            seq.push(MergedSpineSeqNode::Inserted(left_ins_seq));
-                            seq.push(MergedSpineSeqNode::Inserted(right_ins_seq));
-                        }
-                        None => seq.push(MergedSpineSeqNode::InsertOrderConflict(
+                        Err(_) => seq.push(MergedSpineSeqNode::InsertOrderConflict(
                             left_ins_seq,
                             right_ins_seq,
                         )),
@@ -272,6 +266,7 @@ impl<'t> Substituter<'t> {
         }
     }
 
+    #[recursive]
     fn remove_solved_conflicts_in_del(&mut self, node: &mut DelNode<'t>) {
         match node {
             DelNode::InPlace(del) => del
@@ -314,6 +309,7 @@ impl<'t> Substituter<'t> {
         }
     }
 
+    #[recursive]
     fn remove_solved_conflicts_in_spine_node(&mut self, node: &mut MergedSpineNode<'t>) {
         match node {
             MergedSpineNode::Spine(spine) => {
@@ -342,6 +338,7 @@ impl<'t> Substituter<'t> {
     }
 }
 
+#[recursive]
 fn replace_color(node: &mut DelNode, color: Color) {
     match node {
         DelNode::InPlace(del) => {
@@ -354,7 +351,8 @@ fn replace_color(node: &mut DelNode, color: Color) {
     }
 }
 
-fn infer_ins_from_del<'t>(del: &DelNode<'t>) -> InsNode<'t> {
+#[recursive]
+pub fn infer_ins_from_del<'t>(del: &DelNode<'t>) -> InsNode<'t> {
     match del {
         DelNode::InPlace(del) => InsNode::InPlace(Colored::new_white(
             del.data
@@ -365,33 +363,35 @@ fn infer_ins_from_del<'t>(del: &DelNode<'t>) -> InsNode<'t> {
     }
 }
 
-pub fn merge_id_ins<'t>(left: &InsNode<'t>, right: &InsNode<'t>) -> Option<InsNode<'t>> {
+#[recursive]
+pub fn merge_id_ins<'t>(left: &InsNode<'t>, right: &InsNode<'t>) -> Result<InsNode<'t>, Error> {
     match (left, right) {
-        (InsNode::InPlace(left), InsNode::InPlace(right)) => Some(InsNode::InPlace(
-            Colored::merge(left.as_ref(), right.as_ref(), |l, r| {
-                Tree::merge_subtrees_to(l, r, merge_id_ins)
-            })?,
-        )),
-        (InsNode::Elided(left), InsNode::Elided(right)) => Some(InsNode::Elided(Colored::merge(
+        (InsNode::InPlace(left), InsNode::InPlace(right)) => Ok(InsNode::InPlace(Colored::merge(
+            left.as_ref(),
+            right.as_ref(),
+            |l, r| Tree::merge_subtrees_to(l, r, merge_id_ins),
+        )?)),
+        (InsNode::Elided(left), InsNode::Elided(right)) => Ok(InsNode::Elided(Colored::merge(
             *left,
             *right,
             |left_mv, right_mv| {
                 if left_mv == right_mv {
-                    Some(left_mv)
+                    Ok(left_mv)
                 } else {
-                    None
+                    Err(Error::SpineKindMismatch)
                 }
             },
         )?)),
-        (InsNode::Inlined(left_repl), InsNode::Inlined(right_repl)) => Some(InsNode::Inlined(
+        (InsNode::Inlined(left_repl), InsNode::Inlined(right_repl)) => Ok(InsNode::Inlined(
             Colored::merge(left_repl.as_ref(), right_repl.as_ref(), |l, r| {
-                Some(Box::new(merge_id_ins(l, r)?))
+                Ok(Box::new(merge_id_ins(l, r)?))
             })?,
         )),
-        _ => None,
+        _ => Err(Error::SpineKindMismatch),
     }
 }
 
+#[recursive]
 fn is_del_equivalent_to_ins(del: &DelNode, ins: &InsNode) -> bool {
     match (del, ins) {
         (DelNode::InPlace(del), InsNode::InPlace(ins)) => {
@@ -410,9 +410,8 @@ pub fn apply_metavar_substitutions<'t>(
     tree: &mut MergedSpineNode<'t>,
     del_subst: Vec<Option<DelNode<'t>>>,
     ins_subst: Vec<MetavarInsReplacementList<'t>>,
-    ordered_insertions: bool,
 ) {
     let mut subst = Substituter::new(del_subst, ins_subst);
-    subst.substitute_in_spine_node(tree, ordered_insertions);
+    subst.substitute_in_spine_node(tree);
     subst.remove_solved_conflicts_in_spine_node(tree);
 }
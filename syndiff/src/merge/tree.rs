@@ -3,6 +3,7 @@ use crate::generic_tree::{FieldId, Subtree, Tree};
 use crate::syn_tree::SynNode;
 use crate::tree_formatter::{TreeFormattable, TreeFormatter};
 use crate::Metavariable;
+use recursive::recursive;
 
 #[derive(Clone)]
 pub enum MetavarInsReplacement<'t> {
@@ -46,6 +47,7 @@ pub enum MergedSpineSeqNode<'t> {
 }
 
 impl<'t> DelNode<'t> {
+    #[recursive]
     pub fn from_syn(tree: &SynNode<'t>, color: Color) -> Self {
         DelNode::InPlace(Colored {
             data: tree.0.map_subtrees(|sub| DelNode::from_syn(sub, color)),
@@ -74,6 +76,7 @@ impl<'t> TreeFormattable for DelNode<'t> {
 }
 
 impl<'t> From<&SynNode<'t>> for InsNode<'t> {
+    #[recursive]
     fn from(tree: &SynNode<'t>) -> Self {
         InsNode::InPlace(Colored::new_white(
             tree.0.map_subtrees(|sub| InsNode::from(sub)),
@@ -82,6 +85,7 @@ impl<'t> From<&SynNode<'t>> for InsNode<'t> {
 }
 
 impl<'t> From<ChangeNode<'t>> for InsNode<'t> {
+    #[recursive]
     fn from(tree: ChangeNode<'t>) -> Self {
         match tree {
             ChangeNode::InPlace(node) => {
@@ -121,6 +125,7 @@ impl<'t> TreeFormattable for MergedInsNode<'t> {
 }
 
 impl<'t> From<&SynNode<'t>> for MergedSpineNode<'t> {
+    #[recursive]
     fn from(tree: &SynNode<'t>) -> Self {
         MergedSpineNode::Spine(tree.0.map_children(|sub| {
             MergedSpineSeqNode::Zipped(sub.as_ref().map(MergedSpineNode::from))
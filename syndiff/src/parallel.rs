@@ -0,0 +1,90 @@
+//! Independent computations, run on all threads with the `parallel` feature and one after the
+//! other without it. Both versions require the same bounds, so that the code using them builds
+//! the same way with or without the feature.
+
+/// Bound of the values shared by parallel computations: `Sync` with the `parallel` feature,
+/// implemented by every type without it
+#[cfg(feature = "parallel")]
+pub trait MaybeSync: Sync {}
+#[cfg(feature = "parallel")]
+impl<T: Sync + ?Sized> MaybeSync for T {}
+
+/// Bound of the values shared by parallel computations: `Sync` with the `parallel` feature,
+/// implemented by every type without it
+#[cfg(not(feature = "parallel"))]
+pub trait MaybeSync {}
+#[cfg(not(feature = "parallel"))]
+impl<T: ?Sized> MaybeSync for T {}
+
+/// Bound of the values moved to other threads by parallel computations: `Send` with the
+/// `parallel` feature, implemented by every type without it
+#[cfg(feature = "parallel")]
+pub trait MaybeSend: Send {}
+#[cfg(feature = "parallel")]
+impl<T: Send + ?Sized> MaybeSend for T {}
+
+/// Bound of the values moved to other threads by parallel computations: `Send` with the
+/// `parallel` feature, implemented by every type without it
+#[cfg(not(feature = "parallel"))]
+pub trait MaybeSend {}
+#[cfg(not(feature = "parallel"))]
+impl<T: ?Sized> MaybeSend for T {}
+
+/// Run two closures, potentially in parallel, and return both of their results
+#[cfg(feature = "parallel")]
+pub fn join<A, B, RA, RB>(first: A, second: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + MaybeSend,
+    B: FnOnce() -> RB + MaybeSend,
+    RA: MaybeSend,
+    RB: MaybeSend,
+{
+    rayon::join(first, second)
+}
+
+/// Run two closures, potentially in parallel, and return both of their results
+#[cfg(not(feature = "parallel"))]
+pub fn join<A, B, RA, RB>(first: A, second: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + MaybeSend,
+    B: FnOnce() -> RB + MaybeSend,
+    RA: MaybeSend,
+    RB: MaybeSend,
+{
+    (first(), second())
+}
+
+/// Apply `map_fn` to every item of a slice, potentially in parallel, and collect the results in
+/// order
+#[cfg(feature = "parallel")]
+pub fn map_slice<T, U>(items: &[T], map_fn: impl Fn(&T) -> U + MaybeSync + MaybeSend) -> Vec<U>
+where
+    T: MaybeSync,
+    U: MaybeSend,
+{
+    use rayon::prelude::*;
+    items.par_iter().map(map_fn).collect()
+}
+
+/// Apply `map_fn` to every item of a slice, potentially in parallel, and collect the results in
+/// order
+#[cfg(not(feature = "parallel"))]
+pub fn map_slice<T, U>(items: &[T], map_fn: impl Fn(&T) -> U + MaybeSync + MaybeSend) -> Vec<U>
+where
+    T: MaybeSync,
+    U: MaybeSend,
+{
+    items.iter().map(map_fn).collect()
+}
+
+/// Number of threads running the parallel computations
+#[cfg(feature = "parallel")]
+pub fn nb_threads() -> usize {
+    rayon::current_num_threads()
+}
+
+/// Number of threads running the parallel computations
+#[cfg(not(feature = "parallel"))]
+pub fn nb_threads() -> usize {
+    1
+}
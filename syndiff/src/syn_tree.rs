@@ -1,6 +1,11 @@
-use crate::generic_tree::{NodeKind, Subtree, Token, Tree};
-use crate::tree_formatter::{TreeFormattable, TreeFormatter};
-use tree_sitter::Parser;
+use crate::generic_tree::{FieldId, NodeKind, Subtree, Token, Tree};
+use crate::tree_formatter::{PlainTreeFormatter, TreeFormattable, TreeFormatter};
+use crate::Error;
+use recursive::recursive;
+use std::cmp::min;
+use std::fmt;
+use std::ops::Range;
+use tree_sitter::{Language, Parser};
 
 pub struct SynNode<'t>(pub Tree<'t, Subtree<SynNode<'t>>>);
 
@@ -10,75 +15,297 @@ impl<'t> TreeFormattable for SynNode<'t> {
     }
 }
 
-fn build_syn_tree<'t>(
-    cursor: &mut tree_sitter::TreeCursor,
+/// Node inserted by tree-sitter to recover from a syntax error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    /// Kind of the node that tree-sitter had to invent, or None for a region of unexpected
+    /// tokens (`ERROR` node)
+    pub missing_kind: Option<&'static str>,
+    pub bytes: Range<usize>,
+    /// Position of the start of the error, lines and columns are numbered from 1
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match self.missing_kind {
+            Some(kind) => write!(f, "missing `{}`", kind),
+            None => write!(f, "syntax error"),
+        }
+    }
+}
+
+/// How to build the syntax tree of regions that tree-sitter failed to parse
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SyntaxErrorHandling {
+    /// Keep the nodes of the error recovery as if they were valid syntax
+    Keep,
+    /// Replace each `ERROR` node by a single leaf, so that its content is only compared as a
+    /// whole
+    Opaque,
+    /// Refuse to build a tree with syntax errors
+    Strict,
+}
+
+struct TreeBuilder<'t> {
     source: &'t [u8],
     ignore_whitespace: bool,
-    root: bool,
-) -> SynNode<'t> {
-    let node = cursor.node();
-    if !node.is_named() {
-        return SynNode(Tree::Leaf(Token::new(
-            &source[node.byte_range()],
-            ignore_whitespace,
-        )));
+    opaque_errors: bool,
+    syntax_errors: Vec<SyntaxError>,
+}
+
+impl<'t> TreeBuilder<'t> {
+    fn leaf(&self, bytes: Range<usize>) -> SynNode<'t> {
+        SynNode(Tree::Leaf(Token::new(
+            &self.source[bytes],
+            self.ignore_whitespace,
+        )))
+    }
+
+    fn record_syntax_error(&mut self, node: tree_sitter::Node) {
+        let position = node.start_position();
+        self.syntax_errors.push(SyntaxError {
+            missing_kind: if node.is_missing() {
+                Some(node.kind())
+            } else {
+                None
+            },
+            bytes: node.byte_range(),
+            line: position.row + 1,
+            column: position.column + 1,
+        })
     }
-    let kind = node.kind_id();
-    let mut children = Vec::new();
-
-    // We cannot trust tree-sitter root node span, that is shorter than full file if the file
-    // starts with an ignored leaf (like space or new line).
-    let mut cur_byte = if root { 0 } else { node.start_byte() };
-
-    if cursor.goto_first_child() {
-        loop {
-            let field = cursor.field_id();
-            let start_byte = cursor.node().start_byte();
-            if cur_byte < start_byte {
-                // Never loose any byte by recreating leaf node
+
+    #[recursive]
+    fn build_syn_tree(&mut self, cursor: &mut tree_sitter::TreeCursor, root: bool) -> SynNode<'t> {
+        let node = cursor.node();
+        if node.is_missing() {
+            self.record_syntax_error(node);
+        }
+        if !node.is_named() {
+            return self.leaf(node.byte_range());
+        }
+
+        // We cannot trust tree-sitter root node span, that is shorter than full file if the file
+        // starts with an ignored leaf (like space or new line).
+        let mut cur_byte = if root { 0 } else { node.start_byte() };
+        let end_byte = if root {
+            self.source.len()
+        } else {
+            node.end_byte()
+        };
+
+        if node.is_error() {
+            self.record_syntax_error(node);
+            if self.opaque_errors {
+                return self.leaf(cur_byte..end_byte);
+            }
+        }
+
+        let kind = node.kind_id();
+        let mut children = Vec::new();
+        if cursor.goto_first_child() {
+            loop {
+                let field = cursor.field_id();
+                let start_byte = cursor.node().start_byte();
+                if cur_byte < start_byte {
+                    // Never loose any byte by recreating leaf node
+                    children.push(Subtree {
+                        field: None,
+                        node: self.leaf(cur_byte..start_byte),
+                    });
+                }
+                let subtree = self.build_syn_tree(cursor, false);
                 children.push(Subtree {
-                    field: None,
-                    node: SynNode(Tree::Leaf(Token::new(
-                        &source[cur_byte..start_byte],
-                        ignore_whitespace,
-                    ))),
+                    field,
+                    node: subtree,
                 });
+                cur_byte = cursor.node().end_byte();
+                if !cursor.goto_next_sibling() {
+                    break;
+                }
             }
-            let subtree = build_syn_tree(cursor, source, ignore_whitespace, false);
-            children.push(Subtree {
-                field,
-                node: subtree,
-            });
-            cur_byte = cursor.node().end_byte();
-            if !cursor.goto_next_sibling() {
-                break;
-            }
+            assert!(cursor.goto_parent());
         }
-        assert!(cursor.goto_parent());
-    }
 
-    let end_byte = if root { source.len() } else { node.end_byte() };
-    if cur_byte < end_byte {
-        children.push(Subtree {
-            field: None,
-            node: SynNode(Tree::Leaf(Token::new(
-                &source[cur_byte..end_byte],
-                ignore_whitespace,
-            ))),
-        })
+        if cur_byte < end_byte {
+            children.push(Subtree {
+                field: None,
+                node: self.leaf(cur_byte..end_byte),
+            })
+        }
+        SynNode(Tree::Node(kind, children.into()))
     }
-    SynNode(Tree::Node(kind, children))
 }
 
+/// Parse the source into a syntax tree.
+/// Also returns the syntax errors found by tree-sitter, unless `syntax_errors` is `Strict` in
+/// which case they make the parsing fail.
 pub fn parse_source<'t>(
     source: &'t [u8],
     parser: &mut Parser,
     ignore_whitespace: bool,
-) -> Option<SynNode<'t>> {
+    syntax_errors: SyntaxErrorHandling,
+) -> Result<(SynNode<'t>, Vec<SyntaxError>), Error> {
     parser.reset();
-    let tree = parser.parse(source, None)?;
-    let syn_tree = build_syn_tree(&mut tree.walk(), source, ignore_whitespace, true);
-    Some(syn_tree)
+    let tree = parser.parse(source, None).ok_or(Error::ParseFailure)?;
+    let mut builder = TreeBuilder {
+        source,
+        ignore_whitespace,
+        opaque_errors: syntax_errors == SyntaxErrorHandling::Opaque,
+        syntax_errors: Vec::new(),
+    };
+    let syn_tree = builder.build_syn_tree(&mut tree.walk(), true);
+    if syntax_errors == SyntaxErrorHandling::Strict && !builder.syntax_errors.is_empty() {
+        return Err(Error::SyntaxErrors(builder.syntax_errors));
+    }
+    Ok((syn_tree, builder.syntax_errors))
+}
+
+/// Node of a syntax tree with its depth and its byte range in the printed source
+struct PlacedNode {
+    depth: usize,
+    kind: NodeKind,
+    field: Option<FieldId>,
+    bytes: Range<usize>,
+    /// Only has leaves as children
+    flat: bool,
+}
+
+impl PlacedNode {
+    fn same_place(&self, other: &PlacedNode) -> bool {
+        self.depth == other.depth
+            && (self.kind == other.kind || self.flat)
+            && self.field == other.field
+            && self.bytes == other.bytes
+    }
+}
+
+/// Flatten a syntax tree in prefix order, ignoring extra blocks that are not part of the grammar
+#[recursive]
+fn collect_placed_nodes(
+    tree: &Tree<Subtree<SynNode>>,
+    field: Option<FieldId>,
+    depth: usize,
+    cur_byte: &mut usize,
+    nodes: &mut Vec<PlacedNode>,
+) {
+    match tree {
+        Tree::Node(kind, children) => {
+            let (index, child_depth) = if *kind == EXTRA_BLOCK {
+                (None, depth)
+            } else {
+                nodes.push(PlacedNode {
+                    depth,
+                    kind: *kind,
+                    field,
+                    bytes: *cur_byte..*cur_byte,
+                    flat: children.iter().all(|ch| matches!(ch.node.0, Tree::Leaf(_))),
+                });
+                (Some(nodes.len() - 1), depth + 1)
+            };
+            for ch in children {
+                collect_placed_nodes(&ch.node.0, ch.field, child_depth, cur_byte, nodes);
+            }
+            if let Some(index) = index {
+                nodes[index].bytes.end = *cur_byte;
+            }
+        }
+        Tree::Leaf(tok) => *cur_byte += tok.bytes().len(),
+    }
+}
+
+/// Find the byte position of the first node that differs between the expected and reparsed
+/// trees. Where the expected tree only has tokens, the kind and the inner structure of the
+/// reparsed node are ignored, as conflict refinement flattens subtrees into tokens under the
+/// kind of the original subtree, but the syntax errors of the reparsed tree inside it are still
+/// mismatches.
+fn first_mismatch(
+    expected: &[PlacedNode],
+    reparsed: &[PlacedNode],
+    reparse_errors: &[SyntaxError],
+) -> Option<usize> {
+    let mut reparsed_iter = reparsed.iter().peekable();
+    for expected_node in expected {
+        let reparsed_node = match reparsed_iter.next() {
+            Some(node) => node,
+            None => return Some(expected_node.bytes.start),
+        };
+        if !expected_node.same_place(reparsed_node) {
+            return Some(min(expected_node.bytes.start, reparsed_node.bytes.start));
+        }
+        if expected_node.flat {
+            while reparsed_iter
+                .next_if(|node| node.depth > expected_node.depth)
+                .is_some()
+            {}
+            // An error node at the place of the expected node was compared by its kind
+            let inner_error = reparse_errors.iter().find(|err| {
+                expected_node.bytes.start <= err.bytes.start
+                    && err.bytes.end <= expected_node.bytes.end
+                    && (err.missing_kind.is_some() || err.bytes != reparsed_node.bytes)
+            });
+            if let Some(err) = inner_error {
+                return Some(err.bytes.start);
+            }
+        }
+    }
+    reparsed_iter.next().map(|node| node.bytes.start)
+}
+
+/// Check that printing the tree and parsing the result again gives back the same tree.
+/// A merged tree can fail this check when tokens coming from different sources are glued
+/// together, the error then gives the position of the first difference in the printed source.
+pub fn validate_reparse(
+    tree: &SynNode,
+    parser: &mut Parser,
+    ignore_whitespace: bool,
+    syntax_errors: SyntaxErrorHandling,
+) -> Result<(), Error> {
+    let mut source = Vec::new();
+    tree.write_with(&mut PlainTreeFormatter::new(&mut source))
+        .expect("writing to memory cannot fail");
+    // Syntax errors already present in the tree are compared like other nodes
+    let syntax_errors = match syntax_errors {
+        SyntaxErrorHandling::Strict => SyntaxErrorHandling::Keep,
+        handling => handling,
+    };
+    let (reparsed_tree, reparse_errors) =
+        parse_source(&source, parser, ignore_whitespace, syntax_errors)?;
+
+    let mut expected_nodes = Vec::new();
+    collect_placed_nodes(&tree.0, None, 0, &mut 0, &mut expected_nodes);
+    let mut reparsed_nodes = Vec::new();
+    collect_placed_nodes(&reparsed_tree.0, None, 0, &mut 0, &mut reparsed_nodes);
+
+    match first_mismatch(&expected_nodes, &reparsed_nodes, &reparse_errors) {
+        None => Ok(()),
+        Some(byte) => {
+            let line_start = source[..byte]
+                .iter()
+                .rposition(|c| *c == b'\n')
+                .map_or(0, |pos| pos + 1);
+            Err(Error::ReparseMismatch {
+                line: source[..byte].iter().filter(|c| **c == b'\n').count() + 1,
+                column: byte - line_start + 1,
+            })
+        }
+    }
+}
+
+pub fn node_kind_id(language: Language, kind_name: &str) -> Result<NodeKind, Error> {
+    match language.id_for_node_kind(kind_name, true) {
+        0 => Err(Error::UnknownNodeKind(kind_name.to_string())),
+        kind_id => Ok(kind_id),
+    }
+}
+
+pub fn field_id(language: Language, field_name: &str) -> Result<FieldId, Error> {
+    language
+        .field_id_for_name(field_name)
+        .ok_or_else(|| Error::UnknownFieldName(field_name.to_string()))
 }
 
 const EXTRA_BLOCK: NodeKind = NodeKind::MAX - 2;
@@ -104,6 +331,7 @@ fn finalize_last_extra_block(child_list: &mut Vec<Subtree<SynNode>>) {
     child_list.extend(final_leaves);
 }
 
+#[recursive]
 pub fn add_extra_blocks<'t>(tree: &SynNode<'t>) -> SynNode<'t> {
     SynNode(tree.0.convert(|children| {
         let mut child_list = Vec::new();
@@ -151,7 +379,7 @@ pub fn add_extra_blocks<'t>(tree: &SynNode<'t>) -> SynNode<'t> {
                         field: child.field,
                         node: SynNode(Tree::Node(
                             EXTRA_BLOCK,
-                            vec![child.as_ref().map(add_extra_blocks)],
+                            vec![child.as_ref().map(add_extra_blocks)].into(),
                         )),
                     })
                 }
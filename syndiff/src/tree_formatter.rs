@@ -53,8 +53,8 @@ pub trait TreeFormatter {
 
     fn write_changed(
         &mut self,
-        write_del: impl FnOnce(&mut Self) -> Result,
-        write_ins: impl FnOnce(&mut Self) -> Result,
+        write_del: impl Fn(&mut Self) -> Result,
+        write_ins: impl Fn(&mut Self) -> Result,
     ) -> Result {
         self.write_tag("changed", |fmt| {
             write!(fmt.output(), "«")?;
@@ -65,7 +65,7 @@ pub trait TreeFormatter {
         })
     }
 
-    fn write_deleted(&mut self, write_del: impl FnOnce(&mut Self) -> Result) -> Result {
+    fn write_deleted(&mut self, write_del: impl Fn(&mut Self) -> Result) -> Result {
         self.write_tag("deleted", |fmt| {
             fmt.write_change_tree(ChangeType::Deletion, write_del)
         })
@@ -89,7 +89,7 @@ pub trait TreeFormatter {
         &mut self,
         mv: Metavariable,
         write_del: impl FnOnce(&mut Self) -> Result,
-        write_repl: Option<impl FnOnce(&mut Self) -> Result>,
+        write_repl: Option<impl Fn(&mut Self) -> Result>,
     ) -> Result {
         self.write_tag("mv_conflict", |fmt| {
             fmt.write_metavariable(mv)?;
@@ -122,7 +122,7 @@ pub trait TreeFormatter {
     fn write_del_conflict(
         &mut self,
         write_del: impl FnOnce(&mut Self) -> Result,
-        write_ins: impl FnOnce(&mut Self) -> Result,
+        write_ins: impl Fn(&mut Self) -> Result,
     ) -> Result {
         self.write_tag("delete_conflict", |fmt| {
             write!(fmt.output(), "«")?;
@@ -158,7 +158,7 @@ impl<O> PlainTreeFormatter<O> {
     }
 }
 
-impl<'o, O: std::io::Write> TreeFormatter for PlainTreeFormatter<O> {
+impl<O: std::io::Write> TreeFormatter for PlainTreeFormatter<O> {
     type Output = O;
     fn output(&mut self) -> &mut O {
         &mut self.output
@@ -272,7 +272,7 @@ impl<O: std::io::Write> TreeFormatter for AnsiColoredTreeFormatter<O> {
         typ: ChangeType,
         write_tree: impl FnOnce(&mut Self) -> Result,
     ) -> Result {
-        let prev_change_type = std::mem::replace(&mut self.change_type, Some(typ));
+        let prev_change_type = self.change_type.replace(typ);
         let prev_parent_color = self.parent_color;
         self.parent_color = Color::Both; // Both by default to color single differences
         self.write_with_style(self.style_for_color(Color::Both), write_tree)?;
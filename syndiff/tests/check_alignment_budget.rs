@@ -0,0 +1,35 @@
+#![cfg(feature = "rust")]
+
+use goldenfile::Mint;
+use std::process::Command;
+
+#[test]
+fn minimal_alignment_over_budget() {
+    let mut mint = Mint::new("tests/prgms/trait_change");
+    let diff_file = mint.new_goldenfile("diff.over_budget.rs").unwrap();
+
+    let diff_out = Command::new(env!("CARGO_BIN_EXE_syndiff"))
+        .args(["--max-alignment-cells", "20"])
+        .arg("tests/prgms/trait_change/orig.rs")
+        .arg("tests/prgms/trait_change/edit.rs")
+        .stdout(diff_file)
+        .output()
+        .expect("Failed to launch syndiff");
+    assert!(diff_out.status.success());
+    let stderr = String::from_utf8_lossy(&diff_out.stderr);
+    assert!(stderr.contains("exceeds the memory budget, approximated with patience"));
+}
+
+#[test]
+fn patience_gaps_over_budget() {
+    let diff_out = Command::new(env!("CARGO_BIN_EXE_syndiff"))
+        .args(["--max-alignment-cells", "20"])
+        .arg("tests/prgms/factorize/orig.rs")
+        .arg("tests/prgms/factorize/edit_left.rs")
+        .output()
+        .expect("Failed to launch syndiff");
+    assert!(diff_out.status.success());
+    let stderr = String::from_utf8_lossy(&diff_out.stderr);
+    assert!(stderr.contains("exceeds the memory budget, approximated with patience"));
+    assert!(stderr.contains("exceeds the memory budget, approximated with myers"));
+}
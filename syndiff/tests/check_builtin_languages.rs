@@ -0,0 +1,20 @@
+#![cfg(feature = "rust")]
+
+use std::fs::read;
+use std::path::Path;
+use syndiff::{builtin_language, parse_source, SyntaxErrorHandling};
+use tree_sitter::Parser;
+
+#[test]
+fn select_embedded_rust_grammar() {
+    let by_extension = builtin_language(Path::new("tests/prgms/disjoint/orig.rs"), None);
+    let by_scope = builtin_language(Path::new("orig"), Some("source.rust"));
+    assert!(by_extension.is_some());
+    assert_eq!(by_extension, by_scope);
+    assert_eq!(builtin_language(Path::new("orig.unknown"), None), None);
+
+    let mut parser = Parser::new();
+    parser.set_language(by_extension.unwrap()).unwrap();
+    let source = read("tests/prgms/disjoint/orig.rs").unwrap();
+    assert!(parse_source(&source, &mut parser, false, SyntaxErrorHandling::Keep).is_ok());
+}
@@ -0,0 +1,110 @@
+#![cfg(feature = "rust")]
+
+use goldenfile::Mint;
+use std::path::Path;
+use std::process::Command;
+use syndiff::{
+    builtin_language, compute_chunked_diff, compute_diff, compute_diff_pair, merge_chunked,
+    parse_source, CostModel, DiffOptions, Error, MergeOptions, SyntaxErrorHandling,
+};
+use tree_sitter::Parser;
+
+#[test]
+fn expensive_kind_multiplier() {
+    let mut mint = Mint::new("tests/prgms/change_and_move");
+    let diff_file = mint.new_goldenfile("diff.expensive_literals.rs").unwrap();
+
+    let diff_out = Command::new(env!("CARGO_BIN_EXE_syndiff"))
+        .args(["--kind-multipliers", "integer_literal=20"])
+        .arg("tests/prgms/change_and_move/orig.rs")
+        .arg("tests/prgms/change_and_move/edit.rs")
+        .stdout(diff_file)
+        .output()
+        .expect("Failed to launch syndiff");
+    assert!(diff_out.status.success());
+    assert!(diff_out.stderr.is_empty());
+}
+
+#[test]
+fn saturated_kind_multiplier() {
+    let mut mint = Mint::new("tests/prgms/nested_parens");
+    let diff_file = mint.new_goldenfile("diff.expensive_parens.rs").unwrap();
+
+    // 20 nested multipliers of 20 would overflow the weights
+    let diff_out = Command::new(env!("CARGO_BIN_EXE_syndiff"))
+        .args(["--kind-multipliers", "parenthesized_expression=20"])
+        .arg("tests/prgms/nested_parens/orig.rs")
+        .arg("tests/prgms/nested_parens/edit.rs")
+        .stdout(diff_file)
+        .output()
+        .expect("Failed to launch syndiff");
+    assert!(diff_out.status.success());
+    assert!(diff_out.stderr.is_empty());
+}
+
+fn run_with_cost_options(options: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_syndiff"))
+        .args(options)
+        .arg("tests/prgms/nested_parens/orig.rs")
+        .arg("tests/prgms/nested_parens/edit.rs")
+        .output()
+        .expect("Failed to launch syndiff")
+}
+
+#[test]
+fn inadmissible_cost_models_are_refused() {
+    let zero_multiplier = run_with_cost_options(&["--kind-multipliers", "integer_literal=0"]);
+    assert!(!zero_multiplier.status.success());
+    assert!(String::from_utf8_lossy(&zero_multiplier.stderr).contains("Invalid kind multiplier"));
+
+    let expensive_spine = run_with_cost_options(&["--spine-weight", "3", "--leaf-weight", "2"]);
+    assert!(!expensive_spine.status.success());
+    assert!(String::from_utf8_lossy(&expensive_spine.stderr)
+        .contains("spine weight must not exceed the leaf weight"));
+}
+
+#[test]
+fn cost_model_validation() {
+    assert_eq!(CostModel::default().validate(), Ok(()));
+
+    let mut zero_multiplier = CostModel::default();
+    zero_multiplier.kind_multipliers.insert(1, 0);
+    assert_eq!(
+        zero_multiplier.validate(),
+        Err(Error::ZeroKindMultiplier(1))
+    );
+
+    let expensive_spine = CostModel {
+        spine_leaf_weight: 3,
+        ..CostModel::default()
+    };
+    assert!(matches!(
+        expensive_spine.validate(),
+        Err(Error::InadmissibleCostModel(_))
+    ));
+}
+
+#[test]
+fn inadmissible_cost_models_are_refused_by_the_library() {
+    let mut parser = Parser::new();
+    parser
+        .set_language(builtin_language(Path::new("test.rs"), None).unwrap())
+        .unwrap();
+    let (tree, _) =
+        parse_source(b"fn f() {}", &mut parser, false, SyntaxErrorHandling::Keep).unwrap();
+    let mut options = DiffOptions::default();
+    options.cost_model.kind_multipliers.insert(1, 0);
+
+    let refused = Err(Error::ZeroKindMultiplier(1));
+    assert_eq!(compute_diff(&tree, &tree, &options).map(|_| ()), refused);
+    assert_eq!(
+        compute_chunked_diff(&tree, &tree, &options).map(|_| ()),
+        refused
+    );
+    assert_eq!(
+        compute_diff_pair(&tree, &tree, &tree, &options).map(|_| ()),
+        refused
+    );
+    let (merged, _) = merge_chunked(&tree, &tree, &tree, &options, &MergeOptions::default());
+    assert_eq!(merged.map(|_| ()), refused);
+}
@@ -0,0 +1,242 @@
+#![cfg(feature = "rust")]
+
+use goldenfile::Mint;
+use std::fs::read;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use syndiff::{
+    builtin_language, compute_diff, compute_diff_pair, parse_source, AlignmentContext,
+    AlignmentIssue, DiffOptions, MinimalAlignment, PlainTreeFormatter, SeqNodeAlignment, Subtree,
+    SubtreeAlignment, SyntaxErrorHandling, TreeFormattable, Weight, WeightedNode,
+};
+use tree_sitter::Parser;
+
+/// Zip children at the same position, delete or insert the remaining ones
+struct PositionalAlignment;
+
+impl SubtreeAlignment for PositionalAlignment {
+    fn align(
+        &self,
+        del_seq: &[Subtree<WeightedNode>],
+        ins_seq: &[Subtree<WeightedNode>],
+        alignment: &mut Vec<SeqNodeAlignment>,
+        context: &AlignmentContext,
+    ) -> Weight {
+        let mut cost = 0;
+        let (mut del_iter, mut ins_iter) = (del_seq.iter(), ins_seq.iter());
+        loop {
+            match (del_iter.next(), ins_iter.next()) {
+                (Some(del), Some(ins)) if context.can_zip(del, ins) => {
+                    let (zip_cost, zip_align) = context.align_nodes(&del.node, &ins.node);
+                    alignment.push(SeqNodeAlignment::Zip(zip_align));
+                    cost += zip_cost;
+                }
+                (Some(del), Some(ins)) => {
+                    alignment.push(SeqNodeAlignment::Delete);
+                    alignment.push(SeqNodeAlignment::Insert);
+                    cost += del.node.weight + ins.node.weight;
+                }
+                (Some(del), None) => {
+                    alignment.push(SeqNodeAlignment::Delete);
+                    cost += del.node.weight;
+                }
+                (None, Some(ins)) => {
+                    alignment.push(SeqNodeAlignment::Insert);
+                    cost += ins.node.weight;
+                }
+                (None, None) => return cost,
+            }
+        }
+    }
+}
+
+/// Zip the first children and forget the other ones
+struct ForgetfulAlignment;
+
+impl SubtreeAlignment for ForgetfulAlignment {
+    fn align(
+        &self,
+        del_seq: &[Subtree<WeightedNode>],
+        ins_seq: &[Subtree<WeightedNode>],
+        alignment: &mut Vec<SeqNodeAlignment>,
+        context: &AlignmentContext,
+    ) -> Weight {
+        match (del_seq.first(), ins_seq.first()) {
+            (Some(del), Some(ins)) => {
+                let (cost, zip_align) = context.align_nodes(&del.node, &ins.node);
+                alignment.push(SeqNodeAlignment::Zip(zip_align));
+                cost
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// Count the children sequences aligned by the minimal alignment
+struct CountingAlignment(Arc<AtomicUsize>);
+
+impl SubtreeAlignment for CountingAlignment {
+    fn align(
+        &self,
+        del_seq: &[Subtree<WeightedNode>],
+        ins_seq: &[Subtree<WeightedNode>],
+        alignment: &mut Vec<SeqNodeAlignment>,
+        context: &AlignmentContext,
+    ) -> Weight {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        MinimalAlignment::default().align(del_seq, ins_seq, alignment, context)
+    }
+}
+
+/// Count the children sequences aligned by the minimal alignment, without being `Sync`
+#[cfg(not(feature = "parallel"))]
+struct CellCountingAlignment(std::rc::Rc<std::cell::Cell<usize>>);
+
+#[cfg(not(feature = "parallel"))]
+impl SubtreeAlignment for CellCountingAlignment {
+    fn align(
+        &self,
+        del_seq: &[Subtree<WeightedNode>],
+        ins_seq: &[Subtree<WeightedNode>],
+        alignment: &mut Vec<SeqNodeAlignment>,
+        context: &AlignmentContext,
+    ) -> Weight {
+        self.0.set(self.0.get() + 1);
+        MinimalAlignment::default().align(del_seq, ins_seq, alignment, context)
+    }
+}
+
+fn diff_with(test_name: &str, suffix: &str, alignment: Box<dyn SubtreeAlignment>) -> Vec<u8> {
+    diff_with_options(
+        test_name,
+        suffix,
+        DiffOptions {
+            alignment,
+            ..DiffOptions::default()
+        },
+    )
+}
+
+fn read_sources(test_name: &str, suffixes: &[&str]) -> (Parser, Vec<Vec<u8>>) {
+    let orig_filename = format!("tests/prgms/{}/orig.rs", test_name);
+    let mut parser = Parser::new();
+    parser
+        .set_language(builtin_language(Path::new(&orig_filename), None).unwrap())
+        .unwrap();
+    let mut sources = vec![read(&orig_filename).unwrap()];
+    for suffix in suffixes {
+        sources.push(read(format!("tests/prgms/{}/edit{}.rs", test_name, suffix)).unwrap());
+    }
+    (parser, sources)
+}
+
+fn print_diff(diff: &impl TreeFormattable) -> Vec<u8> {
+    let mut output = Vec::new();
+    diff.write_with(&mut PlainTreeFormatter::new(&mut output))
+        .unwrap();
+    output
+}
+
+fn diff_with_options(test_name: &str, suffix: &str, options: DiffOptions) -> Vec<u8> {
+    let (mut parser, sources) = read_sources(test_name, &[suffix]);
+    let (orig_tree, _) =
+        parse_source(&sources[0], &mut parser, false, SyntaxErrorHandling::Keep).unwrap();
+    let (edit_tree, _) =
+        parse_source(&sources[1], &mut parser, false, SyntaxErrorHandling::Keep).unwrap();
+
+    let (diff, _) = compute_diff(&orig_tree, &edit_tree, &options).unwrap();
+    print_diff(&diff)
+}
+
+#[test]
+fn delegating_alignment() {
+    let nb_calls = Arc::new(AtomicUsize::new(0));
+    let counted_diff = diff_with(
+        "disjoint",
+        "_left",
+        Box::new(CountingAlignment(nb_calls.clone())),
+    );
+    assert!(nb_calls.load(Ordering::Relaxed) > 0);
+    assert_eq!(
+        counted_diff,
+        diff_with("disjoint", "_left", Box::new(MinimalAlignment::default()))
+    );
+}
+
+#[test]
+#[cfg(not(feature = "parallel"))]
+fn sequential_alignment_without_sync() {
+    let nb_calls = std::rc::Rc::new(std::cell::Cell::new(0));
+    let counted_diff = diff_with(
+        "disjoint",
+        "_left",
+        Box::new(CellCountingAlignment(nb_calls.clone())),
+    );
+    assert!(nb_calls.get() > 0);
+    assert_eq!(
+        counted_diff,
+        diff_with("disjoint", "_left", Box::new(MinimalAlignment::default()))
+    );
+}
+
+#[test]
+fn positional_alignment() {
+    let mut mint = Mint::new("tests/prgms/trait_change");
+    let mut diff_file = mint.new_goldenfile("diff.positional.rs").unwrap();
+    diff_file
+        .write_all(&diff_with(
+            "trait_change",
+            "",
+            Box::new(PositionalAlignment),
+        ))
+        .unwrap();
+}
+
+#[test]
+fn diff_pair() {
+    let (mut parser, sources) = read_sources("factorize", &["_left", "_right"]);
+    let trees: Vec<_> = sources
+        .iter()
+        .map(|src| {
+            parse_source(src, &mut parser, false, SyntaxErrorHandling::Keep)
+                .unwrap()
+                .0
+        })
+        .collect();
+
+    let options = DiffOptions::default();
+    let [(left_diff, _), (right_diff, _)] =
+        compute_diff_pair(&trees[0], &trees[1], &trees[2], &options).unwrap();
+    assert_eq!(
+        print_diff(&left_diff),
+        diff_with_options("factorize", "_left", DiffOptions::default())
+    );
+    assert_eq!(
+        print_diff(&right_diff),
+        diff_with_options("factorize", "_right", DiffOptions::default())
+    );
+}
+
+#[test]
+fn invalid_alignment_is_replaced() {
+    let (mut parser, sources) = read_sources("disjoint", &["_left"]);
+    let (orig_tree, _) =
+        parse_source(&sources[0], &mut parser, false, SyntaxErrorHandling::Keep).unwrap();
+    let (edit_tree, _) =
+        parse_source(&sources[1], &mut parser, false, SyntaxErrorHandling::Keep).unwrap();
+    let options = DiffOptions {
+        alignment: Box::new(ForgetfulAlignment),
+        ..DiffOptions::default()
+    };
+    let (diff, diagnostics) = compute_diff(&orig_tree, &edit_tree, &options).unwrap();
+    assert!(!diagnostics.is_empty());
+    assert!(diagnostics
+        .iter()
+        .all(|diagnostic| diagnostic.issue == AlignmentIssue::Invalid));
+    assert_eq!(
+        print_diff(&diff),
+        diff_with("disjoint", "_left", Box::new(MinimalAlignment::default()))
+    );
+}
@@ -0,0 +1,40 @@
+#![cfg(feature = "rust")]
+
+mod common;
+
+use common::{run_syndiff, write_source};
+use std::path::PathBuf;
+
+/// Nesting depth that diffs and merges must support without overflowing the stack
+const GUARANTEED_DEPTH: usize = 100_000;
+
+/// Write a function returning a sum of `GUARANTEED_DEPTH` terms, parsed as nested binary
+/// expressions, whose first and last terms are given
+fn write_deep_sum(name: &str, first: u32, last: u32) -> PathBuf {
+    let mut source = format!("fn f() -> u32 {{\n    {}", first);
+    for _ in 2..GUARANTEED_DEPTH {
+        source.push_str(" + 1");
+    }
+    source.push_str(&format!(" + {}\n}}\n", last));
+    write_source(&format!("deep_{}", name), &source)
+}
+
+#[test]
+fn deep_diff() {
+    let orig = write_deep_sum("diff_orig", 1, 1);
+    let edit = write_deep_sum("diff_edit", 1, 2);
+    let diff_out = run_syndiff(&[], &[&orig, &edit]);
+    assert!(diff_out.status.success());
+}
+
+#[test]
+fn deep_merge() {
+    let orig = write_deep_sum("merge_orig", 1, 1);
+    let left = write_deep_sum("merge_left", 1, 2);
+    let right = write_deep_sum("merge_right", 3, 1);
+    let merge_out = run_syndiff(&["--merge-files"], &[&orig, &left, &right]);
+    assert!(merge_out.status.success());
+    let merged = String::from_utf8(merge_out.stdout).unwrap();
+    assert!(merged.starts_with("fn f() -> u32 {\n    3 + 1 + 1"));
+    assert!(merged.ends_with("1 + 1 + 2\n}\n"));
+}
@@ -1,3 +1,5 @@
+#![cfg(feature = "rust")]
+
 use goldenfile::Mint;
 use std::process::Command;
 
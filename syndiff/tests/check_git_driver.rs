@@ -0,0 +1,66 @@
+#![cfg(feature = "rust")]
+
+use std::fs::{copy, read};
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn run_git_driver(test_name: &str, extension: &str) -> (Output, Vec<u8>) {
+    let current_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join(format!("git_driver_{}_current", test_name));
+    copy(
+        format!("tests/prgms/{}/edit_left.{}", test_name, extension),
+        &current_file,
+    )
+    .unwrap();
+
+    let driver_out = Command::new(env!("CARGO_BIN_EXE_syndiff"))
+        .arg("--git-merge-driver")
+        .args(["--marker-size", "7"])
+        .args(["--path", &format!("{}.{}", test_name, extension)])
+        .arg(format!("tests/prgms/{}/orig.{}", test_name, extension))
+        .arg(&current_file)
+        .arg(format!(
+            "tests/prgms/{}/edit_right.{}",
+            test_name, extension
+        ))
+        .output()
+        .expect("Failed to launch syndiff");
+    eprint!("{}", String::from_utf8_lossy(&driver_out.stderr));
+    assert!(driver_out.stdout.is_empty());
+    let merged = read(&current_file).unwrap();
+    (driver_out, merged)
+}
+
+fn check_git_driver(test_name: &str, expected_merged: Option<&str>) {
+    let (driver_out, merged) = run_git_driver(test_name, "rs");
+    assert!(driver_out.stderr.is_empty());
+    match expected_merged {
+        Some(merged_file) => {
+            assert_eq!(driver_out.status.code(), Some(0));
+            let expected = read(format!("tests/prgms/{}/{}", test_name, merged_file)).unwrap();
+            assert!(merged == expected);
+        }
+        None => {
+            assert_eq!(driver_out.status.code(), Some(1));
+            assert!(!merged.is_empty());
+        }
+    }
+}
+
+#[test]
+fn clean_merge_driver() {
+    check_git_driver("disjoint", Some("merged.rs"));
+}
+
+#[test]
+fn conflicting_merge_driver() {
+    check_git_driver("ord_conflict", None);
+}
+
+#[test]
+fn merge_driver_without_grammar() {
+    let (driver_out, merged) = run_git_driver("plain_text", "txt");
+    assert_eq!(driver_out.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&driver_out.stderr).contains("line-based merge"));
+    assert!(merged == read("tests/prgms/plain_text/merged.txt").unwrap());
+}
@@ -0,0 +1,62 @@
+#![cfg(feature = "rust")]
+
+use std::process::Command;
+use syndiff::Token;
+
+#[test]
+fn stable_token_hash() {
+    // Reference XXH3 128-bit hash of the empty input
+    assert_eq!(
+        Token::new(b"", false).hash_value(),
+        0x99aa06d3014798d86001c324468d497f
+    );
+    assert_eq!(
+        Token::new(b"", true).hash_value(),
+        Token::new(b"", false).hash_value()
+    );
+}
+
+#[test]
+fn whitespace_insensitive_tokens() {
+    let spaced = Token::new(b"a \tb", true);
+    let glued = Token::new(b"ab", true);
+    assert!(spaced == glued);
+    assert!(spaced.same_bytes(&glued));
+    assert!(Token::new(b"a \tb", false) != Token::new(b"ab", false));
+    assert!(!Token::new(b"a \tb", false).same_bytes(&Token::new(b"ab", false)));
+}
+
+fn diff_output(test_name: &str, suffix: &str, options: &[&str], verify_hashes: bool) -> Vec<u8> {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_syndiff"));
+    if verify_hashes {
+        command.arg("--verify-hashes");
+    }
+    let diff_out = command
+        .args(options)
+        .arg(format!("tests/prgms/{}/orig.rs", test_name))
+        .arg(format!("tests/prgms/{}/edit{}.rs", test_name, suffix))
+        .output()
+        .expect("Failed to launch syndiff");
+    assert!(diff_out.status.success());
+    diff_out.stdout
+}
+
+macro_rules! verified_hashes_tests {
+    { $($test_name:ident: $folder:ident $suffix:literal $([$($opt:literal),*])?,)* } => {
+        $(#[test]
+        fn $test_name() {
+            let options = [$($($opt),*)?];
+            assert_eq!(
+                diff_output(stringify!($folder), $suffix, &options, true),
+                diff_output(stringify!($folder), $suffix, &options, false)
+            );
+        })*
+    }
+}
+
+verified_hashes_tests! {
+    change_and_move_verified: change_and_move "",
+    factorize_verified: factorize "_left",
+    inlining_verified: inlining "_right",
+    commutative_verified: commutative_uses "_left" ["--commutative-kinds", "source_file,field_declaration_list"],
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "json")]
+
+use goldenfile::Mint;
+use std::fs::File;
+use std::process::{Command, Output};
+
+fn run_merge(test_name: &str, options: &[&str], out_file: File) -> Output {
+    let merge_out = Command::new(env!("CARGO_BIN_EXE_syndiff"))
+        .args(options)
+        .arg(format!("tests/prgms/{}/orig.json", test_name))
+        .arg(format!("tests/prgms/{}/edit_left.json", test_name))
+        .arg(format!("tests/prgms/{}/edit_right.json", test_name))
+        .stdout(out_file)
+        .output()
+        .expect("Failed to launch syndiff");
+    eprint!("{}", String::from_utf8_lossy(&merge_out.stderr));
+    assert!(merge_out.stderr.is_empty());
+    merge_out
+}
+
+#[test]
+fn keyed_members() {
+    let mut mint = Mint::new("tests/prgms/keyed_members");
+    let keyed = ["--key-fields", "pair.key"];
+
+    // Without keys, members added at the end of the same object conflict
+    let diff_file = mint.new_goldenfile("mdiff.json").unwrap();
+    assert_eq!(
+        run_merge("keyed_members", &[], diff_file).status.code(),
+        Some(1)
+    );
+
+    let diff_file = mint.new_goldenfile("mdiff.keyed.json").unwrap();
+    assert_eq!(
+        run_merge("keyed_members", &keyed, diff_file).status.code(),
+        Some(0)
+    );
+
+    // Members are unordered: the left reordering of "name" and "version" is not a change, and
+    // the merged file keeps the original order
+    let merged_file = mint.new_goldenfile("merged.keyed.json").unwrap();
+    let options = [&["--merge-files"], &keyed[..]].concat();
+    assert_eq!(
+        run_merge("keyed_members", &options, merged_file)
+            .status
+            .code(),
+        Some(0)
+    );
+}
@@ -0,0 +1,92 @@
+#![cfg(feature = "rust")]
+
+mod common;
+
+use common::{functions_source, run_syndiff, write_source};
+
+/// Source of `count` functions returning their index, except those with a replaced body
+fn replaced_functions_source(count: usize, replaced_bodies: &[(usize, &str)]) -> String {
+    functions_source(0..count, |i| {
+        match replaced_bodies.iter().find(|(pos, _)| *pos == i) {
+            Some((_, body)) => body.to_string(),
+            None => i.to_string(),
+        }
+    })
+}
+
+#[test]
+fn alignment_work_does_not_grow_with_the_file() {
+    // The budget of the minimal alignment is far below the size of the whole sequence of items,
+    // but large enough for each chunk between unchanged items
+    let budget = ["--max-alignment-steps", "1000"];
+    for count in [1_000, 10_000] {
+        let orig = write_source(
+            &format!("large_orig_{}", count),
+            &replaced_functions_source(count, &[]),
+        );
+        let edit = write_source(
+            &format!("large_edit_{}", count),
+            &replaced_functions_source(count, &[(count / 3, "1 + 2"), (count / 2, "3 * 4")]),
+        );
+
+        let chunked = run_syndiff(&[&budget[..], &["--large-file"]].concat(), &[&orig, &edit]);
+        assert!(chunked.status.success());
+        assert_eq!(String::from_utf8_lossy(&chunked.stderr), "");
+
+        let whole = run_syndiff(&budget, &[&orig, &edit]);
+        assert!(whole.status.success());
+        assert!(
+            String::from_utf8_lossy(&whole.stderr).contains("budget, approximated with patience")
+        );
+        assert_eq!(whole.stdout, chunked.stdout);
+    }
+}
+
+#[test]
+fn large_file_merge() {
+    let count = 10_000;
+    let left_edits = [(10, "10 + 1"), (5_000, "5_000 + 1")];
+    let right_edits = [(20, "20 * 2"), (9_990, "9_990 * 2")];
+    let orig = write_source("large_merge_orig", &replaced_functions_source(count, &[]));
+    let left = write_source(
+        "large_merge_left",
+        &replaced_functions_source(count, &left_edits),
+    );
+    let right = write_source(
+        "large_merge_right",
+        &replaced_functions_source(count, &right_edits),
+    );
+
+    let merge_out = run_syndiff(&["--merge-files", "--large-file"], &[&orig, &left, &right]);
+    assert_eq!(String::from_utf8_lossy(&merge_out.stderr), "");
+    assert!(merge_out.status.success());
+    let merged = String::from_utf8(merge_out.stdout).unwrap();
+    assert!(merged == replaced_functions_source(count, &[&left_edits[..], &right_edits].concat()));
+}
+
+#[test]
+fn large_file_conflict() {
+    let count = 1_000;
+    let orig = write_source(
+        "large_conflict_orig",
+        &replaced_functions_source(count, &[]),
+    );
+    let left = write_source(
+        "large_conflict_left",
+        &replaced_functions_source(count, &[(500, "1")]),
+    );
+    let right = write_source(
+        "large_conflict_right",
+        &replaced_functions_source(count, &[(500, "2")]),
+    );
+
+    let merge_out = run_syndiff(
+        &["--merge-files", "--large-file", "--conflict-style", "merge"],
+        &[&orig, &left, &right],
+    );
+    assert_eq!(merge_out.status.code(), Some(1));
+    let merged = String::from_utf8(merge_out.stdout).unwrap();
+    assert_eq!(merged.matches("<<<<<<<").count(), 1);
+    assert!(merged.contains("fn f499() -> usize {\n    499\n}\n\nfn f500() -> usize {\n"));
+    assert!(merged.ends_with("fn f999() -> usize {\n    999\n}\n\n"));
+}
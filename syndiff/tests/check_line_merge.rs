@@ -0,0 +1,30 @@
+use goldenfile::Mint;
+use std::fs::read;
+use syndiff::{merge_lines, ConflictMarkerOptions, ConflictStyle};
+
+fn check_line_merge(test_name: &str, expected_conflicts: usize) {
+    let mut mint = Mint::new(format!("tests/prgms/{}", test_name));
+    let merged_file = mint.new_goldenfile("line_merged.rs").unwrap();
+
+    let orig = read(format!("tests/prgms/{}/orig.rs", test_name)).unwrap();
+    let left = read(format!("tests/prgms/{}/edit_left.rs", test_name)).unwrap();
+    let right = read(format!("tests/prgms/{}/edit_right.rs", test_name)).unwrap();
+    let options = ConflictMarkerOptions {
+        style: ConflictStyle::Diff3,
+        ..ConflictMarkerOptions::default()
+    };
+    let nb_conflicts = merge_lines(&orig, &left, &right)
+        .write_with_conflict_markers(&options, merged_file)
+        .unwrap();
+    assert_eq!(nb_conflicts, expected_conflicts);
+}
+
+#[test]
+fn disjoint_line_merge() {
+    check_line_merge("disjoint", 0);
+}
+
+#[test]
+fn cross_change_line_merge() {
+    check_line_merge("cross_change", 2);
+}
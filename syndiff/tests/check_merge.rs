@@ -1,37 +1,79 @@
-use goldenfile::Mint;
-use std::process::Command;
+#![cfg(feature = "rust")]
 
-fn check_merge(test_name: &str, suffix: &str, extra_options: &[&str]) {
-    let mut mint = Mint::new(format!("tests/prgms/{}", test_name));
-    let diff_file = mint.new_goldenfile(format!("mdiff{}.rs", suffix)).unwrap();
+use goldenfile::Mint;
+use std::fs::{read, read_to_string, File};
+use std::path::Path;
+use std::process::{Command, Output};
+use syndiff::{
+    builtin_language, compute_diff_pair, count_conflicts, merge_diffs, parse_source, DiffOptions,
+    InsertOrderResolution, MergeOptions, PlainTreeFormatter, SyntaxErrorHandling, TreeFormattable,
+};
+use tree_sitter::Parser;
 
-    let diff_out = Command::new(env!("CARGO_BIN_EXE_syndiff"))
-        .args(extra_options)
+fn run_merge(test_name: &str, options: &[&str], out_file: File) -> Output {
+    let merge_out = Command::new(env!("CARGO_BIN_EXE_syndiff"))
+        .args(options)
         .arg(format!("tests/prgms/{}/orig.rs", test_name))
         .arg(format!("tests/prgms/{}/edit_left.rs", test_name))
         .arg(format!("tests/prgms/{}/edit_right.rs", test_name))
-        .stdout(diff_file)
+        .stdout(out_file)
         .output()
         .expect("Failed to launch syndiff");
-    eprint!("{}", String::from_utf8_lossy(&diff_out.stderr));
+    eprint!("{}", String::from_utf8_lossy(&merge_out.stderr));
+    assert!(merge_out.stderr.is_empty());
+    merge_out
+}
+
+fn check_merge(test_name: &str, suffix: &str, extra_options: &[&str]) {
+    let mut mint = Mint::new(format!("tests/prgms/{}", test_name));
+    let diff_file = mint.new_goldenfile(format!("mdiff{}.rs", suffix)).unwrap();
+    let diff_out = run_merge(test_name, extra_options, diff_file);
     let exit_code = diff_out.status.code().unwrap();
     assert!(exit_code >= 0);
-    assert!(diff_out.stderr.is_empty());
 
     if exit_code == 0 {
         let merged_file = mint.new_goldenfile(format!("merged{}.rs", suffix)).unwrap();
-        let diff_out = Command::new(env!("CARGO_BIN_EXE_syndiff"))
-            .arg("--merge-files")
-            .args(extra_options)
-            .arg(format!("tests/prgms/{}/orig.rs", test_name))
-            .arg(format!("tests/prgms/{}/edit_left.rs", test_name))
-            .arg(format!("tests/prgms/{}/edit_right.rs", test_name))
-            .stdout(merged_file)
-            .output()
-            .expect("Failed to launch syndiff");
-        eprint!("{}", String::from_utf8_lossy(&diff_out.stderr));
-        assert!(diff_out.status.code().unwrap() == 0);
-        assert!(diff_out.stderr.is_empty());
+        let options = [&["--merge-files"], extra_options].concat();
+        let merge_out = run_merge(test_name, &options, merged_file);
+        assert!(merge_out.status.code().unwrap() == 0);
+    } else {
+        let conflicts_file = mint
+            .new_goldenfile(format!("conflicts{}.rs", suffix))
+            .unwrap();
+        let options = [
+            &["--merge-files", "--conflict-style", "diff3"],
+            extra_options,
+        ]
+        .concat();
+        let merge_out = run_merge(test_name, &options, conflicts_file);
+        // Conflicts can be refined or merged line by line when written, the exit code counts
+        // the conflict markers actually written
+        let conflicts =
+            read_to_string(format!("tests/prgms/{}/conflicts{}.rs", test_name, suffix)).unwrap();
+        let nb_markers = conflicts
+            .lines()
+            .filter(|line| line.starts_with("<<<<<<<"))
+            .count();
+        assert!(merge_out.status.code().unwrap() == nb_markers as i32);
+        assert!(nb_markers > 0);
+
+        let report_file = mint
+            .new_goldenfile(format!("report{}.json", suffix))
+            .unwrap();
+        let options = [&["--conflict-report"], extra_options].concat();
+        let merge_out = run_merge(test_name, &options, report_file);
+        assert!(merge_out.status.code().unwrap() == exit_code);
+
+        let partial_file = mint
+            .new_goldenfile(format!("partial{}.rs", suffix))
+            .unwrap();
+        let options = [
+            &["--merge-files", "--unresolved-side", "left"],
+            extra_options,
+        ]
+        .concat();
+        let merge_out = run_merge(test_name, &options, partial_file);
+        assert!(merge_out.status.code().unwrap() == exit_code);
     }
 }
 
@@ -56,6 +98,10 @@ check_merge_tests! {
     print_macro,
     inlining,
     disjoint,
+    wrap_call,
+    commutative_uses,
+    keyed_fields,
+    named_items,
 }
 
 macro_rules! check_merge_tests_with_opt {
@@ -71,4 +117,64 @@ check_merge_tests_with_opt! {
     factorize_without_elisions: factorize no_elisions ["--no-elisions"],
     double_del_allow_nested: double_del allow_nested_del ["--allow-nested-deletions"],
     ordered_conflict: ord_conflict ordered ["--ordered-insertions"],
+    right_first_conflict: ord_conflict right_first ["--insert-order-conflicts", "right-first"],
+    double_del_modification_wins: double_del modification_wins ["--delete-conflicts", "modification"],
+    cross_change_left_metavars: cross_change left_metavars ["--metavar-conflicts", "left"],
+    cross_del_and_ins_right_metavars: cross_del_and_ins right_metavars ["--metavar-conflicts", "right"],
+    wrap_call_without_elisions: wrap_call no_elisions ["--no-elisions"],
+    wrap_call_refined: wrap_call refined ["--no-elisions", "--refine-conflicts", "tokens"],
+    commutative_uses_combined: commutative_uses combined ["--commutative-kinds", "source_file,field_declaration_list"],
+    commutative_uses_sorted: commutative_uses sorted ["--commutative-kinds", "source_file,field_declaration_list", "--sort-commutative"],
+    keyed_fields_keyed: keyed_fields keyed ["--key-fields", "field_initializer.name"],
+    named_items_anchored: named_items anchored ["--anchor-names", "function_item.name"],
+    named_reorder_anchored: named_reorder anchored ["--anchor-names", "function_item.name"],
+    cross_del_and_ins_myers: cross_del_and_ins myers ["--myers"],
+    cross_del_and_ins_histogram: cross_del_and_ins histogram ["--histogram"],
+    cross_change_top_level_patience: cross_change top_patience ["--kind-algorithms", "source_file=patience"],
+    disjoint_large_file: disjoint large_file ["--large-file"],
+}
+
+#[test]
+#[allow(deprecated)]
+fn deprecated_ordered_insertions() {
+    let mut parser = Parser::new();
+    parser
+        .set_language(builtin_language(Path::new("orig.rs"), None).unwrap())
+        .unwrap();
+    let sources: Vec<_> = ["orig.rs", "edit_left.rs", "edit_right.rs"]
+        .iter()
+        .map(|name| read(format!("tests/prgms/ord_conflict/{}", name)).unwrap())
+        .collect();
+    let trees: Vec<_> = sources
+        .iter()
+        .map(|src| {
+            parse_source(src, &mut parser, false, SyntaxErrorHandling::Keep)
+                .unwrap()
+                .0
+        })
+        .collect();
+    let [(left_diff, _), (right_diff, _)] =
+        compute_diff_pair(&trees[0], &trees[1], &trees[2], &DiffOptions::default()).unwrap();
+
+    let print_merge = |options| {
+        let merged = merge_diffs(&left_diff, &right_diff, options).unwrap();
+        let mut output = Vec::new();
+        merged
+            .write_with(&mut PlainTreeFormatter::new(&mut output))
+            .unwrap();
+        (count_conflicts(&merged), output)
+    };
+    assert!(print_merge(MergeOptions::default()).0 > 0);
+    let ordered = print_merge(MergeOptions {
+        ordered_insertions: true,
+        ..MergeOptions::default()
+    });
+    assert_eq!(ordered.0, 0);
+    assert_eq!(
+        ordered,
+        print_merge(MergeOptions {
+            insert_order_conflicts: Some(InsertOrderResolution::LeftFirst),
+            ..MergeOptions::default()
+        })
+    );
 }
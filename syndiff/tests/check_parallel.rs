@@ -0,0 +1,96 @@
+#![cfg(all(feature = "parallel", feature = "rust"))]
+
+mod common;
+
+use common::{functions_source, syndiff_command, write_source};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Output;
+
+fn run_syndiff(nb_threads: usize, options: &[&str], files: &[&PathBuf]) -> Output {
+    syndiff_command(options, files)
+        .env("RAYON_NUM_THREADS", nb_threads.to_string())
+        .output()
+        .expect("Failed to launch syndiff")
+}
+
+/// Check that running on several threads gives the same output as running on a single one
+fn check_same_as_sequential(options: &[&str], files: &[&PathBuf]) {
+    let sequential = run_syndiff(1, options, files);
+    let parallel = run_syndiff(8, options, files);
+    assert_eq!(parallel.status.code(), sequential.status.code());
+    assert_eq!(
+        String::from_utf8_lossy(&parallel.stdout),
+        String::from_utf8_lossy(&sequential.stdout),
+        "{:?} on {:?}",
+        options,
+        files
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&parallel.stderr),
+        String::from_utf8_lossy(&sequential.stderr)
+    );
+}
+
+#[test]
+fn merges_are_the_same_as_sequential() {
+    for entry in fs::read_dir("tests/prgms").unwrap() {
+        let dir = entry.unwrap().path();
+        let files: Vec<PathBuf> = ["orig.rs", "edit_left.rs", "edit_right.rs"]
+            .iter()
+            .map(|name| dir.join(name))
+            .collect();
+        if !files.iter().all(|file| file.exists()) {
+            continue;
+        }
+        let files: Vec<_> = files.iter().collect();
+        check_same_as_sequential(&[], &files);
+        check_same_as_sequential(&["--merge-files"], &files);
+    }
+}
+
+#[test]
+fn large_diffs_are_the_same_as_sequential() {
+    let count = 60;
+    // Every body changes, so the alignment of the items zips many changed pairs
+    let body = |shift: usize| move |i: usize| format!("let y = {};\n    y * {}", i + shift, i % 7);
+    let orig = write_source("parallel_orig", &functions_source(0..count, body(0)));
+    let shifted = write_source("parallel_shifted", &functions_source(0..count, body(1)));
+    let reversed = write_source(
+        "parallel_reversed",
+        &functions_source((0..count).rev(), body(0)),
+    );
+
+    check_same_as_sequential(&[], &[&orig, &shifted]);
+    check_same_as_sequential(&["--verify-hashes"], &[&orig, &shifted]);
+    check_same_as_sequential(&["--max-alignment-steps", "1000"], &[&orig, &shifted]);
+    check_same_as_sequential(&["--merge-files"], &[&orig, &shifted, &reversed]);
+    check_same_as_sequential(
+        &["--large-file", "--merge-files"],
+        &[&orig, &shifted, &reversed],
+    );
+}
+
+#[test]
+fn diagnostics_are_the_same_as_sequential() {
+    let count = 40;
+    // Bodies have different lengths and most of them change, so that many distinct sequences
+    // exceed the alignment budget
+    let body = |shift: usize| {
+        move |i: usize| {
+            let nb_statements = 2 + (i + shift * (i % 4).min(1)) % 6;
+            let statements: Vec<_> = (0..nb_statements)
+                .map(|j| format!("let y{} = {};", j, i + j + shift * (i % 4).min(1)))
+                .collect();
+            format!("{}\n    y0", statements.join("\n    "))
+        }
+    };
+    let orig = write_source("diagnostics_orig", &functions_source(0..count, body(0)));
+    let edit = write_source("diagnostics_edit", &functions_source(0..count, body(1)));
+
+    let options = ["--max-alignment-cells", "20"];
+    let sequential = run_syndiff(1, &options, &[&orig, &edit]);
+    assert!(String::from_utf8_lossy(&sequential.stderr).lines().count() > 2);
+    check_same_as_sequential(&options, &[&orig, &edit]);
+    check_same_as_sequential(&["--large-file", options[0], options[1]], &[&orig, &edit]);
+}
@@ -0,0 +1,31 @@
+#![cfg(feature = "rust")]
+
+use goldenfile::Mint;
+use std::process::Command;
+
+fn check_invalid_merge(test_name: &str, options: &[&str]) {
+    let mut mint = Mint::new(format!("tests/prgms/{}", test_name));
+    let merged_file = mint.new_goldenfile("merged.rs").unwrap();
+    let merge_out = Command::new(env!("CARGO_BIN_EXE_syndiff"))
+        .arg("--merge-files")
+        .args(options)
+        .arg(format!("tests/prgms/{}/orig.rs", test_name))
+        .arg(format!("tests/prgms/{}/edit_left.rs", test_name))
+        .arg(format!("tests/prgms/{}/edit_right.rs", test_name))
+        .stdout(merged_file)
+        .output()
+        .expect("Failed to launch syndiff");
+    assert_eq!(merge_out.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&merge_out.stderr);
+    assert!(stderr.contains("Invalid merged file"));
+}
+
+#[test]
+fn glued_tokens_are_conflicts() {
+    check_invalid_merge("glued_tokens", &[]);
+}
+
+#[test]
+fn glued_refined_tokens_are_conflicts() {
+    check_invalid_merge("glued_refined", &["--refine-conflicts", "tokens"]);
+}
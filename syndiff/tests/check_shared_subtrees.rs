@@ -0,0 +1,108 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use syndiff::{
+    compute_diff, AlignmentContext, DiffOptions, MinimalAlignment, NodeKind, PlainTreeFormatter,
+    SeqNodeAlignment, Subtree, SubtreeAlignment, SynNode, Token, Tree, TreeFormattable, Weight,
+    WeightedNode,
+};
+
+/// Count the zipped children sequences where the identical subtrees of both sides share the
+/// same data
+struct SharingAlignment(Arc<AtomicUsize>);
+
+impl SubtreeAlignment for SharingAlignment {
+    fn align(
+        &self,
+        del_seq: &[Subtree<WeightedNode>],
+        ins_seq: &[Subtree<WeightedNode>],
+        alignment: &mut Vec<SeqNodeAlignment>,
+        context: &AlignmentContext,
+    ) -> Weight {
+        let shared = del_seq.iter().any(|del| {
+            ins_seq
+                .iter()
+                .any(|ins| del.node == ins.node && std::ptr::eq(&*del.node, &*ins.node))
+        });
+        if shared {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+        MinimalAlignment::default().align(del_seq, ins_seq, alignment, context)
+    }
+}
+
+const ROOT: NodeKind = 1;
+const ITEM: NodeKind = 2;
+
+fn node(kind: NodeKind, children: Vec<SynNode>) -> SynNode {
+    SynNode(Tree::Node(
+        kind,
+        children
+            .into_iter()
+            .map(|node| Subtree { field: None, node })
+            .collect::<Vec<_>>()
+            .into(),
+    ))
+}
+
+/// Item made of the given tokens, that are taken from `source`
+fn item<'t>(source: &'t [u8], tokens: &[&str]) -> SynNode<'t> {
+    let leaves = tokens
+        .iter()
+        .map(|tok| {
+            let start = source
+                .windows(tok.len())
+                .position(|window| window == tok.as_bytes())
+                .unwrap();
+            SynNode(Tree::Leaf(Token::new(
+                &source[start..start + tok.len()],
+                false,
+            )))
+        })
+        .collect();
+    node(ITEM, leaves)
+}
+
+fn diff_with_options(options: DiffOptions) -> Vec<u8> {
+    let orig_source = b"fn a ( ) ; fn b ( ) ;";
+    let edit_source = b"fn a ( ) ; fn c ( ) ; fn b ( ) ;";
+    let orig = node(
+        ROOT,
+        vec![
+            item(orig_source, &["fn", "a", "(", ")", ";"]),
+            item(orig_source, &["fn", "b", "(", ")", ";"]),
+        ],
+    );
+    let edit = node(
+        ROOT,
+        vec![
+            item(edit_source, &["fn", "a", "(", ")", ";"]),
+            item(edit_source, &["fn", "c", "(", ")", ";"]),
+            item(edit_source, &["fn", "b", "(", ")", ";"]),
+        ],
+    );
+
+    let (diff, _) = compute_diff(&orig, &edit, &options).unwrap();
+    let mut output = Vec::new();
+    diff.write_with(&mut PlainTreeFormatter::new(&mut output))
+        .unwrap();
+    output
+}
+
+#[test]
+fn shared_subtrees() {
+    let nb_shared = Arc::new(AtomicUsize::new(0));
+    let shared_diff = diff_with_options(DiffOptions {
+        alignment: Box::new(SharingAlignment(nb_shared.clone())),
+        ..DiffOptions::default()
+    });
+    assert!(nb_shared.load(Ordering::Relaxed) > 0);
+
+    nb_shared.store(0, Ordering::Relaxed);
+    let unshared_diff = diff_with_options(DiffOptions {
+        alignment: Box::new(SharingAlignment(nb_shared.clone())),
+        share_subtrees: false,
+        ..DiffOptions::default()
+    });
+    assert_eq!(nb_shared.load(Ordering::Relaxed), 0);
+    assert_eq!(unshared_diff, shared_diff);
+}
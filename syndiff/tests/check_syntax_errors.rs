@@ -0,0 +1,50 @@
+#![cfg(feature = "rust")]
+
+use goldenfile::Mint;
+use std::process::{Command, Output, Stdio};
+
+fn run_merge(test_name: &str, options: &[&str], stdout: Stdio) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_syndiff"))
+        .args(options)
+        .arg(format!("tests/prgms/{}/orig.rs", test_name))
+        .arg(format!("tests/prgms/{}/edit_left.rs", test_name))
+        .arg(format!("tests/prgms/{}/edit_right.rs", test_name))
+        .stdout(stdout)
+        .output()
+        .expect("Failed to launch syndiff")
+}
+
+#[test]
+fn syntax_errors_are_reported() {
+    let merge_out = run_merge("syntax_error", &["--merge-files"], Stdio::null());
+    assert_eq!(merge_out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&merge_out.stderr);
+    for filename in ["orig.rs", "edit_left.rs", "edit_right.rs"] {
+        assert!(stderr.contains(&format!("{}:6:19: syntax error", filename)));
+    }
+}
+
+#[test]
+fn strict_refuses_syntax_errors() {
+    let merge_out = run_merge(
+        "syntax_error",
+        &["--merge-files", "--strict"],
+        Stdio::piped(),
+    );
+    assert_ne!(merge_out.status.code(), Some(0));
+    assert!(merge_out.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&merge_out.stderr);
+    assert!(stderr.contains("orig.rs:6:19: syntax error"));
+}
+
+#[test]
+fn opaque_syntax_errors() {
+    let mut mint = Mint::new("tests/prgms/syntax_error");
+    let merged_file = mint.new_goldenfile("merged_opaque.rs").unwrap();
+    let merge_out = run_merge(
+        "syntax_error",
+        &["--merge-files", "--opaque-syntax-errors"],
+        Stdio::from(merged_file.try_clone().unwrap()),
+    );
+    assert_eq!(merge_out.status.code(), Some(0));
+}
@@ -0,0 +1,37 @@
+//! Helpers shared by the tests generating their inputs
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Source of functions with the given indices in order, each returning `body(index)`
+pub fn functions_source(
+    indices: impl IntoIterator<Item = usize>,
+    body: impl Fn(usize) -> String,
+) -> String {
+    let mut source = String::new();
+    for i in indices {
+        source.push_str(&format!("fn f{}() -> usize {{\n    {}\n}}\n\n", i, body(i)));
+    }
+    source
+}
+
+/// Write a generated input in the temporary directory of the tests
+pub fn write_source(name: &str, source: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.rs", name));
+    fs::write(&path, source).expect("Failed to write generated input");
+    path
+}
+
+pub fn syndiff_command(options: &[&str], files: &[&PathBuf]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_syndiff"));
+    command.args(options).args(files);
+    command
+}
+
+pub fn run_syndiff(options: &[&str], files: &[&PathBuf]) -> Output {
+    syndiff_command(options, files)
+        .output()
+        .expect("Failed to launch syndiff")
+}
@@ -0,0 +1,12 @@
+fn ·· {
+    if · {
+        ·;
+        CHANGED![«x» -> «y»]
+    } else {INSERTED![
+        $0;
+        $0;]
+        ·DELETED![;
+        $0;
+        $0];
+    }
+}
@@ -0,0 +1,15 @@
+use std::io::Write;
+use std::fs::File;
+use std::path::Path;
+use std::collections::HashMap;
+
+struct Config {
+    name: String,
+    size: usize,
+<<<<<<< tests/prgms/commutative_uses/edit_left.rs
+    path: Path,
+||||||| tests/prgms/commutative_uses/orig.rs
+=======
+    options: HashMap<String, String>,
+>>>>>>> tests/prgms/commutative_uses/edit_right.rs
+}
@@ -0,0 +1,9 @@
+use std::io::Write;
+use std::fs::File;
+use std::path::Path;
+
+struct Config {
+    name: String,
+    size: usize,
+    path: Path,
+}
@@ -0,0 +1,9 @@
+use std::fs::File;
+use std::io::Write;
+use std::collections::HashMap;
+
+struct Config {
+    name: String,
+    size: usize,
+    options: HashMap<String, String>,
+}
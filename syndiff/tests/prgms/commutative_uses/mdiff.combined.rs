@@ -0,0 +1,11 @@
+·
+·INSERTED![
+use std::path::Path;
+use std::collections::HashMap;]
+
+struct · {
+    ·,
+    ·INSERTED![,
+    path: Path,
+    options: HashMap<String, String>],
+}
@@ -0,0 +1,11 @@
+INSERTED![$0
+]·
+CHANGED![«$0» -> «use std::path::Path;»]INSERTED![
+use std::collections::HashMap;]
+
+struct · {
+    ·,
+    ·INSERT_ORDER_CONFLICT![«,
+    path: Path», «,
+    options: HashMap<String, String>»],
+}
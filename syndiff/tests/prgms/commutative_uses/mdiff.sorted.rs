@@ -0,0 +1,11 @@
+·
+·INSERTED![
+use std::collections::HashMap;
+use std::path::Path;]
+
+struct · {
+    ·,
+    ·INSERTED![,
+    options: HashMap<String, String>,
+    path: Path],
+}
@@ -0,0 +1,11 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::collections::HashMap;
+
+struct Config {
+    name: String,
+    size: usize,
+    path: Path,
+    options: HashMap<String, String>,
+}
@@ -0,0 +1,11 @@
+use std::fs::File;
+use std::io::Write;
+use std::collections::HashMap;
+use std::path::Path;
+
+struct Config {
+    name: String,
+    size: usize,
+    options: HashMap<String, String>,
+    path: Path,
+}
@@ -0,0 +1,7 @@
+use std::fs::File;
+use std::io::Write;
+
+struct Config {
+    name: String,
+    size: usize,
+}
@@ -0,0 +1,10 @@
+use std::io::Write;
+use std::fs::File;
+use std::path::Path;
+use std::collections::HashMap;
+
+struct Config {
+    name: String,
+    size: usize,
+    path: Path,
+}
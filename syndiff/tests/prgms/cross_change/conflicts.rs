@@ -0,0 +1,27 @@
+<<<<<<< tests/prgms/cross_change/edit_left.rs
+fn i0() -> i32 {
+    0
+||||||| tests/prgms/cross_change/orig.rs
+fn zero() -> i32 {
+    0
+=======
+fn two() -> i32 {
+    2
+>>>>>>> tests/prgms/cross_change/edit_right.rs
+}
+
+fn one() -> i32 {
+    1
+}
+
+<<<<<<< tests/prgms/cross_change/edit_left.rs
+fn zero() -> i32 {
+    0
+||||||| tests/prgms/cross_change/orig.rs
+fn two() -> i32 {
+    2
+=======
+fn i2() -> i32 {
+    2
+>>>>>>> tests/prgms/cross_change/edit_right.rs
+}
@@ -0,0 +1,31 @@
+fn i0() -> i32 {
+    0
+}
+
+fn one() -> i32 {
+    1
+}
+
+fn two() -> i32 {
+    2
+}
+<<<<<<< tests/prgms/cross_change/edit_left.rs
+||||||| tests/prgms/cross_change/orig.rs
+
+fn one() -> i32 {
+    1
+}
+
+fn two() -> i32 {
+    2
+}
+=======
+
+fn one() -> i32 {
+    1
+}
+
+fn i2() -> i32 {
+    2
+}
+>>>>>>> tests/prgms/cross_change/edit_right.rs
@@ -0,0 +1,27 @@
+<<<<<<< left
+fn i0() -> i32 {
+    0
+||||||| base
+fn zero() -> i32 {
+    0
+=======
+fn two() -> i32 {
+    2
+>>>>>>> right
+}
+
+fn one() -> i32 {
+    1
+}
+
+<<<<<<< left
+fn zero() -> i32 {
+    0
+||||||| base
+fn two() -> i32 {
+    2
+=======
+fn i2() -> i32 {
+    2
+>>>>>>> right
+}
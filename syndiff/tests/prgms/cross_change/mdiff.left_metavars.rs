@@ -0,0 +1,13 @@
+CHANGED![«fn zero() -> i32 {
+    0
+}» -> «fn i0() -> i32 {
+    0
+}»]
+
+·
+
+CHANGED![«fn two() -> i32 {
+    2
+}» -> «fn zero() -> i32 {
+    0
+}»]
@@ -0,0 +1,19 @@
+INSERTED![fn i0$0 -> $1 {
+    0
+}
+
+$2
+
+]CHANGED![«fn zero() -> i32 {
+    0
+}» -> «fn two$0 -> $1 {
+    2
+}»]DELETED![
+
+$2
+
+]DELETE_CONFLICT![«fn two$0 -> $1 {
+    2
+}» -/> «fn i2() -> i32 {
+    2
+}»]
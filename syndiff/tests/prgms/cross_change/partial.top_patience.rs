@@ -0,0 +1,11 @@
+fn i0() -> i32 {
+    0
+}
+
+fn one() -> i32 {
+    1
+}
+
+fn two() -> i32 {
+    2
+}
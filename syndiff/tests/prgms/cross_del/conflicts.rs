@@ -0,0 +1,27 @@
+<<<<<<< tests/prgms/cross_del/edit_left.rs
+||||||| tests/prgms/cross_del/orig.rs
+fn zero() -> i32 {
+    0
+}
+
+=======
+fn two() -> i32 {
+    2
+}
+
+>>>>>>> tests/prgms/cross_del/edit_right.rs
+fn one() -> i32 {
+    1
+}
+<<<<<<< tests/prgms/cross_del/edit_left.rs
+
+fn zero() -> i32 {
+    0
+}
+||||||| tests/prgms/cross_del/orig.rs
+
+fn two() -> i32 {
+    2
+}
+=======
+>>>>>>> tests/prgms/cross_del/edit_right.rs
@@ -0,0 +1,7 @@
+fn one() -> i32 {
+    1
+}
+
+fn zero() -> i32 {
+    0
+}
@@ -0,0 +1,17 @@
+
+
+fn two() -> i32 {
+    2
+}
+<<<<<<< tests/prgms/cross_del_and_ins/edit_left.rs
+
+fn two() -> i32 {
+    2
+}
+||||||| tests/prgms/cross_del_and_ins/orig.rs
+=======
+
+fn one() -> i32 {
+    1
+}
+>>>>>>> tests/prgms/cross_del_and_ins/edit_right.rs
@@ -0,0 +1,35 @@
+<<<<<<< tests/prgms/cross_del_and_ins/edit_left.rs
+fn one() -> i32 {
+    1
+||||||| tests/prgms/cross_del_and_ins/orig.rs
+fn zero() -> i32 {
+    0
+=======
+fn two() -> i32 {
+    2
+>>>>>>> tests/prgms/cross_del_and_ins/edit_right.rs
+}
+
+<<<<<<< tests/prgms/cross_del_and_ins/edit_left.rs
+fn zero() -> i32 {
+    0
+||||||| tests/prgms/cross_del_and_ins/orig.rs
+fn one() -> i32 {
+    1
+=======
+fn two() -> i32 {
+    2
+>>>>>>> tests/prgms/cross_del_and_ins/edit_right.rs
+}
+
+<<<<<<< tests/prgms/cross_del_and_ins/edit_left.rs
+fn zero() -> i32 {
+    0
+||||||| tests/prgms/cross_del_and_ins/orig.rs
+fn two() -> i32 {
+    2
+=======
+fn one() -> i32 {
+    1
+>>>>>>> tests/prgms/cross_del_and_ins/edit_right.rs
+}
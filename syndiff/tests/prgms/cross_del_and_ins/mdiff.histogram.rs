@@ -0,0 +1,17 @@
+DELETED![fn zero$0 -> $1 {
+    0
+}
+
+$2]
+
+CHANGED![«fn two() -> i32 {
+    2
+}» -> «fn two$0 -> $1 {
+    2
+}»]INSERT_ORDER_CONFLICT![«
+
+fn two$0 -> $1 {
+    2
+}», «
+
+$2»]
@@ -0,0 +1,13 @@
+CHANGED![«fn zero() -> i32 {
+    0
+}» -> «fn two() -> i32 {
+    2
+}»]
+
+CHANGED![«$0» -> «fn two() -> i32 {
+    2
+}»]
+
+CHANGED![«fn two() -> i32 {
+    2
+}» -> «$0»]
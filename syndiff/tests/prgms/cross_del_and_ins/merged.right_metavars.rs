@@ -0,0 +1,11 @@
+fn two() -> i32 {
+    2
+}
+
+fn two() -> i32 {
+    2
+}
+
+fn one() -> i32 {
+    1
+}
@@ -0,0 +1,9 @@
+
+
+fn two() -> i32 {
+    2
+}
+
+fn two() -> i32 {
+    2
+}
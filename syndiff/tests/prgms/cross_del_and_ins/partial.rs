@@ -0,0 +1,11 @@
+fn one() -> i32 {
+    1
+}
+
+fn zero() -> i32 {
+    0
+}
+
+fn zero() -> i32 {
+    0
+}
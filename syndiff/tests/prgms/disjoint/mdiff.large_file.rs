@@ -0,0 +1,17 @@
+INSERTED![use std::fs::read;
+use std::process::exit;
+
+]·
+
+fn ·· {
+    ·
+    ·!("INSERTED![file content:\n]{}", ·::··);
+    ·;
+}
+
+fn ·· -> · {
+    CHANGED![«std::fs::$0» -> «$0»]·.·(· {
+        ·;
+        CHANGED![«std::process::$1» -> «$1»]·
+    })
+}
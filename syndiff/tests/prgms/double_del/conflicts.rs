@@ -0,0 +1,13 @@
+<<<<<<< tests/prgms/double_del/edit_left.rs
+fn f() -> i32 {
+    let mut a = 0;
+    a
+}
+||||||| tests/prgms/double_del/orig.rs
+fn f() -> i32 {
+    let mut a = 0;
+    a += 1;
+    a
+}
+=======
+>>>>>>> tests/prgms/double_del/edit_right.rs
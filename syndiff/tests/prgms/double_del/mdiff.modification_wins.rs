@@ -0,0 +1,9 @@
+CHANGED![«fn f() -> i32 {
+    let mut a = 0;
+    a += 1;
+    a
+}» -> «fn f() -> i32 {
+    let mut a = 0;
+    a
+}»]DELETED![
+]
\ No newline at end of file
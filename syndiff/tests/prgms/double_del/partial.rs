@@ -0,0 +1,4 @@
+fn f() -> i32 {
+    let mut a = 0;
+    a
+}
@@ -0,0 +1,20 @@
+fn f() {
+<<<<<<< tests/prgms/factorize/edit_left.rs
+||||||| tests/prgms/factorize/orig.rs
+    let a = 2;
+    let b = 40;
+    let x = a + b;
+=======
+    let a = 3;
+    let b = 42;
+    let x = a + b;
+>>>>>>> tests/prgms/factorize/edit_right.rs
+    let y = answer() * 5;
+    println!("{}", y)
+}
+
+fn answer() -> i32 {
+    let a = 2;
+    let b = 40;
+    a + b
+}
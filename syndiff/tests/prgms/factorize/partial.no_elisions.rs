@@ -0,0 +1,10 @@
+fn f() {
+    let y = answer() * 5;
+    println!("{}", y)
+}
+
+fn answer() -> i32 {
+    let a = 2;
+    let b = 40;
+    a + b
+}
@@ -0,0 +1,3 @@
+fn f(x: i32, y: i32) -> i32 {
+    x . -y
+}
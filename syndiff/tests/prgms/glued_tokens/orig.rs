@@ -0,0 +1,3 @@
+fn f() {
+    return (x);
+}
@@ -0,0 +1,8 @@
+fn config() -> Config {
+    Config {
+        size: 10,
+        name: String::from("syndiff"),
+        depth: 2,
+        verbose: true,
+    }
+}
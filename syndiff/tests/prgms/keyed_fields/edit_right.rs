@@ -0,0 +1,8 @@
+fn config() -> Config {
+    Config {
+        name: String::from("syndiff"),
+        size: 20,
+        depth: 2,
+        path: None,
+    }
+}
@@ -0,0 +1,9 @@
+fn ·· -> · {
+    · {
+        ·,
+        ·: CHANGED![«10» -> «20»],
+        ·INSERTED![,
+        verbose: true,
+        path: None],
+    }
+}
@@ -0,0 +1,9 @@
+fn ·· -> · {
+    · {INSERTED![
+        $0: 20,]
+        ·,
+        CHANGED![«$0: 10» -> «$1»],
+        CHANGED![«$1» -> «verbose: true»]INSERTED![,
+        path: None],
+    }
+}
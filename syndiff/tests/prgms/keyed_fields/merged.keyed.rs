@@ -0,0 +1,9 @@
+fn config() -> Config {
+    Config {
+        name: String::from("syndiff"),
+        size: 20,
+        depth: 2,
+        verbose: true,
+        path: None,
+    }
+}
@@ -0,0 +1,9 @@
+fn config() -> Config {
+    Config {
+        size: 20,
+        name: String::from("syndiff"),
+        depth: 2,
+        verbose: true,
+        path: None,
+    }
+}
@@ -0,0 +1,7 @@
+fn config() -> Config {
+    Config {
+        name: String::from("syndiff"),
+        size: 10,
+        depth: 2,
+    }
+}
@@ -0,0 +1,17 @@
+fn parse_footer(input: &str) -> Footer {
+    let mut lines = input.lines();
+    let title = lines.next_back().unwrap();
+    Footer { title }
+}
+
+fn parse_header(input: &str) -> Result<Header, Error> {
+    let title = input.trim().strip_prefix('#').ok_or(Error::NoTitle)?;
+    Ok(Header { title })
+}
+
+fn render_header(header: &Header) -> String {
+    let mut output = String::new();
+    output.push_str("# ");
+    output.push_str(&header.title);
+    output
+}
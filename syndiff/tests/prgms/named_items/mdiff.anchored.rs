@@ -0,0 +1,13 @@
+INSERTED![fn parse_footer(input: &str) -> Footer {
+    $0
+    let title = $1.next_back$2.$3$2;
+    Footer { title }
+}
+
+]fn ·· -> CHANGED![«$4» -> «Result<$4, Error>»] {DELETED![
+    $0]
+    let · = CHANGED![«$1.next$2.$3$2» -> «input.trim$2.strip_prefix('#').ok_or(Error::NoTitle)?»];
+    CHANGED![«$5 { $6 }» -> «Ok($5 { title: $6.to_string() })»]
+}
+
+·
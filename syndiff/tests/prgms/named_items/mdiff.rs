@@ -0,0 +1,12 @@
+fn CHANGED![«$0» -> «parse_footer»]· -> CHANGED![«$1» -> «Footer»] {
+    ·
+    let · = ·.CHANGED![«next» -> «next_back»]·.··;
+    CHANGED![«$2 { $3 }» -> «Footer { title }»]
+}INSERTED![
+
+fn $0(input: &str) -> Result<$1, Error> {
+    let title = input.trim().strip_prefix('#').ok_or(Error::NoTitle)?;
+    Ok($2 { title: $3.to_string() })
+}]
+
+·
@@ -0,0 +1,12 @@
+fn parse_header(input: &str) -> Header {
+    let mut lines = input.lines();
+    let title = lines.next().unwrap();
+    Header { title }
+}
+
+fn render_header(header: &Header) -> String {
+    let mut output = String::new();
+    output.push_str("# ");
+    output.push_str(&header.title);
+    output
+}
@@ -0,0 +1,13 @@
+INSERTED![$0
+
+]fn ·· -> · {
+    CHANGED![«$1 - $2» -> «$1.saturating_sub($2)»]
+}
+
+fn ·· -> · {INSERTED![
+    let (top, bottom) = $3.vertical_span();
+    assert!(top <= bottom);]
+    CHANGED![«$3.bottom» -> «bottom»] - CHANGED![«$3.top» -> «top»]
+}DELETED![
+
+$0]
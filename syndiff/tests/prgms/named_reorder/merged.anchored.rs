@@ -0,0 +1,13 @@
+fn area(rect: &Rect) -> u32 {
+    width(rect) * height(rect)
+}
+
+fn width(rect: &Rect) -> u32 {
+    rect.right.saturating_sub(rect.left)
+}
+
+fn height(rect: &Rect) -> u32 {
+    let (top, bottom) = rect.vertical_span();
+    assert!(top <= bottom);
+    bottom - top
+}
@@ -0,0 +1,11 @@
+fn width(rect: &Rect) -> u32 {
+    rect.right - rect.left
+}
+
+fn height(rect: &Rect) -> u32 {
+    rect.bottom - rect.top
+}
+
+fn area(rect: &Rect) -> u32 {
+    width(rect) * height(rect)
+}
@@ -0,0 +1,3 @@
+fn ·· -> · {
+    ((((((((((((((((((((CHANGED![«1» -> «2»]))))))))))))))))))))
+}
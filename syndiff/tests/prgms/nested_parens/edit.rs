@@ -0,0 +1,3 @@
+fn f() -> i32 {
+    ((((((((((((((((((((2))))))))))))))))))))
+}
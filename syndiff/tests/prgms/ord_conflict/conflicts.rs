@@ -0,0 +1,9 @@
+fn f() -> i32 {
+<<<<<<< tests/prgms/ord_conflict/edit_left.rs
+    println!("Returning the answer");
+||||||| tests/prgms/ord_conflict/orig.rs
+=======
+    println!("The life, the universe, and everything");
+>>>>>>> tests/prgms/ord_conflict/edit_right.rs
+    42
+}
@@ -0,0 +1,5 @@
+fn ·· -> · {INSERTED![
+    println!("The life, the universe, and everything");]INSERTED![
+    println!("Returning the answer");]
+    ·
+}
@@ -0,0 +1,5 @@
+fn f() -> i32 {
+    println!("The life, the universe, and everything");
+    println!("Returning the answer");
+    42
+}
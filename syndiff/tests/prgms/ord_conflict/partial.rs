@@ -0,0 +1,4 @@
+fn f() -> i32 {
+    println!("Returning the answer");
+    42
+}
@@ -0,0 +1,11 @@
+fn first() {
+    let x = 1;
+}
+
+fn broken() {
+    let y = 2 + 3 @ 5;
+}
+
+fn last() {
+    let z = 3;
+}
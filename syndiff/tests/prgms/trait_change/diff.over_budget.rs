@@ -0,0 +1,11 @@
+trait · {
+    ·INSERTED![
+    fn purr();]
+}
+
+·
+
+impl · for · {
+    ·INSERTED![
+    fn purr() {}]
+}
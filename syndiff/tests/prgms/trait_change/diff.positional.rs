@@ -0,0 +1,13 @@
+trait · {
+    ·CHANGED![«
+» -> «
+    »]CHANGED![«}» -> «fn purr();»]INSERTED![
+}]
+
+·
+
+impl · for · {
+    ·CHANGED![«
+» -> «
+    »]CHANGED![«}» -> «fn purr() {}»]INSERTED![
+}]
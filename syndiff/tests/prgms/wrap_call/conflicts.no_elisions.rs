@@ -0,0 +1,11 @@
+fn read_config() -> Result<Config, Error> {
+    let path = config_path();
+<<<<<<< tests/prgms/wrap_call/edit_left.rs
+    let config = parse_config(&path, Format::Toml)?;
+||||||| tests/prgms/wrap_call/orig.rs
+    let config = parse_config(&path, Format::Toml);
+=======
+    let config = parse_config(&path, Format::Yaml);
+>>>>>>> tests/prgms/wrap_call/edit_right.rs
+    Ok(config)
+}
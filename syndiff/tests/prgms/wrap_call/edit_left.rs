@@ -0,0 +1,5 @@
+fn read_config() -> Result<Config, Error> {
+    let path = config_path();
+    let config = parse_config(&path, Format::Toml)?;
+    Ok(config)
+}
@@ -0,0 +1,5 @@
+fn ·· -> · {
+    ·
+    let · = CHANGED![«parse_config(&path, Format::Toml)» -> «CONFLICT![«parse_config(&path, Format::Toml)?», «parse_config(&path, Format::Yaml)»]»];
+    ·
+}
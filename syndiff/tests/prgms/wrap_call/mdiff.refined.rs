@@ -0,0 +1,5 @@
+fn read_config() -> Result<Config, Error> {
+    let path = config_path();
+    let config = CHANGED![«parse_config(&path, Format::Toml)» -> «parse_config(&path, Format::Yaml)?»];
+    Ok(config)
+}
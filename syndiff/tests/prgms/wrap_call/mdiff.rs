@@ -0,0 +1,5 @@
+fn ·· -> · {
+    ·
+    let · = CHANGED![«$0($1, $2::Toml)» -> «$0($1, $2::Yaml)?»];
+    ·
+}